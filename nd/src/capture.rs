@@ -0,0 +1,148 @@
+use std::io::{self, Read, Write};
+
+/// Most stdout kept for the notification body; the rest is only passed through
+pub const CAPTURE_LIMIT: usize = 1024;
+
+/// The start and end of a command's output
+#[derive(Debug, Default)]
+pub struct CapturedOutput {
+    bytes: Vec<u8>,
+    /// More output followed what was kept
+    truncated: bool,
+    /// The last bytes of the output, as many as the start
+    tail: Vec<u8>,
+    /// More output preceded the tail
+    tail_truncated: bool,
+}
+
+impl CapturedOutput {
+    /// The captured text, lossily decoded, trimmed, and marked with "…" if
+    /// it was cut short
+    pub fn text(&self) -> String {
+        let text = String::from_utf8_lossy(&self.bytes);
+        let text = text.trim_end();
+        if self.truncated {
+            format!("{}…", text)
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// The end of the captured text, like `text`, marked with "…" if
+    /// output came before it
+    pub fn tail_text(&self) -> String {
+        let text = String::from_utf8_lossy(&self.tail);
+        let text = text.trim();
+        if self.tail_truncated {
+            format!("…{}", text)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+/// Copy `reader` to `writer` until EOF, keeping the first and the last
+/// `limit` bytes; `observe` sees everything read, as it is read
+pub fn tee(
+    mut reader: impl Read,
+    mut writer: impl Write,
+    limit: usize,
+    mut observe: impl FnMut(&[u8]),
+) -> io::Result<CapturedOutput> {
+    let mut captured = CapturedOutput::default();
+    let mut buf = [0u8; 8192];
+    let mut writer_ok = true;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        // Keep draining the pipe even if our stdout went away, so the
+        // command doesn't die of SIGPIPE because of us
+        if writer_ok {
+            writer_ok = writer
+                .write_all(&buf[..n])
+                .and_then(|_| writer.flush())
+                .is_ok();
+        }
+
+        observe(&buf[..n]);
+
+        let room = limit - captured.bytes.len();
+        captured.bytes.extend_from_slice(&buf[..n.min(room)]);
+        if n > room {
+            captured.truncated = true;
+        }
+
+        captured.tail.extend_from_slice(&buf[..n]);
+        if captured.tail.len() > limit {
+            let excess = captured.tail.len() - limit;
+            captured.tail.drain(..excess);
+            captured.tail_truncated = true;
+        }
+    }
+    Ok(captured)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::{Command, Stdio};
+
+    use super::*;
+
+    fn tee_bytes(input: &[u8], limit: usize) -> (CapturedOutput, Vec<u8>) {
+        let mut copy = Vec::new();
+        let captured = tee(input, &mut copy, limit, |_| {}).unwrap();
+        (captured, copy)
+    }
+
+    #[test]
+    fn stderr_of_a_successful_command_is_captured() {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg("echo 'warning: unused variable' >&2; exit 0")
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut copy = Vec::new();
+        let captured = tee(
+            child.stderr.take().unwrap(),
+            &mut copy,
+            CAPTURE_LIMIT,
+            |_| {},
+        )
+        .unwrap();
+        assert!(child.wait().unwrap().success());
+        assert_eq!(copy, b"warning: unused variable\n");
+        assert_eq!(captured.text(), "warning: unused variable");
+    }
+
+    #[test]
+    fn whitespace_only_output_is_empty() {
+        let (captured, copy) = tee_bytes(b"\n  \n", CAPTURE_LIMIT);
+        assert_eq!(copy, b"\n  \n");
+        assert_eq!(captured.text(), "");
+        assert_eq!(captured.tail_text(), "");
+    }
+
+    #[test]
+    fn long_output_keeps_its_start_and_end() {
+        let (captured, copy) = tee_bytes(b"first line\nmiddle\nlast line\n", 10);
+        assert_eq!(copy, b"first line\nmiddle\nlast line\n");
+        assert_eq!(captured.text(), "first line…");
+        assert_eq!(captured.tail_text(), "…last line");
+    }
+
+    #[test]
+    fn observe_sees_everything() {
+        let mut seen = Vec::new();
+        tee(&b"0123456789"[..], io::sink(), 4, |bytes| {
+            seen.extend_from_slice(bytes)
+        })
+        .unwrap();
+        assert_eq!(seen, b"0123456789");
+    }
+}
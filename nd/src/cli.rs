@@ -1,12 +1,129 @@
-use clap::{Parser, Subcommand};
+use clap::builder::{PossibleValuesParser, TypedValueParser};
+use clap::{Args, Parser, Subcommand};
+use notify_done_common::config::Urgency;
 
 #[derive(Parser)]
 #[command(name = "nd")]
 #[command(about = "notify-done - Get notified when long-running commands complete")]
 #[command(version)]
+#[command(arg_required_else_help = true)]
 pub struct Cli {
+    /// Minimum duration in seconds before notifying (default: $ND_THRESHOLD,
+    /// then the config's threshold_seconds, then 10)
+    #[arg(short = 't', long, global = true)]
+    pub threshold: Option<u64>,
+
+    /// Task name; repeated runs with the same name replace the previous notification
+    #[arg(short = 'n', long, global = true)]
+    pub name: Option<String>,
+
+    /// Report to a summary for all runs sharing this group instead of
+    /// notifying each one; it is sent once none have been running for
+    /// group_idle_seconds
+    #[arg(short = 'g', long, global = true, value_name = "GROUP")]
+    pub group: Option<String>,
+
+    /// Print a single JSON object to stdout instead of text (`run` and
+    /// `watch` still pass their command's output through)
+    #[arg(long, global = true)]
+    pub json: bool,
+
+    #[command(flatten)]
+    pub run_options: RunOptions,
+
+    #[command(flatten)]
+    pub overrides: NotifyOverrides,
+
+    /// Command to run, as a shorthand for `nd run -- <command>`
+    #[arg(last = true, value_name = "COMMAND")]
+    pub exec: Vec<String>,
+
     #[command(subcommand)]
-    pub command: Commands,
+    pub command: Option<Commands>,
+}
+
+/// Extra measurements `run` adds to its notification
+#[derive(Args, Clone, Default)]
+pub struct RunOptions {
+    /// Show the command's stdout in the notification (the first 1 KiB is
+    /// kept, output is still printed)
+    #[arg(long, global = true)]
+    pub capture_output: bool,
+
+    /// Show the command's peak memory use (RSS) in the notification
+    #[arg(long, global = true)]
+    pub track_rss: bool,
+
+    /// Show the command's user + system CPU time in the notification
+    #[arg(long, global = true)]
+    pub track_cpu: bool,
+
+    /// Create a fifo, exported as $ND_PROGRESS_FIFO, that the command can
+    /// write "progress: 42%" and "status: linking" lines to; they are shown
+    /// in a notification updated while it runs
+    #[arg(long, global = true)]
+    pub progress_fifo: bool,
+
+    /// Show a progress notification from the command's own output: each
+    /// stdout or stderr line (or \r-separated update) matching REGEX sets
+    /// the percentage to its first capture group, e.g. '(\d+)%'. Both
+    /// streams go through a pipe, so the command no longer sees a terminal
+    #[arg(long, global = true, value_name = "REGEX")]
+    pub progress_regex: Option<String>,
+
+    /// Print the notification to stdout after the command, whatever its
+    /// duration, instead of sending it anywhere
+    #[arg(long, global = true)]
+    pub print_only: bool,
+
+    /// Run the command through `$SHELL -c` (default /bin/sh), its arguments
+    /// joined into one script, so pipes, `&&` and aliases work; without it
+    /// the command is executed directly, with no shell parsing
+    #[arg(long, global = true)]
+    pub shell: bool,
+
+    /// Don't notify if the terminal is the focused window when the command
+    /// finishes (X11 terminals that set $WINDOWID; otherwise always notifies)
+    #[arg(long, global = true)]
+    pub skip_if_focused: bool,
+
+    /// Say on stderr why no notification was sent, e.g. "skipped: duration
+    /// 3s < threshold 10s"
+    #[arg(short = 'v', long, global = true)]
+    pub verbose: bool,
+
+    /// Flag a successful command that wrote anything to stderr (e.g.
+    /// compiler warnings) as "completed with warnings", with the failure
+    /// urgency and the start of its stderr in the notification (stderr is
+    /// still printed)
+    #[arg(long, global = true)]
+    pub notify_on_stderr: bool,
+}
+
+/// One-off notification settings for `run` and `watch-pid`, winning over
+/// the config for both success and failure
+#[derive(Args, Clone, Default)]
+pub struct NotifyOverrides {
+    /// Notification urgency for this invocation
+    #[arg(long, global = true, value_parser = urgency_parser())]
+    pub urgency: Option<Urgency>,
+
+    /// Notification icon for this invocation (icon name or image path)
+    #[arg(long, global = true, value_name = "ICON")]
+    pub icon: Option<String>,
+
+    /// Send desktop notifications even in an SSH session, where nd
+    /// otherwise prints them to the terminal (unless backends are configured)
+    #[arg(long, global = true)]
+    pub local_notify: bool,
+}
+
+fn urgency_parser() -> impl TypedValueParser<Value = Urgency> {
+    PossibleValuesParser::new(["low", "normal", "critical"]).map(|s| match s.as_str() {
+        "low" => Urgency::Low,
+        "critical" => Urgency::Critical,
+        _ => Urgency::Normal,
+    })
 }
 
 #[derive(Subcommand)]
@@ -22,8 +139,33 @@ pub enum Commands {
         /// Number of entries to show
         #[arg(short, long, default_value = "20")]
         count: usize,
+
+        /// Only show completions since a relative duration (e.g. 30m, 1h, 2d12h;
+        /// units s/m/h/d/w) or a local date (YYYY-MM-DD or YYYY-MM-DDTHH:MM)
+        #[arg(long)]
+        since: Option<String>,
+
+        /// Show the terminal session each command ran in
+        #[arg(long, conflicts_with = "clear")]
+        show_session: bool,
+
+        /// Delete all recorded history
+        #[arg(long, conflicts_with = "since")]
+        clear: bool,
+
+        /// Don't ask for confirmation when clearing
+        #[arg(short, long, requires = "clear")]
+        yes: bool,
     },
 
+    /// Show the most recently completed command in detail
+    Last,
+
+    /// Show everything recorded about the most recent failed command: exit
+    /// code or signal, duration, directory, host and the end of its stderr
+    /// (captured with --notify-on-stderr)
+    Why,
+
     /// Manage configuration
     Config {
         #[command(subcommand)]
@@ -33,12 +175,20 @@ pub enum Commands {
     /// Send a test notification
     Test,
 
+    /// Check the setup (config, session bus, notification server, daemon)
+    /// and suggest fixes; exits non-zero if notifications can't work
+    Doctor,
+
+    /// Inspect the daemon's decisions
+    Debug {
+        #[command(subcommand)]
+        action: DebugAction,
+    },
+
     /// Run a command and notify when it completes (wrapper mode)
+    ///
+    /// `nd [OPTIONS] -- <command>` is a shorthand for this subcommand.
     Run {
-        /// Minimum duration in seconds before notifying
-        #[arg(short = 't', long, default_value = "10")]
-        threshold: u64,
-
         /// The command to run
         #[arg(trailing_var_arg = true, required = true)]
         command: Vec<String>,
@@ -46,6 +196,43 @@ pub enum Commands {
 
     /// Watch live events from the daemon
     Watch,
+
+    /// Notify when an already-running process exits (works without the daemon)
+    WatchPid {
+        /// Process to wait for
+        pid: i32,
+    },
+
+    /// Notify when a systemd unit finishes, e.g. a transient `systemd-run`
+    /// unit or a service started by a timer; waits for it to start if it
+    /// isn't running yet
+    WatchUnit {
+        /// Unit to wait for (".service" if no suffix is given)
+        unit: String,
+
+        /// Look in the user's service manager instead of the system's
+        #[arg(long)]
+        user: bool,
+    },
+
+    /// Send a group's summary once it has been idle (started by `run`)
+    #[command(hide = true)]
+    GroupFlush {
+        /// Group state generation the last member left behind
+        generation: u64,
+        group: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum DebugAction {
+    /// Show the daemon's recent log lines (needs `recent_log_lines` in the
+    /// daemon config)
+    Log {
+        /// Number of lines to show
+        #[arg(short, long, default_value = "50")]
+        count: usize,
+    },
 }
 
 #[derive(Subcommand)]
@@ -53,9 +240,15 @@ pub enum ConfigAction {
     /// Show current configuration
     Show,
 
+    /// Show every value in effect after layering, and where it comes from
+    Effective,
+
     /// Initialize user configuration file
     Init,
 
+    /// Open the user configuration in $EDITOR and validate it afterwards
+    Edit,
+
     /// Set notification threshold
     Threshold {
         /// Threshold in seconds
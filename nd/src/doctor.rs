@@ -0,0 +1,262 @@
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use notify_done_common::health::{DaemonHealth, HEALTH_INTERVAL_SECS, HEALTH_PATH};
+use notify_rust::Notification;
+use serde::Serialize;
+
+use crate::history::now_epoch_secs;
+use crate::{load_layered_config, show_command, user_config_path};
+
+/// Exposed by kernels built with CONFIG_DEBUG_INFO_BTF
+const VMLINUX_BTF_PATH: &str = "/sys/kernel/btf/vmlinux";
+
+/// Outcome of one `nd doctor` check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    /// Something is degraded, but `nd run` still notifies
+    Warn,
+    /// Notifications won't work until this is fixed
+    Fail,
+}
+
+/// One line of the `nd doctor` report
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+    /// What to do about a warning or failure
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Warn,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            status: CheckStatus::Fail,
+            ..Self::warn(name, detail, hint)
+        }
+    }
+}
+
+/// Run every check, in the order a user would fix them
+pub fn run_checks() -> Vec<Check> {
+    let mut checks = vec![
+        check_config(),
+        check_config_writable(),
+        check_session_bus(),
+        check_test_notification(),
+    ];
+    let daemon = check_daemon();
+    let running = daemon.status == CheckStatus::Pass;
+    checks.push(daemon);
+    if running {
+        checks.push(check_daemon_health());
+    }
+    checks.push(check_btf());
+    checks.push(check_notify_send());
+    checks
+}
+
+fn check_config() -> Check {
+    match load_layered_config() {
+        Ok(_) => Check::pass("config", "system and user config are valid"),
+        Err(e) => Check::fail(
+            "config",
+            format!("{:#}", e),
+            "fix the reported line, or recreate the file with 'nd config init'",
+        ),
+    }
+}
+
+/// `nd config` subcommands and `nd run` history need to write there
+fn check_config_writable() -> Check {
+    let path = match user_config_path() {
+        Ok(path) => path,
+        Err(e) => return Check::warn("config-dir", format!("{:#}", e), "set $HOME"),
+    };
+    // The directory is created on first use, so check what would hold it
+    let mut dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    while !dir.exists() {
+        match dir.parent() {
+            Some(parent) => dir = parent.to_path_buf(),
+            None => break,
+        }
+    }
+    if is_writable(&dir) {
+        Check::pass("config-dir", format!("{} is writable", dir.display()))
+    } else {
+        Check::warn(
+            "config-dir",
+            format!("{} is not writable", dir.display()),
+            format!("fix the ownership of {}", dir.display()),
+        )
+    }
+}
+
+fn check_session_bus() -> Check {
+    let config = load_layered_config().unwrap_or_default();
+    if config
+        .notification
+        .as_ref()
+        .is_some_and(|n| n.has_command())
+    {
+        return Check::pass(
+            "session-bus",
+            "not needed, notifications go through notification.command or ssh_target",
+        );
+    }
+
+    let address = std::env::var("DBUS_SESSION_BUS_ADDRESS").ok();
+    let runtime_bus = dirs::runtime_dir().map(|dir| dir.join("bus"));
+    match (address, runtime_bus) {
+        (Some(address), _) if !address.is_empty() => Check::pass("session-bus", address),
+        (_, Some(bus)) if bus.exists() => Check::pass("session-bus", bus.display().to_string()),
+        _ => Check::fail(
+            "session-bus",
+            "no D-Bus session bus found",
+            "run nd inside a desktop session; over SSH, set notification.ssh_target \
+             or use the stderr backend",
+        ),
+    }
+}
+
+/// Send a notification the way `nd run` would without backends configured
+fn check_test_notification() -> Check {
+    let config = load_layered_config()
+        .unwrap_or_default()
+        .notification
+        .unwrap_or_default();
+    let mut notification = Notification::new();
+    notification
+        .summary("nd doctor")
+        .body("Notifications are working.")
+        .icon("dialog-information");
+    let result = if config.has_command() {
+        show_command(&notification, &config, config.urgency)
+    } else {
+        notification.show().map(|_| ()).map_err(Into::into)
+    };
+    match result {
+        Ok(()) => Check::pass("notification", "test notification sent"),
+        Err(e) => Check::fail(
+            "notification",
+            format!("test notification failed: {:#}", e),
+            "start a notification server (your desktop's, or e.g. dunst or mako)",
+        ),
+    }
+}
+
+/// The daemon is optional: `nd run` notifies on its own
+fn check_daemon() -> Check {
+    let hint = "for automatic notifications: sudo systemctl enable --now notify-done";
+    match Command::new("systemctl")
+        .args(["is-active", "notify-done"])
+        .output()
+    {
+        Ok(output) if output.status.success() => Check::pass("daemon", "notify-done is running"),
+        Ok(output) => {
+            let state = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            let state = if state.is_empty() {
+                "not running".to_string()
+            } else {
+                state
+            };
+            Check::warn("daemon", format!("notify-done is {}", state), hint)
+        }
+        Err(e) => Check::warn("daemon", format!("can't run systemctl: {}", e), hint),
+    }
+}
+
+/// The daemon only writes its health snapshot once its eBPF programs are
+/// attached, and keeps it fresh while it handles events
+fn check_daemon_health() -> Check {
+    let hint = "check 'journalctl -u notify-done' for load or attach errors";
+    let health = std::fs::read_to_string(HEALTH_PATH)
+        .ok()
+        .and_then(|content| serde_json::from_str::<DaemonHealth>(&content).ok());
+    let Some(health) = health else {
+        return Check::warn(
+            "ebpf",
+            format!("no health snapshot at {}", HEALTH_PATH),
+            hint,
+        );
+    };
+
+    let age = now_epoch_secs().saturating_sub(health.updated_at);
+    if age > 3 * HEALTH_INTERVAL_SECS {
+        return Check::warn(
+            "ebpf",
+            format!("health snapshot is {}s old, the daemon may be stuck", age),
+            hint,
+        );
+    }
+    Check::pass(
+        "ebpf",
+        format!(
+            "programs attached, {} exec and {} exit events so far",
+            health.exec_events, health.exit_events
+        ),
+    )
+}
+
+fn check_btf() -> Check {
+    if Path::new(VMLINUX_BTF_PATH).exists() {
+        Check::pass("btf", format!("{} present", VMLINUX_BTF_PATH))
+    } else {
+        Check::warn(
+            "btf",
+            format!("{} missing", VMLINUX_BTF_PATH),
+            "the daemon still loads, but use a kernel built with CONFIG_DEBUG_INFO_BTF \
+             to be safe across kernel updates",
+        )
+    }
+}
+
+/// The daemon sends its notifications through notify-send
+fn check_notify_send() -> Check {
+    match find_in_path("notify-send") {
+        Some(path) => Check::pass("notify-send", path.display().to_string()),
+        None => Check::warn(
+            "notify-send",
+            "notify-send not found in $PATH",
+            "install libnotify (libnotify-bin on Debian/Ubuntu) for the daemon's notifications",
+        ),
+    }
+}
+
+fn find_in_path(program: &str) -> Option<PathBuf> {
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| path.is_file())
+}
+
+fn is_writable(path: &Path) -> bool {
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
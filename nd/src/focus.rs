@@ -0,0 +1,37 @@
+use std::process::{Command, Stdio};
+
+/// Whether the terminal `nd` runs in appears to be the focused window;
+/// None when that can't be told
+///
+/// Only X11 (including XWayland terminals) is supported: the terminal's
+/// $WINDOWID is compared with the root window's _NET_ACTIVE_WINDOW, read
+/// with xprop. Terminals that don't export $WINDOWID, tmux/screen and
+/// native Wayland sessions all give None.
+pub fn terminal_focused() -> Option<bool> {
+    std::env::var_os("DISPLAY").filter(|display| !display.is_empty())?;
+    // Inside a multiplexer, $WINDOWID is that of whichever terminal
+    // started the server, not necessarily the one attached now
+    if std::env::var_os("TMUX").is_some() || std::env::var_os("STY").is_some() {
+        return None;
+    }
+    let terminal: u64 = std::env::var("WINDOWID").ok()?.trim().parse().ok()?;
+    Some(active_window()? == terminal)
+}
+
+/// The X11 window with input focus, from the window manager's EWMH hint
+fn active_window() -> Option<u64> {
+    let output = Command::new("xprop")
+        .args(["-root", "_NET_ACTIVE_WINDOW"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    // "_NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007"
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (_, id) = stdout.trim().rsplit_once("# ")?;
+    u64::from_str_radix(id.trim_start_matches("0x"), 16).ok()
+}
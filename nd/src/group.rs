@@ -0,0 +1,214 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::history::now_epoch_secs;
+
+/// How long a group waits after its last running member finished before
+/// it counts as complete, so commands started one after another still
+/// end up in one summary
+pub const DEFAULT_GROUP_IDLE_SECS: u64 = 5;
+
+/// A finished command of a group
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupMember {
+    pub label: String,
+    pub success: bool,
+    pub duration_secs: u64,
+}
+
+/// Shared state of the `nd --group` invocations with the same name
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct GroupState {
+    /// Members that joined and haven't finished yet
+    running: u32,
+    /// Bumped on every change, so an idle check can tell nothing happened
+    generation: u64,
+    /// When the first member joined
+    started_at: u64,
+    /// When the last member finished
+    finished_at: u64,
+    finished: Vec<GroupMember>,
+}
+
+/// A completed group, ready to be summarized
+pub struct GroupSummary {
+    pub members: Vec<GroupMember>,
+    /// From the first member starting to the last one finishing
+    pub elapsed_secs: u64,
+}
+
+/// Register a new running member of group `name`
+pub fn join(name: &str) -> Result<()> {
+    update(name, |state| {
+        if state.running == 0 && state.finished.is_empty() {
+            state.started_at = now_epoch_secs();
+        }
+        state.running += 1;
+        state.generation += 1;
+    })
+}
+
+/// Record a member as finished; returns the group's generation if no other
+/// member is still running, for a later `take_if_idle`
+pub fn finish(name: &str, member: GroupMember) -> Result<Option<u64>> {
+    update(name, |state| {
+        state.running = state.running.saturating_sub(1);
+        state.generation += 1;
+        state.finished.push(member);
+        state.finished_at = now_epoch_secs();
+        (state.running == 0).then_some(state.generation)
+    })
+}
+
+/// Take the finished members of group `name` and reset it, if nothing
+/// happened to it since `generation`
+pub fn take_if_idle(name: &str, generation: u64) -> Result<Option<GroupSummary>> {
+    update(name, |state| {
+        if state.running > 0 || state.generation != generation {
+            return None;
+        }
+        let state = std::mem::take(state);
+        Some(GroupSummary {
+            members: state.finished,
+            elapsed_secs: state.finished_at.saturating_sub(state.started_at),
+        })
+    })
+}
+
+impl GroupSummary {
+    /// e.g. "4 tasks, 3 ok, 1 failed, total 6m 2s", with the failed tasks
+    /// listed below
+    pub fn body(&self, format_duration: impl Fn(u64) -> String) -> String {
+        let failed: Vec<&GroupMember> = self.members.iter().filter(|m| !m.success).collect();
+        let mut body = format!(
+            "{} task{}, {} ok, {} failed, total {}",
+            self.members.len(),
+            if self.members.len() == 1 { "" } else { "s" },
+            self.members.len() - failed.len(),
+            failed.len(),
+            format_duration(self.elapsed_secs)
+        );
+        for member in failed {
+            body.push_str(&format!(
+                "\nFailed: {} ({})",
+                member.label,
+                format_duration(member.duration_secs)
+            ));
+        }
+        body
+    }
+
+    pub fn success(&self) -> bool {
+        self.members.iter().all(|m| m.success)
+    }
+}
+
+/// Apply `f` to the state of group `name` under an exclusive lock
+///
+/// The file is reset rather than removed when a group completes, so an
+/// invocation waiting on the lock never writes to an unlinked file.
+fn update<T>(name: &str, f: impl FnOnce(&mut GroupState) -> T) -> Result<T> {
+    let path = group_path(name);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    lock_exclusive(&file).with_context(|| format!("Failed to lock {}", path.display()))?;
+
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+    // An empty or unreadable file is a fresh group
+    let mut state: GroupState = serde_json::from_str(&content).unwrap_or_default();
+    let result = f(&mut state);
+
+    file.set_len(0)?;
+    file.rewind()?;
+    file.write_all(serde_json::to_string(&state)?.as_bytes())?;
+    Ok(result)
+}
+
+/// Group state lives in the runtime directory, which is per user and
+/// cleared at logout
+fn group_path(name: &str) -> PathBuf {
+    // Keep the name from escaping the groups directory
+    let file_name: String = name
+        .chars()
+        .map(|c| if c == '/' || c == '\0' { '_' } else { c })
+        .collect();
+    dirs::runtime_dir()
+        .or_else(dirs::data_dir)
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("notify-done")
+        .join("groups")
+        .join(format!("{}.json", file_name))
+}
+
+/// Take an exclusive flock on `file`, released when it's closed
+pub fn lock_exclusive(file: &File) -> std::io::Result<()> {
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(label: &str, success: bool, duration_secs: u64) -> GroupMember {
+        GroupMember {
+            label: label.to_string(),
+            success,
+            duration_secs,
+        }
+    }
+
+    #[test]
+    fn summary_counts_members_and_lists_failures() {
+        let summary = GroupSummary {
+            members: vec![
+                member("build", true, 40),
+                member("lint", false, 3),
+                member("test", true, 90),
+                member("docs", false, 7),
+            ],
+            elapsed_secs: 95,
+        };
+        assert_eq!(
+            summary.body(|secs| format!("{}s", secs)),
+            "4 tasks, 2 ok, 2 failed, total 95s\nFailed: lint (3s)\nFailed: docs (7s)"
+        );
+        assert!(!summary.success());
+    }
+
+    #[test]
+    fn single_successful_member() {
+        let summary = GroupSummary {
+            members: vec![member("build", true, 40)],
+            elapsed_secs: 40,
+        };
+        assert_eq!(
+            summary.body(|secs| format!("{}s", secs)),
+            "1 task, 1 ok, 0 failed, total 40s"
+        );
+        assert!(summary.success());
+    }
+
+    #[test]
+    fn group_names_stay_in_the_groups_directory() {
+        let path = group_path("../../etc/passwd");
+        assert_eq!(path.file_name().unwrap(), ".._.._etc_passwd.json");
+        assert!(path.parent().unwrap().ends_with("notify-done/groups"));
+    }
+}
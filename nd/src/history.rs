@@ -0,0 +1,593 @@
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use notify_done_common::config::HistoryConfig;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::group::lock_exclusive;
+
+/// What redacted parts of a command are replaced with
+const REDACTED: &str = "***";
+
+/// A completed command recorded in the history file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Full command line
+    pub command: String,
+    /// Task name given with `--name`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Exit code of the command
+    pub exit_code: i32,
+    /// Wall-clock duration in seconds
+    pub duration_secs: u64,
+    /// Completion time in seconds since the Unix epoch
+    pub completed_at: u64,
+    /// Terminal session the command ran in, see `session_tag`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_tag: Option<String>,
+    /// Host the command ran on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// Signal that killed the command, if any (exit_code is then -1)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+    /// The command dumped core when it was killed
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub core_dumped: bool,
+    /// Working directory the command ran in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    /// End of a failed command's stderr, if it was captured
+    /// (`--notify-on-stderr`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stderr_tail: Option<String>,
+}
+
+impl HistoryEntry {
+    /// Whether the command exited non-zero or was killed
+    pub fn failed(&self) -> bool {
+        self.exit_code != 0 || self.signal.is_some()
+    }
+
+    /// Start time in seconds since the Unix epoch
+    pub fn started_at(&self) -> u64 {
+        self.completed_at.saturating_sub(self.duration_secs)
+    }
+}
+
+/// The command line as it should be saved under `config`: just `program`
+/// if arguments aren't stored, otherwise with `redact_patterns` applied
+///
+/// A pattern that doesn't compile could leave a secret in place, so it
+/// reduces the command to `program` as well.
+pub fn redact_command(command: &str, program: &str, config: &HistoryConfig) -> String {
+    if !config.store_args.unwrap_or(true) {
+        return program.to_string();
+    }
+    let mut command = command.to_string();
+    for pattern in &config.redact_patterns {
+        match Regex::new(pattern) {
+            Ok(regex) => command = redact(&command, &regex),
+            Err(e) => {
+                eprintln!(
+                    "nd: invalid history.redact_patterns entry {:?}, saving only the program: {}",
+                    pattern, e
+                );
+                return program.to_string();
+            }
+        }
+    }
+    command
+}
+
+/// Replace `regex`'s capture groups in `text`, or its whole matches if it
+/// has none
+fn redact(text: &str, regex: &Regex) -> String {
+    let mut spans: Vec<_> = regex
+        .captures_iter(text)
+        .flat_map(|captures| {
+            let groups: Vec<_> = if captures.len() > 1 {
+                captures.iter().skip(1).flatten().collect()
+            } else {
+                captures.get(0).into_iter().collect()
+            };
+            groups.into_iter().map(|m| m.range())
+        })
+        .filter(|span| !span.is_empty())
+        .collect();
+    spans.sort_by_key(|span| span.start);
+
+    let mut redacted = String::with_capacity(text.len());
+    let mut pos = 0;
+    for span in spans {
+        // Nested groups overlap what's already replaced
+        if span.start >= pos {
+            redacted.push_str(&text[pos..span.start]);
+            redacted.push_str(REDACTED);
+        }
+        pos = pos.max(span.end);
+    }
+    redacted.push_str(&text[pos..]);
+    redacted
+}
+
+/// Path of the local history file
+pub fn history_path() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("notify-done")
+        .join("history.json")
+}
+
+/// Load all history entries, oldest first
+pub fn load(path: &Path) -> Result<Vec<HistoryEntry>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = std::fs::read_to_string(path)?;
+    serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse history file {}", path.display()))
+}
+
+/// Append an entry to the history file, dropping entries older than
+/// `rotate_days` if set
+pub fn append(path: &Path, entry: HistoryEntry, rotate_days: Option<u64>) -> Result<()> {
+    let _lock = lock(path)?;
+    let mut entries = load(path)?;
+    entries.push(entry);
+    if let Some(days) = rotate_days {
+        rotate(&mut entries, days, now_epoch_secs());
+    }
+    std::fs::write(path, serde_json::to_string_pretty(&entries)?)?;
+    Ok(())
+}
+
+/// Take the lock serializing changes to the history file at `path`,
+/// released when the returned file is closed
+///
+/// It's a separate file, so clearing the history can remove the history
+/// file itself without an `nd run` waiting on the lock writing to an
+/// unlinked copy.
+fn lock(path: &Path) -> Result<File> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let lock_path = path.with_extension("lock");
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&lock_path)
+        .with_context(|| format!("Failed to open {}", lock_path.display()))?;
+    lock_exclusive(&file).with_context(|| format!("Failed to lock {}", lock_path.display()))?;
+    Ok(file)
+}
+
+/// An entry for the history file, written when dropped unless `record`
+/// already did, so a panic or early return between the command finishing
+/// and the normal write doesn't lose it
+pub struct PendingEntry {
+    entry: Option<HistoryEntry>,
+    rotate_days: Option<u64>,
+    path: PathBuf,
+}
+
+impl PendingEntry {
+    pub fn new(entry: HistoryEntry, rotate_days: Option<u64>) -> Self {
+        Self {
+            entry: Some(entry),
+            rotate_days,
+            path: history_path(),
+        }
+    }
+
+    /// Write the entry now
+    pub fn record(mut self) {
+        self.write();
+    }
+
+    fn write(&mut self) {
+        let Some(entry) = self.entry.take() else {
+            return;
+        };
+        if let Err(e) = append(&self.path, entry, self.rotate_days) {
+            eprintln!("nd: failed to record history: {}", e);
+        }
+    }
+}
+
+impl Drop for PendingEntry {
+    fn drop(&mut self) {
+        self.write();
+    }
+}
+
+/// Drop entries that completed more than `days` days before `now`
+pub fn rotate(entries: &mut Vec<HistoryEntry>, days: u64, now: u64) {
+    let cutoff = now.saturating_sub(days.saturating_mul(86400));
+    entries.retain(|e| e.completed_at >= cutoff);
+}
+
+/// Remove all history entries
+pub fn clear(path: &Path) -> Result<()> {
+    let _lock = lock(path)?;
+    if path.exists() {
+        std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove history file {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Select the last `count` entries completed at or after `since` (epoch seconds)
+pub fn filter(entries: &[HistoryEntry], since: Option<u64>, count: usize) -> &[HistoryEntry] {
+    let start = match since {
+        Some(since) => entries
+            .iter()
+            .position(|e| e.completed_at >= since)
+            .unwrap_or(entries.len()),
+        None => 0,
+    };
+    let matching = &entries[start..];
+    &matching[matching.len().saturating_sub(count)..]
+}
+
+/// Identify the terminal session nd is running in, from the most specific
+/// source available: the terminal's session id, the tmux pane, the X11
+/// window, then the controlling tty
+pub fn session_tag() -> Option<String> {
+    let env = |name| std::env::var(name).ok().filter(|v: &String| !v.is_empty());
+
+    if let Some(id) = env("TERM_SESSION_ID") {
+        return Some(id);
+    }
+    if let Some(pane) = env("TMUX_PANE") {
+        return Some(format!("tmux {}", pane));
+    }
+    if let Some(window) = env("WINDOWID") {
+        return Some(format!("window {}", window));
+    }
+
+    let tty = unsafe { libc::ttyname(libc::STDIN_FILENO) };
+    if tty.is_null() {
+        return None;
+    }
+    let tty = unsafe { std::ffi::CStr::from_ptr(tty) };
+    Some(tty.to_string_lossy().into_owned())
+}
+
+/// Name of this machine
+pub fn hostname() -> Option<String> {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .map(|name| name.trim_end().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+/// Current time in seconds since the Unix epoch
+pub fn now_epoch_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Parse a `--since` value into epoch seconds.
+///
+/// Accepts either a relative duration counted back from now, made of one or
+/// more `<number><unit>` pairs where unit is `s`, `m`, `h`, `d` or `w`
+/// (e.g. `30m`, `1h30m`, `2d`), or an absolute local date `YYYY-MM-DD`,
+/// optionally followed by a time as `YYYY-MM-DDTHH:MM` or `YYYY-MM-DD HH:MM`.
+pub fn parse_since(value: &str) -> Result<u64> {
+    let value = value.trim();
+    if let Some(secs) = parse_relative(value) {
+        return Ok(now_epoch_secs().saturating_sub(secs));
+    }
+    parse_absolute(value).with_context(|| {
+        format!(
+            "Invalid --since value '{}' (expected e.g. '1h', '2d12h' or '2024-01-01')",
+            value
+        )
+    })
+}
+
+/// Parse a relative duration like `1h30m` into seconds
+fn parse_relative(value: &str) -> Option<u64> {
+    if value.is_empty() {
+        return None;
+    }
+
+    let mut total: u64 = 0;
+    let mut number = String::new();
+    for c in value.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 7 * 86400,
+            _ => return None,
+        };
+        let n: u64 = number.parse().ok()?;
+        total = total.checked_add(n.checked_mul(unit)?)?;
+        number.clear();
+    }
+
+    // A trailing number without a unit is ambiguous
+    if !number.is_empty() {
+        return None;
+    }
+    Some(total)
+}
+
+/// Parse an absolute local date/time into epoch seconds
+fn parse_absolute(value: &str) -> Result<u64> {
+    let (date, time) = match value.split_once(['T', ' ']) {
+        Some((d, t)) => (d, Some(t)),
+        None => (value, None),
+    };
+
+    let date_parts: Vec<&str> = date.split('-').collect();
+    if date_parts.len() != 3 {
+        anyhow::bail!("expected date as YYYY-MM-DD");
+    }
+    let year: i32 = date_parts[0].parse()?;
+    let month: i32 = date_parts[1].parse()?;
+    let day: i32 = date_parts[2].parse()?;
+    // mktime would quietly turn 2026-02-31 into March 3rd
+    if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        anyhow::bail!("date out of range");
+    }
+
+    let (hour, minute) = match time {
+        Some(t) => {
+            let (h, m) = t.split_once(':').context("expected time as HH:MM")?;
+            let hour: i32 = h.parse()?;
+            let minute: i32 = m.parse()?;
+            if !(0..24).contains(&hour) || !(0..60).contains(&minute) {
+                anyhow::bail!("time out of range");
+            }
+            (hour, minute)
+        }
+        None => (0, 0),
+    };
+
+    // Let mktime resolve the local timezone and DST for us
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    tm.tm_year = year - 1900;
+    tm.tm_mon = month - 1;
+    tm.tm_mday = day;
+    tm.tm_hour = hour;
+    tm.tm_min = minute;
+    tm.tm_isdst = -1;
+    let secs = unsafe { libc::mktime(&mut tm) };
+    if secs < 0 {
+        anyhow::bail!("date out of range");
+    }
+    Ok(secs as u64)
+}
+
+/// Days in `month` (1-12) of `year`, in the Gregorian calendar
+fn days_in_month(year: i32, month: i32) -> i32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Format epoch seconds as a local `YYYY-MM-DD HH:MM:SS` timestamp
+pub fn format_timestamp(secs: u64) -> String {
+    let time = secs as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return secs.to_string();
+    }
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(command: &str, completed_at: u64) -> HistoryEntry {
+        HistoryEntry {
+            command: command.to_string(),
+            name: None,
+            exit_code: 0,
+            duration_secs: 1,
+            completed_at,
+            session_tag: None,
+            host: None,
+            signal: None,
+            core_dumped: false,
+            cwd: None,
+            stderr_tail: None,
+        }
+    }
+
+    /// A history file path of our own under the temp directory
+    fn temp_history(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nd-test-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.join("history.json")
+    }
+
+    #[test]
+    fn rotate_drops_entries_older_than_the_cutoff() {
+        let now = 100 * 86400;
+        let mut entries = vec![
+            entry("old", now - 8 * 86400),
+            entry("edge", now - 7 * 86400),
+            entry("recent", now - 3600),
+        ];
+        rotate(&mut entries, 7, now);
+        let kept: Vec<_> = entries.iter().map(|e| e.command.as_str()).collect();
+        assert_eq!(kept, ["edge", "recent"]);
+    }
+
+    #[test]
+    fn rotate_with_zero_days_keeps_only_now() {
+        let now = 1_000_000;
+        let mut entries = vec![entry("before", now - 1), entry("now", now)];
+        rotate(&mut entries, 0, now);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].command, "now");
+    }
+
+    #[test]
+    fn append_rotates_and_clear_removes_the_file() {
+        let path = temp_history("rotate");
+        append(&path, entry("ancient", 86400), None).unwrap();
+        append(&path, entry("fresh", now_epoch_secs()), Some(30)).unwrap();
+        let entries = load(&path).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].command, "fresh");
+
+        clear(&path).unwrap();
+        assert!(load(&path).unwrap().is_empty());
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn pending_entry_is_recorded_on_panic() {
+        let path = temp_history("pending");
+        let result = std::panic::catch_unwind(|| {
+            let _pending = PendingEntry {
+                entry: Some(entry("make", now_epoch_secs())),
+                rotate_days: None,
+                path: path.clone(),
+            };
+            panic!("after the command finished");
+        });
+        assert!(result.is_err());
+        let entries = load(&path).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].command, "make");
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn pending_entry_is_recorded_once() {
+        let path = temp_history("pending-once");
+        PendingEntry {
+            entry: Some(entry("make", now_epoch_secs())),
+            rotate_days: None,
+            path: path.clone(),
+        }
+        .record();
+        assert_eq!(load(&path).unwrap().len(), 1);
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    fn redacting(patterns: &[&str]) -> HistoryConfig {
+        HistoryConfig {
+            redact_patterns: patterns.iter().map(|p| p.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn redaction_replaces_groups_or_whole_matches() {
+        let command = "mysql -u root --password=hunter2 -e 'select 1'";
+        let config = redacting(&["--password=(\\S+)"]);
+        assert_eq!(
+            redact_command(command, "mysql", &config),
+            "mysql -u root --password=*** -e 'select 1'"
+        );
+
+        let command = "curl -H 'Authorization: Bearer abc.def' https://api.example.com";
+        let config = redacting(&["Bearer [A-Za-z0-9.]+", "https://\\S+"]);
+        assert_eq!(
+            redact_command(command, "curl", &config),
+            "curl -H 'Authorization: ***' ***"
+        );
+    }
+
+    #[test]
+    fn redaction_covers_every_match_and_group() {
+        let command = "tool --user=alice --token=t1 --token=t2";
+        let config = redacting(&["--(?:user|token)=(\\w+)"]);
+        assert_eq!(
+            redact_command(command, "tool", &config),
+            "tool --user=*** --token=*** --token=***"
+        );
+        assert_eq!(redact_command(command, "tool", &redacting(&[])), command);
+    }
+
+    #[test]
+    fn without_args_or_with_a_bad_pattern_only_the_program_is_kept() {
+        let command = "deploy --key=secret";
+        let config = HistoryConfig {
+            store_args: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(redact_command(command, "deploy", &config), "deploy");
+        let config = redacting(&["--key=(\\S+", "x"]);
+        assert_eq!(redact_command(command, "deploy", &config), "deploy");
+    }
+
+    #[test]
+    fn relative_since_sums_units() {
+        assert_eq!(parse_relative("30m"), Some(1800));
+        assert_eq!(parse_relative("1h30m"), Some(5400));
+        assert_eq!(parse_relative("2d12h"), Some(2 * 86400 + 12 * 3600));
+        assert_eq!(parse_relative("1w"), Some(7 * 86400));
+    }
+
+    #[test]
+    fn relative_since_rejects_malformed_values() {
+        assert_eq!(parse_relative(""), None);
+        assert_eq!(parse_relative("10"), None);
+        assert_eq!(parse_relative("h"), None);
+        assert_eq!(parse_relative("5y"), None);
+    }
+
+    #[test]
+    fn relative_since_counts_back_from_now() {
+        let since = parse_since("1h").unwrap();
+        assert!(now_epoch_secs() - since >= 3600);
+        assert!(now_epoch_secs() - since < 3700);
+    }
+
+    #[test]
+    fn absolute_since_is_local_time() {
+        let secs = parse_absolute("2024-03-05T14:30").unwrap();
+        assert_eq!(format_timestamp(secs), "2024-03-05 14:30:00");
+        let secs = parse_absolute("2024-03-05").unwrap();
+        assert_eq!(format_timestamp(secs), "2024-03-05 00:00:00");
+        let secs = parse_absolute("2024-03-05 09:05").unwrap();
+        assert_eq!(format_timestamp(secs), "2024-03-05 09:05:00");
+    }
+
+    #[test]
+    fn absolute_since_checks_the_calendar() {
+        assert!(parse_absolute("2024-02-29").is_ok());
+        assert!(parse_absolute("2000-02-29").is_ok());
+        assert!(parse_absolute("2023-02-29").is_err());
+        assert!(parse_absolute("1900-02-29").is_err());
+        assert!(parse_absolute("2026-02-31").is_err());
+        assert!(parse_absolute("2026-04-31").is_err());
+        assert!(parse_absolute("2026-13-01").is_err());
+        assert!(parse_absolute("2026-01-00").is_err());
+        assert!(parse_absolute("2026-01-01T24:00").is_err());
+        assert!(parse_absolute("2026-01-01T12").is_err());
+        assert!(parse_absolute("yesterday").is_err());
+    }
+}
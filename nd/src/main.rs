@@ -1,80 +1,172 @@
+mod capture;
 mod cli;
+mod doctor;
+mod focus;
+mod group;
+mod history;
+mod notification_ids;
+mod output;
+mod pid_watch;
+mod progress;
+mod signals;
+mod unit_watch;
 
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
 use clap::Parser;
-use notify_rust::Notification;
-use serde::{Deserialize, Serialize};
+use notify_done_common::config::{
+    self, format_parse_error, is_under_any, min_expected_for, normalize_icon, Backend,
+    DurationPrecision, HintValue, HistoryConfig, NotificationConfig, Urgency, UserConfig,
+    DEFAULT_APP_NAME, DEFAULT_MIN_DURATION_MS,
+};
+use notify_done_common::dedup;
+use notify_done_common::health::{DaemonHealth, RecentLogLine, HEALTH_PATH, RECENT_LOG_PATH};
+use notify_rust::{Hint, Notification, Timeout};
+use regex::Regex;
+use serde::de::DeserializeOwned;
 
-use cli::{Cli, Commands, ConfigAction};
+use cli::{Cli, Commands, ConfigAction, DebugAction, NotifyOverrides, RunOptions};
+use doctor::CheckStatus;
+use group::{GroupMember, DEFAULT_GROUP_IDLE_SECS};
+use history::{HistoryEntry, PendingEntry};
+use notification_ids::NotificationIds;
+use output::{EffectiveValue, ListedProcess, Output};
+use pid_watch::WatchedProcess;
+use progress::{ProgressFifo, PROGRESS_FIFO_ENV};
+use signals::DeferredSignals;
+use unit_watch::WatchedUnit;
 
-/// User configuration (same structure as daemon)
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-struct UserConfig {
-    #[serde(default)]
-    threshold_seconds: Option<u64>,
-    #[serde(default)]
-    ignore_patterns: Vec<String>,
-    #[serde(default)]
-    always_notify: Vec<String>,
-    #[serde(default)]
-    disabled: bool,
-}
+/// System-wide config, shared with the daemon
+const SYSTEM_CONFIG_PATH: &str = "/etc/notify-done/config.toml";
+
+/// Overrides the configured threshold, below `--threshold`
+const THRESHOLD_ENV: &str = "ND_THRESHOLD";
+
+const DEFAULT_THRESHOLD_SECS: u64 = 10;
+
+/// Passes group_idle_seconds on to `nd group-flush`
+const GROUP_IDLE_ENV: &str = "ND_GROUP_IDLE_SECS";
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
-    match cli.command {
-        Commands::Status => cmd_status(),
-        Commands::List => cmd_list(),
-        Commands::History { count } => cmd_history(count),
-        Commands::Config { action } => cmd_config(action),
-        Commands::Test => cmd_test(),
-        Commands::Run { threshold, command } => cmd_run(threshold, command),
-        Commands::Watch => cmd_watch(),
-    }
+    let threshold = cli.threshold;
+
+    // `nd -- <command>` is a shorthand for `nd run -- <command>`
+    let Some(command) = cli.command else {
+        return cmd_run(
+            threshold,
+            cli.name,
+            cli.group,
+            &cli.overrides,
+            &cli.run_options,
+            cli.exec,
+        );
+    };
+
+    let output = match command {
+        // These stream the command's or journal's own output
+        Commands::Run { command } => {
+            return cmd_run(
+                threshold,
+                cli.name,
+                cli.group,
+                &cli.overrides,
+                &cli.run_options,
+                command,
+            )
+        }
+        Commands::Watch => return cmd_watch(),
+        Commands::GroupFlush { generation, group } => return cmd_group_flush(&group, generation),
+
+        Commands::Status => cmd_status()?,
+        Commands::List => cmd_list()?,
+        Commands::History {
+            count,
+            since,
+            show_session,
+            clear,
+            yes,
+        } => {
+            if clear {
+                cmd_history_clear(yes)?
+            } else {
+                cmd_history(count, since, show_session)?
+            }
+        }
+        Commands::Last => cmd_last()?,
+        Commands::Why => cmd_why()?,
+        Commands::Config { action } => cmd_config(action)?,
+        Commands::Test => cmd_test()?,
+        Commands::Doctor => {
+            let checks = doctor::run_checks();
+            let failed = checks.iter().any(|c| c.status == CheckStatus::Fail);
+            Output::Doctor { checks }.render(cli.json)?;
+            std::process::exit(if failed { 1 } else { 0 });
+        }
+        Commands::Debug {
+            action: DebugAction::Log { count },
+        } => cmd_debug_log(count)?,
+        Commands::WatchPid { pid } => cmd_watch_pid(threshold, cli.name, &cli.overrides, pid)?,
+        Commands::WatchUnit { unit, user } => {
+            cmd_watch_unit(threshold, cli.name, &cli.overrides, &unit, user)?
+        }
+    };
+
+    output.render(cli.json)
 }
 
-fn cmd_status() -> Result<()> {
+fn cmd_status() -> Result<Output> {
     // Check if daemon is running
     let output = Command::new("systemctl")
         .args(["is-active", "notify-done"])
         .output();
 
-    match output {
-        Ok(o) if o.status.success() => {
-            println!("Daemon status: running");
+    let running = matches!(output, Ok(o) if o.status.success());
+    let mut active = None;
+    let mut main_pid = None;
 
-            // Get more info
-            let info = Command::new("systemctl")
-                .args(["status", "notify-done", "--no-pager", "-n", "0"])
-                .output()?;
+    if running {
+        // Get more info
+        let info = Command::new("systemctl")
+            .args(["status", "notify-done", "--no-pager", "-n", "0"])
+            .output()?;
 
-            let stdout = String::from_utf8_lossy(&info.stdout);
-            for line in stdout.lines() {
-                if line.contains("Active:") || line.contains("Main PID:") {
-                    println!("{}", line.trim());
-                }
+        let stdout = String::from_utf8_lossy(&info.stdout);
+        for line in stdout.lines() {
+            let line = line.trim();
+            if let Some(rest) = line.strip_prefix("Active:") {
+                active = Some(rest.trim().to_string());
+            } else if let Some(rest) = line.strip_prefix("Main PID:") {
+                main_pid = rest.split_whitespace().next().and_then(|p| p.parse().ok());
             }
         }
-        _ => {
-            println!("Daemon status: not running");
-            println!("\nTo start the daemon:");
-            println!("  sudo systemctl start notify-done");
-        }
     }
 
-    Ok(())
-}
+    // Written by the daemon every few seconds; missing on older daemons
+    let health = if running {
+        std::fs::read_to_string(HEALTH_PATH)
+            .ok()
+            .and_then(|content| serde_json::from_str::<DaemonHealth>(&content).ok())
+    } else {
+        None
+    };
 
-fn cmd_list() -> Result<()> {
-    println!("Currently tracked processes:");
-    println!("(Note: Full tracking requires the daemon to be running)");
-    println!();
+    Ok(Output::Status {
+        running,
+        active,
+        main_pid,
+        health,
+    })
+}
 
+fn cmd_list() -> Result<Output> {
     // For now, show processes belonging to current user that might be tracked
     let uid = unsafe { libc::getuid() };
     let output = Command::new("ps")
@@ -88,85 +180,147 @@ fn cmd_list() -> Result<()> {
         .output()?;
 
     let stdout = String::from_utf8_lossy(&output.stdout);
-    println!("{:>8} {:>12} COMMAND", "PID", "ELAPSED");
-    println!("{:-<8} {:-<12} {:-<20}", "", "", "");
+    let processes = stdout
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some(ListedProcess {
+                pid: fields.next()?.parse().ok()?,
+                elapsed: fields.next()?.to_string(),
+                comm: fields.collect::<Vec<_>>().join(" "),
+            })
+        })
+        .collect();
 
-    for line in stdout.lines() {
-        println!("{}", line);
-    }
+    Ok(Output::List { processes })
+}
 
-    Ok(())
+fn cmd_history(count: usize, since: Option<String>, show_session: bool) -> Result<Output> {
+    let since = since.as_deref().map(history::parse_since).transpose()?;
+
+    let history_path = history::history_path();
+    let entries = history::load(&history_path)?;
+    let entries = history::filter(&entries, since, count).to_vec();
+
+    Ok(Output::History {
+        since,
+        entries,
+        show_session,
+    })
 }
 
-fn cmd_history(_count: usize) -> Result<()> {
-    // History is stored by the daemon
-    // For now, we can check a local history file if it exists
-    let history_path = dirs::data_dir()
-        .unwrap_or_else(|| PathBuf::from("."))
-        .join("notify-done")
-        .join("history.json");
+fn cmd_last() -> Result<Output> {
+    let entries = history::load(&history::history_path())?;
+    Ok(Output::Last {
+        entry: entries.last().cloned(),
+    })
+}
 
-    if history_path.exists() {
-        let content = std::fs::read_to_string(&history_path)?;
-        println!("{}", content);
-    } else {
-        println!("No history available.");
-        println!("History is recorded when the daemon is running.");
+fn cmd_why() -> Result<Output> {
+    let entries = history::load(&history::history_path())?;
+    Ok(Output::Why {
+        entry: entries.into_iter().rev().find(HistoryEntry::failed),
+    })
+}
+
+fn cmd_history_clear(yes: bool) -> Result<Output> {
+    let history_path = history::history_path();
+    let entries = history::load(&history_path)?;
+    if entries.is_empty() {
+        return Ok(Output::HistoryClear {
+            cleared: 0,
+            aborted: false,
+        });
     }
 
-    Ok(())
+    if !yes {
+        // Prompt on stderr so stdout stays clean for --json
+        eprint!("Clear {} history entries? [y/N] ", entries.len());
+        std::io::stderr().flush()?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            return Ok(Output::HistoryClear {
+                cleared: 0,
+                aborted: true,
+            });
+        }
+    }
+
+    history::clear(&history_path)?;
+    Ok(Output::HistoryClear {
+        cleared: entries.len(),
+        aborted: false,
+    })
 }
 
-fn cmd_config(action: ConfigAction) -> Result<()> {
+fn cmd_config(action: ConfigAction) -> Result<Output> {
     let config_path = user_config_path()?;
 
-    match action {
+    let (changed, message) = match action {
         ConfigAction::Show => {
-            if config_path.exists() {
-                let content = std::fs::read_to_string(&config_path)?;
-                println!("User config ({}):\n", config_path.display());
-                println!("{}", content);
+            let user_config = if config_path.exists() {
+                Some(std::fs::read_to_string(&config_path)?)
             } else {
-                println!("No user config found at {}", config_path.display());
-                println!("\nUsing system defaults. Run 'nd config init' to create a user config.");
-            }
+                None
+            };
 
             // Also show system config if readable
-            let system_path = PathBuf::from("/etc/notify-done/config.toml");
-            if system_path.exists() {
-                if let Ok(content) = std::fs::read_to_string(&system_path) {
-                    println!("\nSystem config ({}):\n", system_path.display());
-                    println!("{}", content);
-                }
-            }
+            let system_path = PathBuf::from(SYSTEM_CONFIG_PATH);
+            let system_config = std::fs::read_to_string(&system_path).ok();
+
+            return Ok(Output::ConfigShow {
+                user_path: config_path,
+                user_config,
+                system_path,
+                system_config,
+            });
         }
 
+        ConfigAction::Effective => return cmd_config_effective(),
+
         ConfigAction::Init => {
             if config_path.exists() {
-                println!("Config already exists at {}", config_path.display());
-                return Ok(());
+                return Ok(Output::ConfigInit {
+                    path: config_path,
+                    created: false,
+                });
             }
 
-            std::fs::create_dir_all(config_path.parent().unwrap())?;
+            write_default_config(&config_path)?;
 
-            let default_config = UserConfig {
-                threshold_seconds: Some(10),
-                ignore_patterns: vec![],
-                always_notify: vec![],
-                disabled: false,
-            };
+            return Ok(Output::ConfigInit {
+                path: config_path,
+                created: true,
+            });
+        }
 
-            let content = toml::to_string_pretty(&default_config)?;
-            std::fs::write(&config_path, content)?;
+        ConfigAction::Edit => {
+            if !config_path.exists() {
+                write_default_config(&config_path)?;
+            }
+
+            let editor = editor_command();
+            let status = Command::new(&editor[0])
+                .args(&editor[1..])
+                .arg(&config_path)
+                .status()
+                .with_context(|| format!("Failed to run editor '{}'", editor.join(" ")))?;
+            if !status.success() {
+                anyhow::bail!("Editor exited with {}", status);
+            }
 
-            println!("Created config at {}", config_path.display());
+            // Catch mistakes now rather than when the config is next used
+            load_config(&config_path)?;
+
+            return Ok(Output::ConfigEdit { path: config_path });
         }
 
         ConfigAction::Threshold { seconds } => {
             let mut config = load_or_create_config(&config_path)?;
             config.threshold_seconds = Some(seconds);
             save_config(&config_path, &config)?;
-            println!("Set threshold to {} seconds", seconds);
+            (true, format!("Set threshold to {} seconds", seconds))
         }
 
         ConfigAction::Ignore { pattern } => {
@@ -174,9 +328,12 @@ fn cmd_config(action: ConfigAction) -> Result<()> {
             if !config.ignore_patterns.contains(&pattern) {
                 config.ignore_patterns.push(pattern.clone());
                 save_config(&config_path, &config)?;
-                println!("Added '{}' to ignore patterns", pattern);
+                (true, format!("Added '{}' to ignore patterns", pattern))
             } else {
-                println!("'{}' is already in ignore patterns", pattern);
+                (
+                    false,
+                    format!("'{}' is already in ignore patterns", pattern),
+                )
             }
         }
 
@@ -185,9 +342,15 @@ fn cmd_config(action: ConfigAction) -> Result<()> {
             if !config.always_notify.contains(&pattern) {
                 config.always_notify.push(pattern.clone());
                 save_config(&config_path, &config)?;
-                println!("Added '{}' to always-notify patterns", pattern);
+                (
+                    true,
+                    format!("Added '{}' to always-notify patterns", pattern),
+                )
             } else {
-                println!("'{}' is already in always-notify patterns", pattern);
+                (
+                    false,
+                    format!("'{}' is already in always-notify patterns", pattern),
+                )
             }
         }
 
@@ -195,83 +358,693 @@ fn cmd_config(action: ConfigAction) -> Result<()> {
             let mut config = load_or_create_config(&config_path)?;
             config.disabled = true;
             save_config(&config_path, &config)?;
-            println!("Notifications disabled");
+            (true, "Notifications disabled".to_string())
         }
 
         ConfigAction::Enable => {
             let mut config = load_or_create_config(&config_path)?;
             config.disabled = false;
             save_config(&config_path, &config)?;
-            println!("Notifications enabled");
+            (true, "Notifications enabled".to_string())
         }
-    }
+    };
 
-    Ok(())
+    Ok(Output::ConfigUpdate {
+        path: config_path,
+        changed,
+        message,
+    })
 }
 
-fn cmd_test() -> Result<()> {
-    println!("Sending test notification...");
-
-    Notification::new()
+fn cmd_test() -> Result<Output> {
+    // Use the configured app identity so per-app theming can be checked
+    let config = load_layered_config()
+        .unwrap_or_default()
+        .notification
+        .unwrap_or_default();
+    let mut notification = Notification::new();
+    notification
         .summary("notify-done test")
         .body("If you see this, notifications are working!")
         .icon("dialog-information")
-        .appname("notify-done")
-        .show()
-        .context("Failed to send notification")?;
+        .appname(&config.app_name);
+    if let Some(entry) = config.desktop_entry {
+        notification.hint(Hint::DesktopEntry(entry));
+    }
+    notification.show().context("Failed to send notification")?;
 
-    println!("Notification sent!");
-    Ok(())
+    Ok(Output::Test { sent: true })
+}
+
+fn cmd_debug_log(count: usize) -> Result<Output> {
+    // Only written while the daemon runs with recent_log_lines set
+    let lines = match std::fs::read_to_string(RECENT_LOG_PATH) {
+        Ok(content) => Some(
+            serde_json::from_str::<Vec<RecentLogLine>>(&content)
+                .with_context(|| format!("Failed to parse {}", RECENT_LOG_PATH))?,
+        ),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", RECENT_LOG_PATH)),
+    };
+
+    Ok(Output::DebugLog {
+        lines: lines.map(|lines| {
+            let skip = lines.len().saturating_sub(count);
+            lines.into_iter().skip(skip).collect()
+        }),
+    })
 }
 
-fn cmd_run(threshold: u64, command: Vec<String>) -> Result<()> {
+fn cmd_run(
+    threshold: Option<u64>,
+    name: Option<String>,
+    group: Option<String>,
+    overrides: &NotifyOverrides,
+    options: &RunOptions,
+    command: Vec<String>,
+) -> Result<()> {
     if command.is_empty() {
         anyhow::bail!("No command specified");
     }
+    let progress_regex = options
+        .progress_regex
+        .as_deref()
+        .map(Regex::new)
+        .transpose()
+        .context("Invalid --progress-regex")?;
+    // The whole script stands in for the command in titles and history
+    let command = if options.shell {
+        vec![command.join(" ")]
+    } else {
+        command
+    };
+
+    let config = load_layered_config_or_default();
+    let threshold = resolve_threshold(threshold, &config);
+
+    let group = group.filter(|group| match group::join(group) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!(
+                "nd: notifying on its own, failed to join group {}: {:#}",
+                group, e
+            );
+            false
+        }
+    });
+
+    // Let the command report progress through a fifo, or scan its output
+    // for it
+    let progress = if options.progress_fifo || progress_regex.is_some() {
+        let summary = format!("Running: {}", name.as_deref().unwrap_or(&command[0]));
+        let app_name = config
+            .notification
+            .as_ref()
+            .map_or_else(|| DEFAULT_APP_NAME.to_string(), |n| n.app_name.clone());
+        match ProgressFifo::create(summary, app_name) {
+            Ok(progress) => Some(progress),
+            Err(e) => {
+                eprintln!("nd: progress reporting disabled: {:#}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
 
     let start = Instant::now();
 
     // Run the command
-    let status = Command::new(&command[0])
-        .args(&command[1..])
-        .stdin(Stdio::inherit())
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .status()
+    let mut cmd = child_command(&command, options.shell);
+    cmd.stdin(Stdio::inherit())
+        .stdout(if options.capture_output || progress_regex.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::inherit()
+        })
+        .stderr(if options.notify_on_stderr || progress_regex.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::inherit()
+        });
+    if let Some(progress) = &progress {
+        cmd.env(PROGRESS_FIFO_ENV, progress.path());
+    }
+    let mut child = cmd
+        .spawn()
         .with_context(|| format!("Failed to execute: {}", command[0]))?;
+    // From here on, a signal lets the command finish and gets it recorded
+    let signals = DeferredSignals::install(child.id());
+    // The name the daemon sees the command exit under, read while it runs
+    let child_pid = child.id();
+    let child_comm = std::fs::read_to_string(format!("/proc/{}/comm", child_pid))
+        .ok()
+        .map(|comm| comm.trim_end().to_string());
+
+    // Each stream gets its own scanner, so their lines don't mix
+    let scanner = || {
+        let (progress, regex) = (progress.as_ref()?, progress_regex.clone()?);
+        progress
+            .scanner(regex)
+            .map_err(|e| eprintln!("nd: progress scanning disabled: {:#}", e))
+            .ok()
+    };
+
+    // Pass stderr through on a thread of its own, noting whether there was any
+    let stderr_tee = child.stderr.take().map(|stderr| {
+        let mut scanner = scanner();
+        std::thread::spawn(move || {
+            capture::tee(stderr, io::stderr(), capture::CAPTURE_LIMIT, |bytes| {
+                if let Some(scanner) = &mut scanner {
+                    scanner.feed(bytes);
+                }
+            })
+        })
+    });
 
+    // Pass stdout through while keeping its start for the notification
+    let mut stdout_scanner = scanner();
+    let captured = match child.stdout.take() {
+        Some(stdout) => match capture::tee(stdout, io::stdout(), capture::CAPTURE_LIMIT, |bytes| {
+            if let Some(scanner) = &mut stdout_scanner {
+                scanner.feed(bytes);
+            }
+        }) {
+            Ok(captured) => Some(captured),
+            Err(e) => {
+                eprintln!("nd: failed to capture output: {}", e);
+                None
+            }
+        },
+        None => None,
+    };
+    let status = child
+        .wait()
+        .with_context(|| format!("Failed to wait for: {}", command[0]))?;
     let duration = start.elapsed();
     let duration_secs = duration.as_secs();
     let exit_code = status.code().unwrap_or(-1);
+    let stderr_output = stderr_tee.and_then(|tee| match tee.join() {
+        Ok(Ok(captured)) => Some(captured),
+        Ok(Err(e)) => {
+            eprintln!("nd: failed to capture stderr: {}", e);
+            None
+        }
+        Err(_) => None,
+    });
+
+    let program = program_name(&command, options.shell);
 
-    // Only notify if above threshold
-    if duration_secs >= threshold {
-        let status_str = if status.success() {
+    // Recorded even if what follows panics
+    let mut entry = HistoryEntry {
+        command: history::redact_command(&command.join(" "), program, &config.history),
+        name: name.clone(),
+        exit_code,
+        duration_secs,
+        completed_at: history::now_epoch_secs(),
+        session_tag: history::session_tag(),
+        host: history::hostname(),
+        signal: status.signal(),
+        core_dumped: status.core_dumped(),
+        cwd: std::env::current_dir()
+            .ok()
+            .map(|cwd| cwd.to_string_lossy().into_owned()),
+        stderr_tail: None,
+    };
+    // Kept for `nd why`, which only looks at failures
+    if entry.failed() {
+        entry.stderr_tail = stderr_output
+            .as_ref()
+            .map(|captured| captured.tail_text())
+            .filter(|tail| !tail.is_empty());
+    }
+    let history_entry = PendingEntry::new(entry, config.history.rotate_days);
+    // nd has no other children, so this is the command and its descendants
+    let usage = children_usage();
+    let peak_rss_kib = usage
+        .as_ref()
+        .filter(|_| options.track_rss)
+        .and_then(peak_rss_kib);
+    let cpu_time = usage.as_ref().filter(|_| options.track_cpu).map(cpu_time);
+
+    history_entry.record();
+
+    // A task finishing far sooner than expected usually failed early, so
+    // alert even below the threshold
+    let min_expected = name
+        .as_deref()
+        .and_then(|name| min_expected_for(&config.min_expected_seconds, name))
+        .or_else(|| min_expected_for(&config.min_expected_seconds, program))
+        .filter(|&min| duration_secs < min);
+
+    let exit_messages = config.exit_messages.get(program);
+    let success = match exit_messages {
+        Some(m) => m.is_success(exit_code),
+        None => status.success(),
+    };
+    let failure_override = !success && config.notify_failures_regardless.unwrap_or(false);
+    // A success that wrote to stderr (e.g. compiler warnings), with --notify-on-stderr
+    let warnings = stderr_output
+        .as_ref()
+        .filter(|_| success && options.notify_on_stderr)
+        .map(|captured| captured.text())
+        .filter(|text| !text.is_empty());
+
+    // Group members only count towards the group's summary
+    if let Some(group) = &group {
+        let member = GroupMember {
+            label: name.clone().unwrap_or_else(|| program.to_string()),
+            success,
+            duration_secs,
+        };
+        finish_group_member(group, member, &config);
+    }
+
+    let min_duration =
+        Duration::from_millis(config.min_duration_ms.unwrap_or(DEFAULT_MIN_DURATION_MS));
+    let skip_if_focused = options.skip_if_focused || config.skip_if_focused.unwrap_or(false);
+    let skipped = if options.print_only {
+        None
+    } else if let Some(group) = &group {
+        Some(SkipReason::Grouped(group.clone()))
+    } else if duration < min_duration {
+        Some(SkipReason::BelowMinDuration {
+            duration,
+            min_duration,
+        })
+    } else if duration_secs < threshold && min_expected.is_none() && !failure_override {
+        Some(SkipReason::BelowThreshold {
+            duration_secs,
+            threshold,
+        })
+    } else if skip_if_focused && focus::terminal_focused() == Some(true) {
+        // Whoever is looking at the terminal already saw the command
+        // finish; when focus can't be told, notify anyway
+        Some(SkipReason::TerminalFocused)
+    } else if let Some(cwd) = outside_cwd_prefixes(&config.notify_cwd_prefixes) {
+        Some(SkipReason::OutsideCwdPrefixes(cwd))
+    } else if !claim_notification(child_pid, child_comm.as_deref()) {
+        // Checked last: claiming is what keeps the daemon from notifying
+        Some(SkipReason::AlreadyNotified)
+    } else {
+        None
+    };
+    if let Some(reason) = skipped.as_ref().filter(|_| options.verbose) {
+        eprintln!("nd: skipped: {}", reason);
+    }
+    let notify = skipped.is_none();
+
+    // The result replaces the progress notification; without one, close it
+    let progress_id = progress.and_then(|progress| progress.finish(!notify || options.print_only));
+
+    if notify {
+        let notify_config = config.notification.unwrap_or_default();
+        let status_str = if status.core_dumped() {
+            "crashed (core dumped)"
+        } else if warnings.is_some() {
+            "succeeded with warnings"
+        } else if success {
             "succeeded"
         } else {
             "failed"
         };
+        let mut status_str = match exit_messages.and_then(|m| m.label(exit_code)) {
+            Some(label) => format!("{} ({})", status_str, label),
+            None => status_str.to_string(),
+        };
+        if let Some(min) = min_expected {
+            status_str.push_str(&format!(
+                ", but finished unexpectedly fast (expected at least {})",
+                format_duration(min)
+            ));
+        }
 
-        let body = format!(
+        let mut body = format!(
             "{}\nDuration: {}\nExit code: {}",
             status_str,
-            format_duration(duration_secs),
+            config::format_duration(duration, notify_config.duration_precision),
             exit_code
         );
+        if let Some(cpu) = cpu_time {
+            body.push_str(&format!("\nCPU time: {}", format_cpu_time(cpu)));
+        }
+        if let Some(kib) = peak_rss_kib {
+            body.push_str(&format!("\nPeak memory: {}", format_rss(kib)));
+        }
+        if let Some(output) = captured
+            .filter(|_| options.capture_output)
+            .map(|c| c.text())
+            .filter(|t| !t.is_empty())
+        {
+            body.push_str(&format!("\n\n{}", output));
+        }
+        if let Some(warnings) = &warnings {
+            body.push_str(&format!("\n\nstderr:\n{}", warnings));
+        }
 
-        Notification::new()
-            .summary(&format!("Command completed: {}", command[0]))
-            .body(&body)
-            .icon("dialog-information")
-            .appname("notify-done")
-            .show()
-            .ok(); // Don't fail if notification fails
+        let mut notification = Notification::new();
+        notification
+            .summary(&format!(
+                "Command completed{}: {}",
+                if warnings.is_some() {
+                    " with warnings"
+                } else {
+                    ""
+                },
+                name.as_deref().unwrap_or(&command[0])
+            ))
+            .body(&body);
+        // An unexpectedly fast success, or one with warnings, is shown
+        // like a failure
+        let urgency = apply_notification_config(
+            &mut notification,
+            &notify_config,
+            overrides,
+            Some(program),
+            success && min_expected.is_none() && warnings.is_none(),
+        );
+        if warnings.is_some() && overrides.icon.is_none() && notify_config.icon.is_none() {
+            notification.icon("dialog-warning");
+        }
+
+        if let Some(id) = progress_id {
+            notification.id(id);
+        }
+
+        if options.print_only {
+            if let Err(e) = print_notification(&mut io::stdout(), &notification) {
+                eprintln!("nd: failed to print notification: {}", e);
+            }
+        } else {
+            // Don't fail if notification fails
+            show_notification(
+                &mut notification,
+                name.as_deref(),
+                &notify_config,
+                urgency,
+                overrides.local_notify,
+            );
+        }
     }
 
+    // A signal nd got while the command ran ends it now, as it would have
+    signals.finish();
+
     // Exit with the same code as the command
     std::process::exit(exit_code);
 }
 
+/// The command to spawn: `command` itself, or with `shell` its one-string
+/// script run through `$SHELL -c` (default /bin/sh)
+fn child_command(command: &[String], shell: bool) -> Command {
+    if shell {
+        let shell = std::env::var_os("SHELL")
+            .filter(|shell| !shell.is_empty())
+            .unwrap_or_else(|| "/bin/sh".into());
+        let mut cmd = Command::new(shell);
+        cmd.arg("-c").arg(&command[0]);
+        cmd
+    } else {
+        let mut cmd = Command::new(&command[0]);
+        cmd.args(&command[1..]);
+        cmd
+    }
+}
+
+/// File name of the program run; a script is matched by its first program
+fn program_name(command: &[String], shell: bool) -> &str {
+    let program_path = if shell {
+        command[0].split_whitespace().next().unwrap_or_default()
+    } else {
+        &command[0]
+    };
+    Path::new(program_path)
+        .file_name()
+        .and_then(|f| f.to_str())
+        .unwrap_or(program_path)
+}
+
+/// Why `nd run` sent no notification, shown with `--verbose`
+enum SkipReason {
+    Grouped(String),
+    BelowMinDuration {
+        duration: Duration,
+        min_duration: Duration,
+    },
+    BelowThreshold {
+        duration_secs: u64,
+        threshold: u64,
+    },
+    TerminalFocused,
+    OutsideCwdPrefixes(String),
+    AlreadyNotified,
+}
+
+impl std::fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SkipReason::Grouped(group) => {
+                write!(f, "part of group {}, which notifies once idle", group)
+            }
+            SkipReason::BelowMinDuration {
+                duration,
+                min_duration,
+            } => write!(
+                f,
+                "duration {}ms < min_duration_ms {}",
+                duration.as_millis(),
+                min_duration.as_millis()
+            ),
+            SkipReason::BelowThreshold {
+                duration_secs,
+                threshold,
+            } => write!(
+                f,
+                "duration {} < threshold {}",
+                format_duration(*duration_secs),
+                format_duration(*threshold)
+            ),
+            SkipReason::TerminalFocused => write!(f, "terminal is focused (--skip-if-focused)"),
+            SkipReason::OutsideCwdPrefixes(cwd) => {
+                write!(f, "{} is outside notify_cwd_prefixes", cwd)
+            }
+            SkipReason::AlreadyNotified => write!(f, "the daemon already notified for it"),
+        }
+    }
+}
+
+/// Claim the notification for the command's exit, so the daemon doesn't
+/// send one too; false if it got there first
+///
+/// Without a running daemon the dedup directory doesn't exist, and there's
+/// nothing to claim against.
+fn claim_notification(pid: u32, comm: Option<&str>) -> bool {
+    let Some(comm) = comm else {
+        return true;
+    };
+    let uid = unsafe { libc::getuid() };
+    dedup::claim(uid, pid, comm, history::now_epoch_secs()).unwrap_or(true)
+}
+
+/// The working directory, if `prefixes` are set and it lies under none
+/// of them
+fn outside_cwd_prefixes(prefixes: &[String]) -> Option<String> {
+    if prefixes.is_empty() {
+        return None;
+    }
+    let cwd = std::env::current_dir().ok()?;
+    let cwd = cwd.to_string_lossy();
+    (!is_under_any(&cwd, prefixes)).then(|| cwd.into_owned())
+}
+
+/// Record a finished group member; the last one running starts a
+/// background `nd group-flush` that sends the summary once the group idles
+fn finish_group_member(group: &str, member: GroupMember, config: &UserConfig) {
+    let generation = match group::finish(group, member) {
+        Ok(Some(generation)) => generation,
+        Ok(None) => return,
+        Err(e) => {
+            eprintln!("nd: failed to record group {}: {:#}", group, e);
+            return;
+        }
+    };
+
+    let idle_secs = config.group_idle_seconds.unwrap_or(DEFAULT_GROUP_IDLE_SECS);
+    let spawned = std::env::current_exe().and_then(|exe| {
+        Command::new(exe)
+            .args(["group-flush", &generation.to_string(), "--", group])
+            .env(GROUP_IDLE_ENV, idle_secs.to_string())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            // Outlive the terminal's Ctrl-C
+            .process_group(0)
+            .spawn()
+    });
+    if let Err(e) = spawned {
+        eprintln!(
+            "nd: failed to schedule the summary of group {}: {}",
+            group, e
+        );
+    }
+}
+
+/// Wait for the group to stay idle, then send its summary unless another
+/// member joined or finished meanwhile
+fn cmd_group_flush(group: &str, generation: u64) -> Result<()> {
+    let idle_secs = std::env::var(GROUP_IDLE_ENV)
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(DEFAULT_GROUP_IDLE_SECS);
+    std::thread::sleep(Duration::from_secs(idle_secs));
+
+    let Some(summary) = group::take_if_idle(group, generation)? else {
+        return Ok(());
+    };
+    if summary.members.is_empty() {
+        return Ok(());
+    }
+
+    let notify_config = load_layered_config_or_default()
+        .notification
+        .unwrap_or_default();
+    let mut notification = Notification::new();
+    notification
+        .summary(&format!("Group completed: {}", group))
+        .body(&summary.body(format_duration));
+    let urgency = apply_notification_config(
+        &mut notification,
+        &notify_config,
+        &NotifyOverrides::default(),
+        None,
+        summary.success(),
+    );
+    show_notification(&mut notification, None, &notify_config, urgency, false);
+    Ok(())
+}
+
+fn cmd_watch_pid(
+    threshold: Option<u64>,
+    name: Option<String>,
+    overrides: &NotifyOverrides,
+    pid: i32,
+) -> Result<Output> {
+    let config = load_layered_config_or_default();
+    let threshold = resolve_threshold(threshold, &config);
+
+    let process = WatchedProcess::find(pid)?;
+    let label = name.as_deref().unwrap_or(&process.comm).to_string();
+
+    eprintln!("Waiting for {} (pid {}) to exit...", label, pid);
+    process.wait()?;
+
+    let elapsed = process.elapsed()?;
+    let duration_secs = elapsed.as_secs();
+    let notified = duration_secs >= threshold;
+    if notified {
+        // The exit code of a process we didn't start is not available
+        let notify_config = config.notification.unwrap_or_default();
+        let mut notification = Notification::new();
+        notification
+            .summary(&format!("Process exited: {}", label))
+            .body(&format!(
+                "PID: {}\nDuration: {}",
+                pid,
+                config::format_duration(elapsed, notify_config.duration_precision)
+            ));
+        let urgency = apply_notification_config(
+            &mut notification,
+            &notify_config,
+            overrides,
+            Some(&process.comm),
+            true,
+        );
+
+        show_notification(
+            &mut notification,
+            name.as_deref(),
+            &notify_config,
+            urgency,
+            overrides.local_notify,
+        );
+    }
+
+    Ok(Output::WatchPid {
+        pid,
+        comm: process.comm,
+        duration_secs,
+        notified,
+    })
+}
+
+fn cmd_watch_unit(
+    threshold: Option<u64>,
+    name: Option<String>,
+    overrides: &NotifyOverrides,
+    unit: &str,
+    user: bool,
+) -> Result<Output> {
+    let config = load_layered_config_or_default();
+    let threshold = resolve_threshold(threshold, &config);
+
+    let unit = WatchedUnit::find(unit, user)?;
+    let label = name.as_deref().unwrap_or(&unit.name).to_string();
+
+    eprintln!("Waiting for {} to finish...", label);
+    let outcome = unit.wait()?;
+
+    let duration_secs = outcome.duration.as_secs();
+    let notified = duration_secs >= threshold;
+    if notified {
+        let notify_config = config.notification.unwrap_or_default();
+        let mut body = format!(
+            "{} ({})\nDuration: {}",
+            if outcome.success {
+                "succeeded"
+            } else {
+                "failed"
+            },
+            outcome.result,
+            config::format_duration(outcome.duration, notify_config.duration_precision)
+        );
+        match (outcome.result.as_str(), outcome.exit_status) {
+            ("signal" | "core-dump", Some(signal)) => {
+                body.push_str(&format!("\nSignal: {}", signal))
+            }
+            (_, Some(code)) => body.push_str(&format!("\nExit code: {}", code)),
+            (_, None) => {}
+        }
+
+        let mut notification = Notification::new();
+        notification
+            .summary(&format!("Unit finished: {}", label))
+            .body(&body);
+        let urgency = apply_notification_config(
+            &mut notification,
+            &notify_config,
+            overrides,
+            Some(&unit.name),
+            outcome.success,
+        );
+
+        show_notification(
+            &mut notification,
+            name.as_deref(),
+            &notify_config,
+            urgency,
+            overrides.local_notify,
+        );
+    }
+
+    Ok(Output::WatchUnit {
+        unit: unit.name,
+        result: outcome.result,
+        success: outcome.success,
+        duration_secs,
+        notified,
+    })
+}
+
 fn cmd_watch() -> Result<()> {
     println!("Watching for events... (Ctrl+C to stop)");
     println!("(Note: This requires the daemon to be running with debug enabled)");
@@ -301,28 +1074,660 @@ fn user_config_path() -> Result<PathBuf> {
     Ok(config_dir.join("notify-done").join("config.toml"))
 }
 
+fn write_default_config(path: &Path) -> Result<()> {
+    std::fs::create_dir_all(path.parent().unwrap())?;
+
+    let default_config = UserConfig {
+        threshold_seconds: Some(10),
+        threshold_mode: None,
+        min_duration_ms: None,
+        ignore_patterns: vec![],
+        always_notify: vec![],
+        notify_cwd_prefixes: vec![],
+        disabled: false,
+        exit_messages: HashMap::new(),
+        min_expected_seconds: HashMap::new(),
+        notify_failures_regardless: None,
+        dedup_window_seconds: None,
+        notification: None,
+        group_idle_seconds: None,
+        skip_if_focused: None,
+        history: HistoryConfig::default(),
+    };
+
+    let content = toml::to_string_pretty(&default_config)?;
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
+/// Editor command line from $VISUAL or $EDITOR, falling back to
+/// sensible-editor (Debian) and then vi
+fn editor_command() -> Vec<String> {
+    for var in ["VISUAL", "EDITOR"] {
+        if let Ok(value) = std::env::var(var) {
+            let words: Vec<String> = value.split_whitespace().map(String::from).collect();
+            if !words.is_empty() {
+                return words;
+            }
+        }
+    }
+
+    let has_sensible_editor = Command::new("sensible-editor")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok();
+    let fallback = if has_sensible_editor {
+        "sensible-editor"
+    } else {
+        "vi"
+    };
+    vec![fallback.to_string()]
+}
+
+/// Threshold from the flag, then $ND_THRESHOLD, then the config
+fn resolve_threshold(flag: Option<u64>, config: &UserConfig) -> u64 {
+    flag.or_else(|| {
+        std::env::var(THRESHOLD_ENV)
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+    })
+    .or(config.threshold_seconds)
+    .unwrap_or(DEFAULT_THRESHOLD_SECS)
+}
+
+/// Every value `nd` runs with and where it comes from: a config file, the
+/// environment, or the built-in default
+fn cmd_config_effective() -> Result<Output> {
+    let (merged, sources) = load_layered_table()?;
+    let config: UserConfig = toml::Value::Table(merged)
+        .try_into()
+        .context("Invalid merged config")?;
+
+    // Going through UserConfig fills in the defaults of unset fields
+    let mut values = Vec::new();
+    for_each_value(&toml::Value::try_from(&config)?, "", &mut |key, value| {
+        let key = key.trim_start_matches('.').to_string();
+        let source = match sources.get(&key) {
+            Some(path) => path.display().to_string(),
+            None => "default".to_string(),
+        };
+        values.push(EffectiveValue {
+            key,
+            value: value.clone(),
+            source,
+        });
+    });
+
+    // The threshold has its own fallbacks outside the config files
+    let env_threshold = std::env::var(THRESHOLD_ENV)
+        .ok()
+        .and_then(|v| v.trim().parse::<u64>().ok());
+    let threshold = match env_threshold {
+        Some(secs) => Some((secs, format!("${}", THRESHOLD_ENV))),
+        None if config.threshold_seconds.is_none() => {
+            Some((DEFAULT_THRESHOLD_SECS, "default".to_string()))
+        }
+        None => None,
+    };
+    if let Some((secs, source)) = threshold {
+        values.retain(|v| v.key != "threshold_seconds");
+        values.push(EffectiveValue {
+            key: "threshold_seconds".to_string(),
+            value: toml::Value::Integer(secs as i64),
+            source,
+        });
+    }
+    values.sort_by(|a, b| a.key.cmp(&b.key));
+
+    Ok(Output::ConfigEffective { values })
+}
+
+/// The config `nd` runs with: the system config, overridden field by field
+/// by the user config. Tables such as `[notification]` merge key by key;
+/// other values, lists included, replace the lower layer's.
+fn load_layered_config() -> Result<UserConfig> {
+    let (merged, _) = load_layered_table()?;
+    toml::Value::Table(merged)
+        .try_into()
+        .context("Invalid merged config")
+}
+
+/// Names of the byte-typed hints in `config`, which desktop notifications
+/// go without: notify-rust can't send them, and an int in their place is
+/// the wrong D-Bus type for servers expecting `y` (notify-send, used for
+/// commands, ssh and by the daemon, sends them)
+fn byte_hint_names(config: &NotificationConfig) -> Vec<&str> {
+    config
+        .hints
+        .iter()
+        .filter(|(_, value)| matches!(value, HintValue::Byte(_)))
+        .map(|(name, _)| name.as_str())
+        .collect()
+}
+
+/// The merged config files, and for each value (by dotted key) the file
+/// that set it
+fn load_layered_table() -> Result<(toml::Table, BTreeMap<String, PathBuf>)> {
+    merge_layers(&config_layers())
+}
+
+/// The config files, lowest layer first: the system config, then the user's
+fn config_layers() -> Vec<PathBuf> {
+    let mut layers = vec![PathBuf::from(SYSTEM_CONFIG_PATH)];
+    if let Ok(path) = user_config_path() {
+        layers.push(path);
+    }
+    layers
+}
+
+/// Merge the config files in `layers`, each overriding the ones before it,
+/// skipping those that can't be read
+fn merge_layers(layers: &[PathBuf]) -> Result<(toml::Table, BTreeMap<String, PathBuf>)> {
+    let mut merged = toml::Table::new();
+    let mut sources = BTreeMap::new();
+    for path in layers {
+        // The system config may not be readable by users
+        let Ok(content) = std::fs::read_to_string(path) else {
+            continue;
+        };
+        let layer = parse_config(path, &content)?;
+        merge_tables(&mut merged, layer, "", path, &mut sources);
+    }
+    Ok((merged, sources))
+}
+
+/// The layered config, or the defaults with a warning if it's invalid, for
+/// commands that should still notify
+fn load_layered_config_or_default() -> UserConfig {
+    load_layered_config().unwrap_or_else(|e| {
+        eprintln!("nd: ignoring invalid config: {:#}", e);
+        UserConfig::default()
+    })
+}
+
+/// Merge `layer` (read from `source`) into `base`, recursing into tables
+/// present in both, and record `source` for every value it sets
+fn merge_tables(
+    base: &mut toml::Table,
+    layer: toml::Table,
+    prefix: &str,
+    source: &Path,
+    sources: &mut BTreeMap<String, PathBuf>,
+) {
+    for (key, value) in layer {
+        let path = format!("{}{}", prefix, key);
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(layer)) => {
+                merge_tables(base, layer, &format!("{}.", path), source, sources)
+            }
+            (_, value) => {
+                // Whatever the lower layer had under this key is gone
+                let nested = format!("{}.", path);
+                sources.retain(|key, _| *key != path && !key.starts_with(&nested));
+                for_each_value(&value, &path, &mut |key, _| {
+                    sources.insert(key, source.to_path_buf());
+                });
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Call `f` with the dotted key of every non-table value in `value`
+fn for_each_value(value: &toml::Value, path: &str, f: &mut impl FnMut(String, &toml::Value)) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                for_each_value(value, &format!("{}.{}", path, key), f);
+            }
+        }
+        value => f(path.to_string(), value),
+    }
+}
+
+fn load_config(path: &Path) -> Result<UserConfig> {
+    if path.exists() {
+        let content = std::fs::read_to_string(path)?;
+        parse_config(path, &content)
+    } else {
+        Ok(UserConfig::default())
+    }
+}
+
 fn load_or_create_config(path: &PathBuf) -> Result<UserConfig> {
     if path.exists() {
         let content = std::fs::read_to_string(path)?;
-        Ok(toml::from_str(&content)?)
+        parse_config(path, &content)
     } else {
         std::fs::create_dir_all(path.parent().unwrap())?;
         Ok(UserConfig::default())
     }
 }
 
+/// Parse a config file, pointing at the offending line on error
+fn parse_config<T: DeserializeOwned>(path: &Path, content: &str) -> Result<T> {
+    toml::from_str(content).map_err(|e| {
+        anyhow::anyhow!(
+            "Invalid config {}: {}",
+            path.display(),
+            format_parse_error(content, e.span(), e.message())
+        )
+    })
+}
+
 fn save_config(path: &PathBuf, config: &UserConfig) -> Result<()> {
     let content = toml::to_string_pretty(config)?;
     std::fs::write(path, content)?;
     Ok(())
 }
 
-fn format_duration(secs: u64) -> String {
-    if secs < 60 {
-        format!("{}s", secs)
-    } else if secs < 3600 {
-        format!("{}m {}s", secs / 60, secs % 60)
+/// Resource usage of all reaped children
+fn children_usage() -> Option<libc::rusage> {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrusage(libc::RUSAGE_CHILDREN, &mut usage) } != 0 {
+        return None;
+    }
+    Some(usage)
+}
+
+/// Peak resident set size in KiB
+///
+/// `ru_maxrss` is the largest RSS of any single waited-for descendant, not
+/// their sum. nd only runs the one command, so this is the command itself
+/// or whichever of its children peaked highest.
+fn peak_rss_kib(usage: &libc::rusage) -> Option<u64> {
+    u64::try_from(usage.ru_maxrss).ok().filter(|&kib| kib > 0)
+}
+
+/// User + system CPU time, summed over all waited-for descendants
+fn cpu_time(usage: &libc::rusage) -> Duration {
+    let timeval = |tv: libc::timeval| {
+        Duration::from_secs(tv.tv_sec.max(0) as u64)
+            + Duration::from_micros(tv.tv_usec.max(0) as u64)
+    };
+    timeval(usage.ru_utime) + timeval(usage.ru_stime)
+}
+
+fn format_rss(kib: u64) -> String {
+    format!("{:.1} MiB", kib as f64 / 1024.0)
+}
+
+fn format_cpu_time(cpu: Duration) -> String {
+    if cpu.as_secs() < 60 {
+        format!("{:.1}s", cpu.as_secs_f64())
     } else {
-        format!("{}h {}m {}s", secs / 3600, (secs % 3600) / 60, secs % 60)
+        format_duration(cpu.as_secs())
+    }
+}
+
+/// Send a notification through each configured backend, warning about the
+/// ones that failed; no backends means the notification command or ssh
+/// target if one is set, or else desktop, with a stderr fallback
+fn show_notification(
+    notification: &mut Notification,
+    name: Option<&str>,
+    config: &NotificationConfig,
+    urgency: Urgency,
+    local_notify: bool,
+) {
+    if config.backends.is_empty() {
+        let (backend, result) = if config.has_command() {
+            (
+                Backend::Command,
+                show_command(notification, config, urgency),
+            )
+        } else if !local_notify && in_ssh_session() {
+            // The desktop would be the remote host's, which nobody watches
+            show_stderr(notification);
+            return;
+        } else {
+            (Backend::Desktop, show_desktop(notification, name, config))
+        };
+        if let Err(e) = result {
+            eprintln!(
+                "nd: {} notification failed ({}), printing instead",
+                backend.as_str(),
+                e
+            );
+            show_stderr(notification);
+        }
+        return;
+    }
+
+    for backend in &config.backends {
+        let result = match backend {
+            Backend::Desktop => show_desktop(notification, name, config),
+            Backend::Command => show_command(notification, config, urgency),
+            Backend::Stderr => {
+                show_stderr(notification);
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            eprintln!("nd: {} notification failed: {}", backend.as_str(), e);
+        }
+    }
+}
+
+/// Whether nd runs in an SSH login, going by what sshd exports
+fn in_ssh_session() -> bool {
+    ["SSH_CONNECTION", "SSH_TTY"]
+        .iter()
+        .any(|var| std::env::var_os(var).is_some_and(|value| !value.is_empty()))
+}
+
+/// Show a desktop notification, replacing the previous one for the same
+/// task name
+fn show_desktop(
+    notification: &mut Notification,
+    name: Option<&str>,
+    config: &NotificationConfig,
+) -> Result<()> {
+    let skipped = byte_hint_names(config);
+    if !skipped.is_empty() {
+        eprintln!(
+            "nd: desktop notifications can't carry byte hints, leaving out notification.hints.{}",
+            skipped.join(", notification.hints.")
+        );
+    }
+    match name {
+        Some(name) => NotificationIds::load().show(name, notification),
+        None => {
+            notification.show()?;
+            Ok(())
+        }
+    }
+}
+
+/// Run the configured notification command, or notify-send over ssh, with
+/// the summary and body substituted into its arguments
+fn show_command(
+    notification: &Notification,
+    config: &NotificationConfig,
+    urgency: Urgency,
+) -> Result<()> {
+    let argv = config
+        .command_argv(
+            &notification.summary,
+            &notification.body,
+            urgency,
+            &config.app_name,
+        )
+        .context("neither notification.command nor notification.ssh_target is set")?;
+    let status = Command::new(&argv[0])
+        .args(&argv[1..])
+        .stdin(Stdio::null())
+        .status()
+        .with_context(|| format!("Failed to run {}", argv[0]))?;
+    if !status.success() {
+        anyhow::bail!("{} exited with {}", argv[0], status);
+    }
+    Ok(())
+}
+
+/// Write the summary and body to `out` (stdout), for `--print-only`
+fn print_notification(out: &mut impl Write, notification: &Notification) -> io::Result<()> {
+    writeln!(out, "{}", notification.summary)?;
+    writeln!(out, "{}", notification.body)
+}
+
+fn show_stderr(notification: &Notification) {
+    eprintln!("nd: {}", notification.summary);
+    for line in notification.body.lines() {
+        eprintln!("nd:   {}", line);
+    }
+}
+
+/// Apply icon, app identity, urgency, expiry and the transient hint for a
+/// success or failure of `command`, returning the urgency; command-line
+/// overrides win over the config
+fn apply_notification_config(
+    notification: &mut Notification,
+    config: &NotificationConfig,
+    overrides: &NotifyOverrides,
+    command: Option<&str>,
+    success: bool,
+) -> Urgency {
+    let icon = overrides
+        .icon
+        .as_deref()
+        .or(config.icon.as_deref())
+        .unwrap_or("dialog-information");
+    notification.icon(&normalize_icon(icon));
+    notification.appname(&config.app_name);
+    if let Some(entry) = &config.desktop_entry {
+        notification.hint(Hint::DesktopEntry(entry.clone()));
+    }
+    for (name, value) in &config.hints {
+        notification.hint(match value {
+            HintValue::String(value) => Hint::Custom(name.clone(), value.clone()),
+            HintValue::Int(value) => Hint::CustomInt(name.clone(), *value),
+            // Left out with a warning when shown, see byte_hint_names
+            HintValue::Byte(_) => continue,
+        });
+    }
+
+    // Overriding both outcomes keeps the config's expiry rules, so a
+    // critical override still persists unless a timeout was set
+    let config = match overrides.urgency {
+        Some(urgency) => NotificationConfig {
+            urgency,
+            urgency_failure: urgency,
+            ..config.clone()
+        },
+        None => config.clone(),
+    };
+    let (urgency, timeout_ms) = config.for_command(command, success);
+    notification.urgency(notify_urgency(urgency));
+    if let Some(ms) = timeout_ms {
+        notification.timeout(notify_timeout(ms));
+    }
+    if config.transient {
+        notification.hint(Hint::Transient(true));
+    }
+    urgency
+}
+
+fn notify_urgency(urgency: Urgency) -> notify_rust::Urgency {
+    match urgency {
+        Urgency::Low => notify_rust::Urgency::Low,
+        Urgency::Normal => notify_rust::Urgency::Normal,
+        Urgency::Critical => notify_rust::Urgency::Critical,
+    }
+}
+
+/// Expiry in ms, 0 = never
+fn notify_timeout(ms: u32) -> Timeout {
+    match ms {
+        0 => Timeout::Never,
+        ms => Timeout::Milliseconds(ms),
+    }
+}
+
+fn format_duration(secs: u64) -> String {
+    config::format_duration(Duration::from_secs(secs), DurationPrecision::Seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peak_rss_covers_a_memory_allocating_child() {
+        // The shell holds 8 MB in a variable
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg("x=$(head -c 8000000 /dev/zero | tr '\\0' a); test ${#x} -gt 0")
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let kib = children_usage().as_ref().and_then(peak_rss_kib).unwrap();
+        assert!(kib > 8000, "peak RSS {} KiB", kib);
+    }
+
+    #[test]
+    fn rss_is_shown_in_mib() {
+        assert_eq!(format_rss(1536), "1.5 MiB");
+    }
+
+    #[test]
+    fn cpu_time_sums_user_and_system() {
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        usage.ru_utime.tv_sec = 1;
+        usage.ru_utime.tv_usec = 250_000;
+        usage.ru_stime.tv_usec = 500_000;
+        assert_eq!(cpu_time(&usage), Duration::from_millis(1750));
+    }
+
+    #[test]
+    fn cpu_time_switches_to_minutes_past_a_minute() {
+        assert_eq!(format_cpu_time(Duration::from_millis(1750)), "1.8s");
+        assert_eq!(format_cpu_time(Duration::from_secs(75)), "1m 15s");
+    }
+
+    #[test]
+    fn print_only_writes_summary_then_body() {
+        let mut notification = Notification::new();
+        notification
+            .summary("✓ make")
+            .body("Completed in 1m 5s\nexit 0");
+        let mut out = Vec::new();
+        print_notification(&mut out, &notification).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "✓ make\nCompleted in 1m 5s\nexit 0\n"
+        );
+    }
+
+    #[test]
+    fn shell_runs_the_whole_pipeline() {
+        let command = vec!["echo done | tr a-z A-Z && exit 3".to_string()];
+        let output = child_command(&command, true).output().unwrap();
+        assert_eq!(output.stdout, b"DONE\n");
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(program_name(&command, true), "echo");
+    }
+
+    #[test]
+    fn without_shell_arguments_are_passed_verbatim() {
+        let command: Vec<String> = ["/bin/echo", "a | b", "&&"].map(String::from).to_vec();
+        let output = child_command(&command, false).output().unwrap();
+        assert_eq!(output.stdout, b"a | b &&\n");
+        assert_eq!(program_name(&command, false), "echo");
+    }
+
+    #[test]
+    fn only_byte_hints_are_left_out_of_desktop_notifications() {
+        let config = NotificationConfig {
+            hints: BTreeMap::from([
+                ("urgency".to_string(), HintValue::Byte(2)),
+                ("value".to_string(), HintValue::Int(2)),
+                (
+                    "x-canonical-private-synchronous".to_string(),
+                    HintValue::Byte(1),
+                ),
+                (
+                    "category".to_string(),
+                    HintValue::String("build".to_string()),
+                ),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            byte_hint_names(&config),
+            ["urgency", "x-canonical-private-synchronous"]
+        );
+        assert!(byte_hint_names(&NotificationConfig::default()).is_empty());
+    }
+
+    #[test]
+    fn skip_reasons_read_as_sentences() {
+        let reasons = [
+            (
+                SkipReason::Grouped("ci".to_string()),
+                "part of group ci, which notifies once idle",
+            ),
+            (
+                SkipReason::BelowMinDuration {
+                    duration: Duration::from_millis(40),
+                    min_duration: Duration::from_millis(500),
+                },
+                "duration 40ms < min_duration_ms 500",
+            ),
+            (
+                SkipReason::BelowThreshold {
+                    duration_secs: 3,
+                    threshold: 90,
+                },
+                "duration 3s < threshold 1m 30s",
+            ),
+            (
+                SkipReason::TerminalFocused,
+                "terminal is focused (--skip-if-focused)",
+            ),
+            (
+                SkipReason::OutsideCwdPrefixes("/tmp".to_string()),
+                "/tmp is outside notify_cwd_prefixes",
+            ),
+            (
+                SkipReason::AlreadyNotified,
+                "the daemon already notified for it",
+            ),
+        ];
+        for (reason, expected) in reasons {
+            assert_eq!(reason.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn zero_timeout_never_expires() {
+        assert_eq!(notify_timeout(0), Timeout::Never);
+        assert_eq!(notify_timeout(5000), Timeout::Milliseconds(5000));
+    }
+
+    #[test]
+    fn config_layers_are_system_then_user() {
+        let layers = config_layers();
+        assert_eq!(layers[0], Path::new(SYSTEM_CONFIG_PATH));
+        if let Ok(user) = user_config_path() {
+            assert_eq!(layers[1..], [user]);
+        }
+    }
+
+    #[test]
+    fn user_config_overrides_the_system_config() {
+        let dir = std::env::temp_dir().join(format!("nd-layers-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let system = dir.join("system.toml");
+        let user = dir.join("user.toml");
+        std::fs::write(
+            &system,
+            "threshold_seconds = 10\n[notification]\nurgency = \"low\"\ntimeout_ms = 5000\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &user,
+            "threshold_seconds = 20\n[notification]\nurgency = \"critical\"\n",
+        )
+        .unwrap();
+
+        let (merged, sources) = merge_layers(&[system.clone(), user.clone()]).unwrap();
+        assert_eq!(merged["threshold_seconds"].as_integer(), Some(20));
+        let notification = merged["notification"].as_table().unwrap();
+        assert_eq!(notification["urgency"].as_str(), Some("critical"));
+        // Keys the user config leaves out keep the system value
+        assert_eq!(notification["timeout_ms"].as_integer(), Some(5000));
+        assert_eq!(sources["threshold_seconds"], user);
+        assert_eq!(sources["notification.urgency"], user);
+        assert_eq!(sources["notification.timeout_ms"], system);
+
+        // An unreadable layer, like a system config users can't read, is skipped
+        let (merged, sources) = merge_layers(&[dir.join("missing.toml"), user.clone()]).unwrap();
+        assert_eq!(merged["threshold_seconds"].as_integer(), Some(20));
+        assert!(sources.values().all(|source| *source == user));
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
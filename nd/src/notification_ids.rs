@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use notify_rust::Notification;
+
+/// Remembers the desktop notification ID last shown for each task name, so a
+/// repeated run of the same named task replaces its previous notification
+/// instead of stacking a new one.
+pub struct NotificationIds {
+    path: PathBuf,
+    ids: HashMap<String, u32>,
+}
+
+impl NotificationIds {
+    /// Load the ID map from the runtime directory.
+    ///
+    /// The runtime directory is cleared when the user logs out, which is also
+    /// when the notification server forgets its IDs, so stale entries never
+    /// outlive the session they belong to.
+    pub fn load() -> Self {
+        let path = dirs::runtime_dir()
+            .or_else(dirs::data_dir)
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("notify-done")
+            .join("notification-ids.json");
+
+        let ids = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+
+        Self { path, ids }
+    }
+
+    /// Show a notification, replacing the previous one shown under `name`
+    pub fn show(&mut self, name: &str, notification: &mut Notification) -> Result<()> {
+        let handle = match self.ids.get(name) {
+            Some(&id) => {
+                // If the old notification was already dismissed the server
+                // either reuses the ID or allocates a new one; if it rejects
+                // the ID outright, fall back to a fresh notification.
+                match notification.id(id).show() {
+                    Ok(handle) => handle,
+                    Err(_) => notification.id(0).show()?,
+                }
+            }
+            None => notification.show()?,
+        };
+
+        self.ids.insert(name.to_string(), handle.id());
+        self.save()
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&self.path, serde_json::to_string(&self.ids)?)?;
+        Ok(())
+    }
+}
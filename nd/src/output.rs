@@ -0,0 +1,477 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use notify_done_common::health::{DaemonHealth, RecentLogLine};
+use serde::Serialize;
+
+use crate::doctor::{Check, CheckStatus};
+use crate::format_duration;
+use crate::history::{self, HistoryEntry};
+
+/// What a subcommand produced, printed as text or, with `--json`, as a
+/// single JSON object on stdout
+#[derive(Debug, Serialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Output {
+    Status {
+        running: bool,
+        /// systemd's "Active:" line
+        active: Option<String>,
+        main_pid: Option<u32>,
+        /// Uptime and counters from the daemon's health snapshot
+        health: Option<DaemonHealth>,
+    },
+    List {
+        processes: Vec<ListedProcess>,
+    },
+    History {
+        since: Option<u64>,
+        entries: Vec<HistoryEntry>,
+        /// Entries always carry their session tag in JSON
+        #[serde(skip)]
+        show_session: bool,
+    },
+    Last {
+        /// None if the history is empty
+        entry: Option<HistoryEntry>,
+    },
+    Why {
+        /// None if no command in the history failed
+        entry: Option<HistoryEntry>,
+    },
+    HistoryClear {
+        cleared: usize,
+        aborted: bool,
+    },
+    ConfigShow {
+        user_path: PathBuf,
+        /// Raw TOML, if the file exists
+        user_config: Option<String>,
+        system_path: PathBuf,
+        system_config: Option<String>,
+    },
+    ConfigEffective {
+        /// Sorted by key
+        values: Vec<EffectiveValue>,
+    },
+    ConfigInit {
+        path: PathBuf,
+        created: bool,
+    },
+    ConfigEdit {
+        path: PathBuf,
+    },
+    ConfigUpdate {
+        path: PathBuf,
+        changed: bool,
+        message: String,
+    },
+    Test {
+        sent: bool,
+    },
+    Doctor {
+        checks: Vec<Check>,
+    },
+    DebugLog {
+        /// None if the daemon isn't keeping a recent log
+        lines: Option<Vec<RecentLogLine>>,
+    },
+    WatchPid {
+        pid: i32,
+        comm: String,
+        duration_secs: u64,
+        notified: bool,
+    },
+    WatchUnit {
+        unit: String,
+        /// The service's Result, or the unit's final state
+        result: String,
+        success: bool,
+        duration_secs: u64,
+        notified: bool,
+    },
+}
+
+/// A row of `nd list`
+#[derive(Debug, Serialize)]
+pub struct ListedProcess {
+    pub pid: u32,
+    /// Elapsed time as reported by ps ([[DD-]hh:]mm:ss)
+    pub elapsed: String,
+    pub comm: String,
+}
+
+/// Widest `key = value` the sources of `nd config effective` align after
+const EFFECTIVE_ALIGN_MAX: usize = 48;
+
+/// A value of `nd config effective`
+#[derive(Debug, Serialize)]
+pub struct EffectiveValue {
+    /// Dotted key, e.g. "notification.urgency"
+    pub key: String,
+    pub value: toml::Value,
+    /// Config file path, "$ND_THRESHOLD" or "default"
+    pub source: String,
+}
+
+impl Output {
+    pub fn render(&self, json: bool) -> Result<()> {
+        if json {
+            println!("{}", serde_json::to_string(self)?);
+            return Ok(());
+        }
+
+        match self {
+            Output::Status {
+                running,
+                active,
+                main_pid,
+                health,
+            } => {
+                if *running {
+                    println!("Daemon status: running");
+                    if let Some(active) = active {
+                        println!("Active: {}", active);
+                    }
+                    if let Some(pid) = main_pid {
+                        println!("Main PID: {}", pid);
+                    }
+                    if let Some(h) = health {
+                        let now = history::now_epoch_secs();
+                        println!(
+                            "Version: {}, up {} (updated {} ago)",
+                            h.version,
+                            format_duration(now.saturating_sub(h.started_at)),
+                            format_duration(now.saturating_sub(h.updated_at))
+                        );
+                        println!(
+                            "Tracking {} processes, {} in history",
+                            h.active_processes, h.history_len
+                        );
+                        println!(
+                            "Events: {} exec, {} exit, {} malformed",
+                            h.exec_events, h.exit_events, h.malformed_events
+                        );
+                        println!(
+                            "Notifications: {} sent, {} failed ({} dropped after retrying), {} pending",
+                            h.notifications_sent,
+                            h.notifications_failed,
+                            h.notifications_dropped,
+                            h.pending_retries
+                        );
+                    }
+                } else {
+                    println!("Daemon status: not running");
+                    println!("\nTo start the daemon:");
+                    println!("  sudo systemctl start notify-done");
+                }
+            }
+
+            Output::List { processes } => {
+                println!("Currently tracked processes:");
+                println!("(Note: Full tracking requires the daemon to be running)");
+                println!();
+                println!("{:>8} {:>12} COMMAND", "PID", "ELAPSED");
+                println!("{:-<8} {:-<12} {:-<20}", "", "", "");
+                for p in processes {
+                    println!("{:>8} {:>12} {}", p.pid, p.elapsed, p.comm);
+                }
+            }
+
+            Output::History {
+                since,
+                entries,
+                show_session,
+            } => {
+                if entries.is_empty() {
+                    match since {
+                        Some(since) => println!(
+                            "No completions since {}.",
+                            history::format_timestamp(*since)
+                        ),
+                        None => {
+                            println!("No history available.");
+                            println!("History is recorded for commands run with 'nd run'.");
+                        }
+                    }
+                    return Ok(());
+                }
+
+                let session_width = if *show_session {
+                    entries
+                        .iter()
+                        .filter_map(|e| e.session_tag.as_ref())
+                        .map(|s| s.chars().count())
+                        .max()
+                        .unwrap_or(0)
+                        .max("SESSION".len())
+                } else {
+                    0
+                };
+
+                let mut header = format!("{:<19} {:>10} {:>5}  ", "FINISHED", "DURATION", "EXIT");
+                let mut rule = format!("{:-<19} {:-<10} {:-<5}  ", "", "", "");
+                if *show_session {
+                    header += &format!("{:<width$}  ", "SESSION", width = session_width);
+                    rule += &format!("{:-<width$}  ", "", width = session_width);
+                }
+                println!("{}COMMAND", header);
+                println!("{}{:-<20}", rule, "");
+
+                for entry in entries {
+                    let mut line = format!(
+                        "{:<19} {:>10} {:>5}  ",
+                        history::format_timestamp(entry.completed_at),
+                        format_duration(entry.duration_secs),
+                        entry.exit_code
+                    );
+                    if *show_session {
+                        line += &format!(
+                            "{:<width$}  ",
+                            entry.session_tag.as_deref().unwrap_or("-"),
+                            width = session_width
+                        );
+                    }
+                    println!("{}{}", line, entry.command);
+                }
+            }
+
+            Output::Last { entry } => {
+                let Some(entry) = entry else {
+                    println!("No history available.");
+                    println!("History is recorded for commands run with 'nd run'.");
+                    return Ok(());
+                };
+
+                println!("Command:  {}", entry.command);
+                if let Some(name) = &entry.name {
+                    println!("Name:     {}", name);
+                }
+                println!("Exit:     {}", entry.exit_code);
+                println!("Duration: {}", format_duration(entry.duration_secs));
+                println!(
+                    "Started:  {}",
+                    history::format_timestamp(entry.started_at())
+                );
+                println!(
+                    "Finished: {}",
+                    history::format_timestamp(entry.completed_at)
+                );
+                if let Some(host) = &entry.host {
+                    println!("Host:     {}", host);
+                }
+                if let Some(session) = &entry.session_tag {
+                    println!("Session:  {}", session);
+                }
+            }
+
+            Output::Why { entry } => {
+                let Some(entry) = entry else {
+                    println!("No failed commands in the history.");
+                    return Ok(());
+                };
+
+                println!("Command:  {}", entry.command);
+                if let Some(name) = &entry.name {
+                    println!("Name:     {}", name);
+                }
+                println!("Exit:     {}", describe_exit(entry));
+                println!("Duration: {}", format_duration(entry.duration_secs));
+                println!(
+                    "Finished: {}",
+                    history::format_timestamp(entry.completed_at)
+                );
+                if let Some(cwd) = &entry.cwd {
+                    println!("Cwd:      {}", cwd);
+                }
+                if let Some(host) = &entry.host {
+                    println!("Host:     {}", host);
+                }
+                if let Some(session) = &entry.session_tag {
+                    println!("Session:  {}", session);
+                }
+                match &entry.stderr_tail {
+                    Some(tail) => {
+                        println!();
+                        println!("stderr:");
+                        println!("{}", tail);
+                    }
+                    None => {
+                        println!();
+                        println!("No stderr captured; run with --notify-on-stderr to keep it.");
+                    }
+                }
+            }
+
+            Output::HistoryClear { cleared, aborted } => {
+                if *aborted {
+                    println!("Aborted.");
+                } else if *cleared == 0 {
+                    println!("History is already empty.");
+                } else {
+                    println!("Cleared {} history entries", cleared);
+                }
+            }
+
+            Output::ConfigShow {
+                user_path,
+                user_config,
+                system_path,
+                system_config,
+            } => {
+                match user_config {
+                    Some(content) => {
+                        println!("User config ({}):\n", user_path.display());
+                        println!("{}", content);
+                    }
+                    None => {
+                        println!("No user config found at {}", user_path.display());
+                        println!(
+                            "\nUsing system defaults. Run 'nd config init' to create a user config."
+                        );
+                    }
+                }
+                if let Some(content) = system_config {
+                    println!("\nSystem config ({}):\n", system_path.display());
+                    println!("{}", content);
+                }
+            }
+
+            Output::ConfigEffective { values } => {
+                let settings: Vec<String> = values
+                    .iter()
+                    .map(|v| format!("{} = {}", v.key, v.value))
+                    .collect();
+                // Long lists and commands don't push every source out
+                let width = settings
+                    .iter()
+                    .map(|s| s.chars().count())
+                    .filter(|&len| len <= EFFECTIVE_ALIGN_MAX)
+                    .max()
+                    .unwrap_or(0);
+                for (setting, value) in settings.iter().zip(values) {
+                    println!(
+                        "{:<width$}  (from {})",
+                        setting,
+                        value.source,
+                        width = width
+                    );
+                }
+            }
+
+            Output::ConfigInit { path, created } => {
+                if *created {
+                    println!("Created config at {}", path.display());
+                } else {
+                    println!("Config already exists at {}", path.display());
+                }
+            }
+
+            Output::ConfigEdit { path } => println!("{} is valid", path.display()),
+
+            Output::ConfigUpdate { message, .. } => println!("{}", message),
+
+            Output::Test { .. } => println!("Notification sent!"),
+
+            Output::Doctor { checks } => {
+                for check in checks {
+                    let mark = match check.status {
+                        CheckStatus::Pass => " ok ",
+                        CheckStatus::Warn => "warn",
+                        CheckStatus::Fail => "FAIL",
+                    };
+                    println!("[{}] {}: {}", mark, check.name, check.detail);
+                    if let Some(hint) = &check.hint {
+                        println!("       -> {}", hint);
+                    }
+                }
+                let count = |status| checks.iter().filter(|c| c.status == status).count();
+                println!(
+                    "\n{} checks: {} passed, {} warnings, {} failed",
+                    checks.len(),
+                    count(CheckStatus::Pass),
+                    count(CheckStatus::Warn),
+                    count(CheckStatus::Fail)
+                );
+            }
+
+            Output::DebugLog { lines } => match lines {
+                Some(lines) => {
+                    for line in lines {
+                        println!(
+                            "{} {:>5} {}",
+                            history::format_timestamp(line.at),
+                            line.level,
+                            line.message
+                        );
+                    }
+                }
+                None => {
+                    println!("No recent log available.");
+                    println!("Set recent_log_lines in the daemon config and restart it.");
+                }
+            },
+
+            Output::WatchPid {
+                pid,
+                comm,
+                duration_secs,
+                notified,
+            } => {
+                println!(
+                    "{} (pid {}) exited after {}",
+                    comm,
+                    pid,
+                    format_duration(*duration_secs)
+                );
+                if !notified {
+                    println!("Below the threshold, no notification sent");
+                }
+            }
+
+            Output::WatchUnit {
+                unit,
+                result,
+                success,
+                duration_secs,
+                notified,
+            } => {
+                println!(
+                    "{} {} ({}) after {}",
+                    unit,
+                    if *success { "finished" } else { "failed" },
+                    result,
+                    format_duration(*duration_secs)
+                );
+                if !notified {
+                    println!("Below the threshold, no notification sent");
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// e.g. "2", or "killed by signal 9 (Killed)" with ", core dumped"
+fn describe_exit(entry: &HistoryEntry) -> String {
+    let Some(signal) = entry.signal else {
+        return entry.exit_code.to_string();
+    };
+    let name = unsafe {
+        let name = libc::strsignal(signal);
+        (!name.is_null()).then(|| {
+            std::ffi::CStr::from_ptr(name)
+                .to_string_lossy()
+                .into_owned()
+        })
+    };
+    let mut description = match name {
+        Some(name) => format!("killed by signal {} ({})", signal, name),
+        None => format!("killed by signal {}", signal),
+    };
+    if entry.core_dumped {
+        description.push_str(", core dumped");
+    }
+    description
+}
@@ -0,0 +1,138 @@
+use std::io::ErrorKind;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+/// How often the /proc fallback checks whether the process is still there
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// An existing process we can wait for
+pub struct WatchedProcess {
+    pub pid: i32,
+    /// Command name from /proc/<pid>/comm
+    pub comm: String,
+    /// Start time in clock ticks since boot, to detect pid reuse
+    start_ticks: u64,
+}
+
+impl WatchedProcess {
+    /// Look up a running process, failing if it doesn't exist
+    pub fn find(pid: i32) -> Result<Self> {
+        if pid <= 0 {
+            anyhow::bail!("Invalid pid {}", pid);
+        }
+
+        if unsafe { libc::kill(pid, 0) } != 0 {
+            let err = std::io::Error::last_os_error();
+            match err.raw_os_error() {
+                Some(libc::ESRCH) => anyhow::bail!("No process with pid {}", pid),
+                // Owned by another user: we can still see it exit
+                Some(libc::EPERM) => {}
+                _ => return Err(err).context(format!("Failed to check pid {}", pid)),
+            }
+        }
+
+        let start_ticks = read_start_ticks(pid)?
+            .ok_or_else(|| anyhow::anyhow!("Process {} has already exited", pid))?;
+        let comm = std::fs::read_to_string(format!("/proc/{}/comm", pid))
+            .map(|c| c.trim_end().to_string())
+            .unwrap_or_else(|_| pid.to_string());
+
+        Ok(Self {
+            pid,
+            comm,
+            start_ticks,
+        })
+    }
+
+    /// Time the process has been running so far
+    pub fn elapsed(&self) -> Result<Duration> {
+        let uptime =
+            std::fs::read_to_string("/proc/uptime").context("Failed to read /proc/uptime")?;
+        let uptime: f64 = uptime
+            .split_whitespace()
+            .next()
+            .and_then(|s| s.parse().ok())
+            .context("Failed to parse /proc/uptime")?;
+
+        let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as f64;
+        let started = self.start_ticks as f64 / ticks_per_sec;
+        Ok(Duration::from_secs_f64((uptime - started).max(0.0)))
+    }
+
+    /// Block until the process exits, using a pidfd when the kernel supports
+    /// it (5.3+) and polling /proc otherwise
+    pub fn wait(&self) -> Result<()> {
+        match pidfd_open(self.pid) {
+            Ok(fd) => wait_pidfd(&fd),
+            // The process exited between `find` and now
+            Err(e) if e.raw_os_error() == Some(libc::ESRCH) => Ok(()),
+            Err(_) => {
+                while self.is_running()? {
+                    thread::sleep(POLL_INTERVAL);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether the same process (not a reused pid) is still alive
+    fn is_running(&self) -> Result<bool> {
+        Ok(read_start_ticks(self.pid)? == Some(self.start_ticks))
+    }
+}
+
+/// Start time from /proc/<pid>/stat, or None if the process is gone
+fn read_start_ticks(pid: i32) -> Result<Option<u64>> {
+    let stat = match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        Ok(stat) => stat,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context(format!("Failed to read /proc/{}/stat", pid)),
+    };
+
+    // comm may contain spaces and parentheses, so split after the last ')'.
+    // What follows starts at field 3 (state); starttime is field 22.
+    let fields: Vec<&str> = stat
+        .rfind(')')
+        .map(|i| stat[i + 1..].split_whitespace().collect())
+        .unwrap_or_default();
+
+    // Zombies still have a stat entry but have finished running
+    if fields.first() == Some(&"Z") {
+        return Ok(None);
+    }
+
+    let start = fields
+        .get(19)
+        .and_then(|s| s.parse().ok())
+        .with_context(|| format!("Failed to parse /proc/{}/stat", pid))?;
+    Ok(Some(start))
+}
+
+fn pidfd_open(pid: i32) -> std::io::Result<OwnedFd> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as i32) })
+}
+
+/// A pidfd becomes readable when the process exits
+fn wait_pidfd(fd: &OwnedFd) -> Result<()> {
+    let mut pfd = libc::pollfd {
+        fd: fd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    loop {
+        if unsafe { libc::poll(&mut pfd, 1, -1) } >= 0 {
+            return Ok(());
+        }
+        let err = std::io::Error::last_os_error();
+        if err.kind() != ErrorKind::Interrupted {
+            return Err(err).context("Failed to wait for process");
+        }
+    }
+}
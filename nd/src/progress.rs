@@ -0,0 +1,273 @@
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use anyhow::{Context, Result};
+use notify_rust::{Hint, Notification, NotificationHandle};
+use regex::Regex;
+
+/// Environment variable telling the command where to report progress
+pub const PROGRESS_FIFO_ENV: &str = "ND_PROGRESS_FIFO";
+
+/// Output lines longer than this are cut before matching the progress regex
+const MAX_SCANNED_LINE: usize = 4096;
+
+/// A line the command wrote to the progress fifo
+#[derive(Debug, PartialEq, Eq)]
+enum Update {
+    /// `progress: 42%`
+    Percent(u8),
+    /// `status: linking`
+    Status(String),
+}
+
+impl Update {
+    fn parse(line: &str) -> Option<Self> {
+        let (key, value) = line.split_once(':')?;
+        let value = value.trim();
+        match key.trim() {
+            "progress" => {
+                let percent: u8 = value.trim_end_matches('%').trim().parse().ok()?;
+                Some(Update::Percent(percent.min(100)))
+            }
+            "status" if !value.is_empty() => Some(Update::Status(value.to_string())),
+            _ => None,
+        }
+    }
+}
+
+/// A fifo the wrapped command can write progress lines to, reflected in a
+/// notification that is updated in place
+pub struct ProgressFifo {
+    dir: PathBuf,
+    path: PathBuf,
+    stop: Arc<AtomicBool>,
+    /// Close the progress notification when stopping
+    close: Arc<AtomicBool>,
+    reader: Option<JoinHandle<Option<u32>>>,
+}
+
+impl ProgressFifo {
+    /// Create the fifo in a private directory and start reading it
+    pub fn create(summary: String, app_name: String) -> Result<Self> {
+        let dir = dirs::runtime_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join(format!("notify-done-progress-{}", std::process::id()));
+        std::fs::DirBuilder::new()
+            .mode(0o700)
+            .create(&dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+
+        let path = dir.join("progress");
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
+        if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+            let err = std::io::Error::last_os_error();
+            let _ = std::fs::remove_dir(&dir);
+            return Err(err).context(format!("Failed to create fifo {}", path.display()));
+        }
+
+        // Opening read-write never blocks waiting for a writer, and the
+        // reader doesn't see EOF each time a writer closes
+        let fifo = match OpenOptions::new().read(true).write(true).open(&path) {
+            Ok(fifo) => fifo,
+            Err(e) => {
+                let _ = std::fs::remove_file(&path);
+                let _ = std::fs::remove_dir(&dir);
+                return Err(e).context(format!("Failed to open fifo {}", path.display()));
+            }
+        };
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let close = Arc::new(AtomicBool::new(false));
+        let reader = {
+            let (stop, close) = (stop.clone(), close.clone());
+            thread::spawn(move || read_updates(fifo, &summary, &app_name, &stop, &close))
+        };
+
+        Ok(Self {
+            dir,
+            path,
+            stop,
+            close,
+            reader: Some(reader),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// A scanner feeding percentages matched by `regex` in the command's
+    /// output to this fifo
+    pub fn scanner(&self, regex: Regex) -> Result<OutputScanner> {
+        // The reader holds the fifo open, so this neither blocks nor fails
+        // for want of one
+        let fifo = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open fifo {}", self.path.display()))?;
+        Ok(OutputScanner {
+            regex,
+            fifo,
+            line: Vec::new(),
+            last: None,
+        })
+    }
+
+    /// Stop reading, closing the progress notification if `close` is set,
+    /// and return its id so the completion notification can replace it
+    pub fn finish(mut self, close: bool) -> Option<u32> {
+        self.close.store(close, Ordering::SeqCst);
+        self.stop.store(true, Ordering::SeqCst);
+
+        // Wake the reader; the fifo is still open on its side, so this
+        // doesn't block
+        if let Ok(mut fifo) = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&self.path)
+        {
+            let _ = fifo.write_all(b"\n");
+        }
+
+        self.reader.take()?.join().ok().flatten()
+    }
+}
+
+impl Drop for ProgressFifo {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+        let _ = std::fs::remove_dir(&self.dir);
+    }
+}
+
+/// Turns the command's output into `progress:` lines, for commands that
+/// print their own progress (rsync --info=progress2, dd status=progress, ...)
+pub struct OutputScanner {
+    regex: Regex,
+    fifo: File,
+    /// The line read so far
+    line: Vec<u8>,
+    /// The last percentage reported, so repeats don't update the notification
+    last: Option<u8>,
+}
+
+impl OutputScanner {
+    /// Scan a chunk of output; progress meters redraw with \r, so that
+    /// ends a line too
+    pub fn feed(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if byte == b'\n' || byte == b'\r' {
+                self.scan_line();
+                self.line.clear();
+            } else if self.line.len() < MAX_SCANNED_LINE {
+                self.line.push(byte);
+            }
+        }
+    }
+
+    fn scan_line(&mut self) {
+        let line = String::from_utf8_lossy(&self.line);
+        let Some(percent) = parse_percent(&self.regex, &line) else {
+            return;
+        };
+        if self.last != Some(percent) {
+            self.last = Some(percent);
+            // A full fifo only loses an intermediate update
+            let _ = writeln!(self.fifo, "progress: {}%", percent);
+        }
+    }
+}
+
+/// The percentage in `line`: the regex's first capture group, or the whole
+/// match if it has none, rounded and capped at 100
+fn parse_percent(regex: &Regex, line: &str) -> Option<u8> {
+    let captures = regex.captures(line)?;
+    let matched = captures.get(1).or_else(|| captures.get(0))?.as_str();
+    let percent: f64 = matched.trim().trim_end_matches('%').trim().parse().ok()?;
+    (percent >= 0.0).then(|| percent.round().min(100.0) as u8)
+}
+
+/// Apply progress lines to a notification until stopped, returning the id
+/// of the notification if one was shown and kept
+fn read_updates(
+    fifo: File,
+    summary: &str,
+    app_name: &str,
+    stop: &AtomicBool,
+    close: &AtomicBool,
+) -> Option<u32> {
+    let mut reader = BufReader::new(fifo);
+    let mut notification = Notification::new();
+    notification
+        .summary(summary)
+        .icon("dialog-information")
+        .appname(app_name);
+    let mut handle: Option<NotificationHandle> = None;
+    let mut warned = false;
+    let mut percent = None;
+    let mut status = None;
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        if !matches!(reader.read_until(b'\n', &mut line), Ok(n) if n > 0) {
+            break;
+        }
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+
+        match Update::parse(&String::from_utf8_lossy(&line)) {
+            Some(Update::Percent(p)) => percent = Some(p),
+            Some(Update::Status(s)) => status = Some(s),
+            None => continue,
+        }
+        let body = match (&status, percent) {
+            (Some(status), Some(percent)) => format!("{} ({}%)", status, percent),
+            (Some(status), None) => status.clone(),
+            (None, Some(percent)) => format!("{}%", percent),
+            (None, None) => continue,
+        };
+
+        // Shown anew with the same id rather than through the handle's
+        // update, which panics on D-Bus errors; the fifo must keep its
+        // reader, or the command's next write blocks forever
+        notification.body(&body);
+        if let Some(percent) = percent {
+            notification.hint(value_hint(percent));
+        }
+        if let Some(handle) = &handle {
+            notification.id(handle.id());
+        }
+        match notification.show() {
+            Ok(shown) => handle = Some(shown),
+            Err(e) if !warned => {
+                eprintln!("nd: failed to show progress notification: {}", e);
+                warned = true;
+            }
+            Err(_) => {}
+        }
+    }
+
+    let handle = handle?;
+    if close.load(Ordering::SeqCst) {
+        handle.close();
+        return None;
+    }
+    Some(handle.id())
+}
+
+/// The freedesktop `value` hint, 0-100: dunst, mako, swaync and
+/// xfce4-notifyd draw it as a progress bar, while GNOME Shell and Plasma
+/// ignore it and show only the percentage in the body
+fn value_hint(percent: u8) -> Hint {
+    Hint::CustomInt("value".to_string(), i32::from(percent))
+}
@@ -0,0 +1,66 @@
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// Signals that would otherwise kill `nd` before it records the command
+const DEFERRED: [libc::c_int; 4] = [libc::SIGINT, libc::SIGQUIT, libc::SIGTERM, libc::SIGHUP];
+
+/// The last deferred signal received, 0 if none
+static PENDING: AtomicI32 = AtomicI32::new(0);
+
+/// The command's pid, which SIGTERM and SIGHUP are forwarded to
+static CHILD: AtomicI32 = AtomicI32::new(0);
+
+/// Keeps termination signals from killing `nd` until the finished
+/// command's history entry and notification are out
+///
+/// SIGINT and SIGQUIT from the terminal also reach the command, so they
+/// are only noted; SIGTERM and SIGHUP are sent to `nd` alone and are
+/// passed on to the command. Either way the command decides when to exit,
+/// and `finish` then lets the signal take `nd` down as it would have.
+pub struct DeferredSignals(());
+
+impl DeferredSignals {
+    /// Start deferring, forwarding to the command with pid `child`
+    pub fn install(child: u32) -> Self {
+        CHILD.store(child as i32, Ordering::SeqCst);
+        for signal in DEFERRED {
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as usize;
+                // Blocking reads and waits carry on instead of failing with EINTR
+                action.sa_flags = libc::SA_RESTART;
+                libc::sigemptyset(&mut action.sa_mask);
+                libc::sigaction(signal, &action, std::ptr::null_mut());
+            }
+        }
+        Self(())
+    }
+
+    /// Restore the default handlers, then re-raise a deferred signal so
+    /// whoever started `nd` sees it die the way it was asked to; returns
+    /// if none was received
+    pub fn finish(self) {
+        for signal in DEFERRED {
+            unsafe {
+                libc::signal(signal, libc::SIG_DFL);
+            }
+        }
+        let signal = PENDING.swap(0, Ordering::SeqCst);
+        if signal != 0 {
+            unsafe {
+                libc::raise(signal);
+            }
+        }
+    }
+}
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    PENDING.store(signal, Ordering::SeqCst);
+    if signal == libc::SIGTERM || signal == libc::SIGHUP {
+        let child = CHILD.load(Ordering::SeqCst);
+        if child > 0 {
+            unsafe {
+                libc::kill(child, signal);
+            }
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Context, Result};
+use zbus::blocking::{Connection, Proxy};
+use zbus::zvariant::OwnedObjectPath;
+
+const SYSTEMD_DESTINATION: &str = "org.freedesktop.systemd1";
+const MANAGER_PATH: &str = "/org/freedesktop/systemd1";
+const MANAGER_INTERFACE: &str = "org.freedesktop.systemd1.Manager";
+const UNIT_INTERFACE: &str = "org.freedesktop.systemd1.Unit";
+const SERVICE_INTERFACE: &str = "org.freedesktop.systemd1.Service";
+
+/// A systemd unit we can wait for, through the manager's D-Bus API
+pub struct WatchedUnit {
+    /// Full unit name, e.g. "backup.service"
+    pub name: String,
+    conn: Connection,
+    path: OwnedObjectPath,
+}
+
+/// How a watched unit finished
+pub struct UnitOutcome {
+    /// The service's Result ("success", "exit-code", "signal", ...), or
+    /// the final state of other unit types
+    pub result: String,
+    pub success: bool,
+    /// Since the unit last left the inactive state
+    pub duration: Duration,
+    /// ExecMainStatus of a service: exit code, or signal number if killed
+    pub exit_status: Option<i32>,
+}
+
+impl WatchedUnit {
+    /// Look up unit `name` (".service" if it has no suffix) in the system
+    /// manager, or in the user's with `user`, failing if it doesn't exist
+    pub fn find(name: &str, user: bool) -> Result<Self> {
+        let name = if name.contains('.') {
+            name.to_string()
+        } else {
+            format!("{}.service", name)
+        };
+        let conn = if user {
+            Connection::session().context("Failed to connect to the session bus")?
+        } else {
+            Connection::system().context("Failed to connect to the system bus")?
+        };
+
+        // Unlike GetUnit, LoadUnit also finds units that aren't loaded yet
+        let manager = Proxy::new(&conn, SYSTEMD_DESTINATION, MANAGER_PATH, MANAGER_INTERFACE)?;
+        let path: OwnedObjectPath = manager
+            .call("LoadUnit", &(name.as_str(),))
+            .map_err(|e| describe_error(e, &name))?;
+
+        let unit = Proxy::new(&conn, SYSTEMD_DESTINATION, path.clone(), UNIT_INTERFACE)?;
+        let load_state: String = unit
+            .get_property("LoadState")
+            .map_err(|e| describe_error(e, &name))?;
+        match load_state.as_str() {
+            "loaded" => {}
+            "not-found" => bail!("No unit named {}", name),
+            "masked" => bail!("{} is masked and can't run", name),
+            state => bail!("{} failed to load ({})", name, state),
+        }
+
+        Ok(Self { name, conn, path })
+    }
+
+    /// Block until the unit finishes; if it isn't running yet, wait for it
+    /// to start first
+    pub fn wait(&self) -> Result<UnitOutcome> {
+        let watch_start = Instant::now();
+        let manager = Proxy::new(
+            &self.conn,
+            SYSTEMD_DESTINATION,
+            MANAGER_PATH,
+            MANAGER_INTERFACE,
+        )?;
+        // systemd only emits unit signals while some client is subscribed
+        manager
+            .call::<_, _, ()>("Subscribe", &())
+            .map_err(|e| describe_error(e, &self.name))?;
+
+        let unit = Proxy::new(
+            &self.conn,
+            SYSTEMD_DESTINATION,
+            self.path.clone(),
+            UNIT_INTERFACE,
+        )?;
+        // Listen before reading the state, so no change falls in between
+        let mut changes = unit.receive_property_changed::<String>("ActiveState");
+        let mut started = is_running(&unit.get_property::<String>("ActiveState")?);
+        let state = loop {
+            let state = changes
+                .next()
+                .context("Lost the connection to systemd")?
+                .get()?;
+            if is_running(&state) {
+                started = true;
+            } else if started {
+                break state;
+            }
+        };
+
+        // Realtime microseconds, 0 if the unit never left inactive
+        let started_at: u64 = unit.get_property("InactiveExitTimestamp").unwrap_or(0);
+        let duration = if started_at > 0 {
+            Duration::from_micros(now_micros().saturating_sub(started_at))
+        } else {
+            watch_start.elapsed()
+        };
+
+        let (result, exit_status) = if self.name.ends_with(".service") {
+            let service = Proxy::new(
+                &self.conn,
+                SYSTEMD_DESTINATION,
+                self.path.clone(),
+                SERVICE_INTERFACE,
+            )?;
+            (
+                service.get_property("Result")?,
+                service.get_property("ExecMainStatus").ok(),
+            )
+        } else {
+            (state.clone(), None)
+        };
+
+        Ok(UnitOutcome {
+            success: state != "failed",
+            result,
+            duration,
+            exit_status,
+        })
+    }
+}
+
+/// Every ActiveState but "inactive" and "failed" means the unit is
+/// (re)starting, running or stopping
+fn is_running(state: &str) -> bool {
+    !matches!(state, "inactive" | "failed")
+}
+
+fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+/// Turn polkit/bus denials into a readable error
+fn describe_error(e: zbus::Error, unit: &str) -> anyhow::Error {
+    match &e {
+        zbus::Error::MethodError(name, message, _)
+            if name.as_str() == "org.freedesktop.DBus.Error.AccessDenied" =>
+        {
+            anyhow!(
+                "Not allowed to watch {}: {}",
+                unit,
+                message.as_deref().unwrap_or("access denied")
+            )
+        }
+        _ => anyhow::Error::new(e).context(format!("Failed to look up {}", unit)),
+    }
+}
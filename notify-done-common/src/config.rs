@@ -0,0 +1,956 @@
+//! Per-user configuration schema, shared by the daemon and `nd`
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::Range;
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Per-user configuration (`~/.config/notify-done/config.toml`), overriding
+/// the system config
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct UserConfig {
+    /// Override threshold for this user
+    #[serde(default)]
+    pub threshold_seconds: Option<u64>,
+
+    /// How the daemon applies the threshold (overrides the system setting;
+    /// `nd run` always uses a fixed threshold)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threshold_mode: Option<ThresholdMode>,
+
+    /// Never notify commands that ran fewer milliseconds than this, even
+    /// with a zero threshold or `always_notify` (overrides the system
+    /// setting; default 500)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_duration_ms: Option<u64>,
+
+    /// Additional patterns to ignore
+    #[serde(default)]
+    pub ignore_patterns: Vec<String>,
+
+    /// Patterns to always notify (even if in system ignore list)
+    #[serde(default)]
+    pub always_notify: Vec<String>,
+
+    /// Disable notifications entirely
+    #[serde(default)]
+    pub disabled: bool,
+
+    /// Only notify commands run under these absolute directories, e.g.
+    /// "/home/me/projects" (replaces the system list; empty = anywhere)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notify_cwd_prefixes: Vec<String>,
+
+    /// Per-command exit code conventions (merged over the system ones)
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub exit_messages: HashMap<String, ExitMessages>,
+
+    /// Alert when a command matching the pattern finishes in fewer seconds
+    /// than this, even if it succeeded (merged over the system ones)
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub min_expected_seconds: HashMap<String, u64>,
+
+    /// Notify failed commands even below the threshold (overrides the
+    /// system setting)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notify_failures_regardless: Option<bool>,
+
+    /// Drop repeat daemon notifications for the same command within this
+    /// many seconds of the last one (overrides the system setting; `nd run`
+    /// always notifies)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dedup_window_seconds: Option<u64>,
+
+    /// Notification urgency and timeouts (replaces the system settings)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notification: Option<NotificationConfig>,
+
+    /// Seconds an `nd --group` group must have nothing running before its
+    /// summary is sent (default 5; ignored by the daemon)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_idle_seconds: Option<u64>,
+
+    /// Skip `nd run` notifications while its terminal is the focused window,
+    /// like `--skip-if-focused` (X11 only; ignored by the daemon)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skip_if_focused: Option<bool>,
+
+    /// `nd run` history settings (ignored by the daemon)
+    #[serde(default, skip_serializing_if = "HistoryConfig::is_default")]
+    pub history: HistoryConfig,
+}
+
+/// History file settings
+///
+/// Commands are stored in full by default, including any secrets passed
+/// as arguments (`--password=...`, tokens in URLs); on shared or audited
+/// machines, set `redact_patterns` or turn off `store_args`.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct HistoryConfig {
+    /// Drop entries older than this many days whenever history is saved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotate_days: Option<u64>,
+
+    /// Regexes whose matches in the command are replaced with `***` before
+    /// it's saved; with capture groups, only the groups are, e.g.
+    /// `'--password[= ](\S+)'` keeps the flag
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redact_patterns: Vec<String>,
+
+    /// Save the command's arguments, not just the program (default true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store_args: Option<bool>,
+}
+
+impl HistoryConfig {
+    pub fn is_default(&self) -> bool {
+        self.rotate_days.is_none() && self.redact_patterns.is_empty() && self.store_args.is_none()
+    }
+}
+
+/// Notification urgency levels from the freedesktop spec
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Urgency {
+    Low,
+    #[default]
+    Normal,
+    Critical,
+}
+
+impl Urgency {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Urgency::Low => "low",
+            Urgency::Normal => "normal",
+            Urgency::Critical => "critical",
+        }
+    }
+}
+
+/// Commands shorter than this never notify unless configured otherwise,
+/// so a zero threshold doesn't mean a notification for every `ls`
+pub const DEFAULT_MIN_DURATION_MS: u64 = 500;
+
+/// App name notifications are sent under unless configured otherwise
+pub const DEFAULT_APP_NAME: &str = "notify-done";
+
+/// Where `nd` delivers notifications
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Desktop notification over D-Bus
+    Desktop,
+    /// A line on the terminal's stderr
+    Stderr,
+    /// The user's own `notification.command`, or notify-send over ssh to
+    /// `notification.ssh_target`
+    Command,
+}
+
+impl Backend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Backend::Desktop => "desktop",
+            Backend::Stderr => "stderr",
+            Backend::Command => "command",
+        }
+    }
+}
+
+/// How the daemon decides a command ran long enough to notify
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThresholdMode {
+    /// `threshold_seconds` for every command
+    #[default]
+    Fixed,
+    /// Longer than the command's recent runs usually take (their
+    /// `dynamic_percentile`), and at least `threshold_seconds`; commands
+    /// with too few recent runs use `threshold_seconds` alone
+    Dynamic,
+}
+
+/// How finely notification bodies show durations
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DurationPrecision {
+    /// Whole seconds: "3s", "1m 5s"
+    #[default]
+    Seconds,
+    /// Milliseconds throughout: "340ms", "3.412s", "1m 5.020s"
+    Millis,
+    /// Tenths of a second under a minute, whole seconds above: "340ms",
+    /// "3.4s", "1m 5s"
+    Auto,
+}
+
+/// e.g. "3s", "2m 5s", "1h 0m 12s", with the seconds as fine as `precision`
+pub fn format_duration(d: Duration, precision: DurationPrecision) -> String {
+    let secs = d.as_secs();
+    let millis = d.subsec_millis();
+    let seconds = match precision {
+        DurationPrecision::Seconds => format!("{}s", secs % 60),
+        _ if secs == 0 => return format!("{}ms", millis),
+        DurationPrecision::Millis => format!("{}.{:03}s", secs % 60, millis),
+        DurationPrecision::Auto if secs < 60 => format!("{}.{}s", secs, millis / 100),
+        DurationPrecision::Auto => format!("{}s", secs % 60),
+    };
+    if secs < 60 {
+        seconds
+    } else if secs < 3600 {
+        format!("{}m {}", secs / 60, seconds)
+    } else {
+        format!("{}h {}m {}", secs / 3600, (secs % 3600) / 60, seconds)
+    }
+}
+
+/// Urgency, expiry and delivery of completion notifications
+///
+/// A timeout of 0 means the notification never expires; any other value
+/// is in milliseconds.
+///
+/// ```toml
+/// [notification]
+/// urgency = "normal"
+/// urgency_failure = "critical"
+/// timeout_ms = 5000
+/// timeout_ms_failure = 0  # failures stay until dismissed
+/// # per-command expiry, winning over the above (glob-style, one `*`)
+/// command_timeouts_ms = { deploy = 0, "cargo*" = 10000 }
+/// backends = ["desktop", "stderr"]
+/// app_name = "notify-done"
+/// desktop_entry = "org.example.Terminal"
+/// icon = "/home/me/.local/share/icons/build.png"  # or a theme icon name
+/// duration_precision = "auto"  # "3.4s" rather than "3s"; or "seconds", "millis"
+/// # extra hints passed through verbatim, as "TYPE:VALUE" (string, int or byte)
+/// hints = { "x-kde-origin-name" = "string:Builds", "x-example-level" = "int:3" }
+/// # run this instead of showing a desktop notification
+/// command = ["my-notify", "-t", "{title}", "-b", "{body}"]
+/// # or notify-send on another machine, e.g. your desktop while building
+/// # remotely (needs key-based ssh auth; ignored if `command` is set)
+/// ssh_target = "me@desktop.local"
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationConfig {
+    /// Urgency for successful commands
+    #[serde(default)]
+    pub urgency: Urgency,
+
+    /// Urgency for failed commands
+    #[serde(default = "default_urgency_failure")]
+    pub urgency_failure: Urgency,
+
+    /// Expiry for successful commands in ms, 0 = never (unset = server default)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u32>,
+
+    /// Expiry for failed commands in ms, 0 = never (unset = same as `timeout_ms`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms_failure: Option<u32>,
+
+    /// Expiry in ms (0 = never) for commands matching a pattern, whatever
+    /// the outcome; an exact name wins, then the longest matching pattern
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub command_timeouts_ms: HashMap<String, u32>,
+
+    /// Ask the server not to keep notifications in its history
+    #[serde(default)]
+    pub transient: bool,
+
+    /// Backends `nd` sends every notification through; empty means auto
+    /// (`command` or `ssh_target` if set, otherwise stderr inside an SSH
+    /// session unless `--local-notify` is given, otherwise desktop, falling
+    /// back to stderr if that fails). The daemon uses `command` or
+    /// `ssh_target` if set, otherwise the desktop.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub backends: Vec<Backend>,
+
+    /// App name notifications are sent under, for per-app theming
+    #[serde(default = "default_app_name")]
+    pub app_name: String,
+
+    /// Desktop entry (without ".desktop") whose icon and name the
+    /// notification server should show
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub desktop_entry: Option<String>,
+
+    /// Icon theme name, or absolute path to an image file (unset = the
+    /// server's default for `nd`'s notifications, none for the daemon's)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+
+    /// Extra hints for desktop notifications, for desktop-specific
+    /// features; each value is "string:…", "int:…" or "byte:…" (`nd`
+    /// leaves byte hints out of its own desktop notifications, with a
+    /// warning)
+    #[serde(
+        default,
+        deserialize_with = "deserialize_hints",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub hints: BTreeMap<String, HintValue>,
+
+    /// How finely the body shows the command's duration
+    #[serde(default)]
+    pub duration_precision: DurationPrecision,
+
+    /// Program and arguments run instead of a desktop notification, with
+    /// {title} and {body} (required) and {urgency} and {app_name}
+    /// substituted; no shell is involved (empty = unset)
+    #[serde(
+        default,
+        deserialize_with = "deserialize_command",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub command: Vec<String>,
+
+    /// `[user@]host` to run notify-send on over ssh instead of showing a
+    /// desktop notification, when `command` isn't set. ssh runs in batch
+    /// mode, so key-based auth (or an agent) must already work.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_ssh_target",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub ssh_target: Option<String>,
+}
+
+impl NotificationConfig {
+    /// Urgency and expiry (ms, 0 = never) for a notification; a `None`
+    /// expiry leaves it to the notification server
+    ///
+    /// Critical notifications persist until dismissed unless a timeout was
+    /// set explicitly for that outcome.
+    pub fn for_outcome(&self, success: bool) -> (Urgency, Option<u32>) {
+        let (urgency, explicit) = if success {
+            (self.urgency, self.timeout_ms)
+        } else {
+            (self.urgency_failure, self.timeout_ms_failure)
+        };
+        let timeout = match explicit {
+            Some(ms) => Some(ms),
+            None if urgency == Urgency::Critical => Some(0),
+            None => self.timeout_ms,
+        };
+        (urgency, timeout)
+    }
+
+    /// Urgency and expiry for a notification about `command`: its
+    /// `command_timeouts_ms` entry wins over the urgency-based default,
+    /// which wins over `timeout_ms`
+    pub fn for_command(&self, command: Option<&str>, success: bool) -> (Urgency, Option<u32>) {
+        let (urgency, timeout) = self.for_outcome(success);
+        let per_command = command.and_then(|command| self.command_timeout(command));
+        (urgency, per_command.or(timeout))
+    }
+
+    fn command_timeout(&self, command: &str) -> Option<u32> {
+        self.command_timeouts_ms.get(command).copied().or_else(|| {
+            self.command_timeouts_ms
+                .iter()
+                .filter(|(pattern, _)| matches_pattern(pattern, command))
+                .max_by_key(|(pattern, _)| pattern.len())
+                .map(|(_, &ms)| ms)
+        })
+    }
+
+    /// Whether notifications go through a command rather than the desktop
+    pub fn has_command(&self) -> bool {
+        !self.command.is_empty() || self.ssh_target.is_some()
+    }
+
+    /// The argv that delivers a notification through `command`, or through
+    /// notify-send over ssh to `ssh_target`; None if neither is set
+    pub fn command_argv(
+        &self,
+        title: &str,
+        body: &str,
+        urgency: Urgency,
+        app_name: &str,
+    ) -> Option<Vec<String>> {
+        let values = |name: &str| match name {
+            "title" => Some(title),
+            "body" => Some(body),
+            "urgency" => Some(urgency.as_str()),
+            "app_name" => Some(app_name),
+            _ => None,
+        };
+        if !self.command.is_empty() {
+            return Some(
+                self.command
+                    .iter()
+                    .map(|arg| expand_placeholders(arg, values))
+                    .collect(),
+            );
+        }
+
+        let target = self.ssh_target.as_deref()?;
+        let mut argv: Vec<String> = [
+            "ssh",
+            "-o",
+            "BatchMode=yes",
+            "-o",
+            SSH_CONNECT_TIMEOUT,
+            "--",
+            target,
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+        // ssh hands the remote arguments to the remote shell as one string
+        argv.extend(
+            SSH_REMOTE_COMMAND
+                .iter()
+                .map(|arg| shell_quote(&expand_placeholders(arg, values))),
+        );
+        Some(argv)
+    }
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        Self {
+            urgency: Urgency::default(),
+            urgency_failure: default_urgency_failure(),
+            timeout_ms: None,
+            timeout_ms_failure: None,
+            command_timeouts_ms: HashMap::new(),
+            transient: false,
+            backends: Vec::new(),
+            app_name: default_app_name(),
+            desktop_entry: None,
+            icon: None,
+            hints: BTreeMap::new(),
+            duration_precision: DurationPrecision::default(),
+            command: Vec::new(),
+            ssh_target: None,
+        }
+    }
+}
+
+/// A typed notification hint value, written "TYPE:VALUE" in the config
+/// like notify-send's `--hint=TYPE:NAME:VALUE`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HintValue {
+    String(String),
+    Int(i32),
+    Byte(u8),
+}
+
+impl HintValue {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            HintValue::String(_) => "string",
+            HintValue::Int(_) => "int",
+            HintValue::Byte(_) => "byte",
+        }
+    }
+
+    /// The hint as notify-send's `--hint` argument
+    pub fn notify_send_arg(&self, name: &str) -> String {
+        format!(
+            "--hint={}:{}:{}",
+            self.type_name(),
+            name,
+            self.value_string()
+        )
+    }
+
+    fn value_string(&self) -> String {
+        match self {
+            HintValue::String(value) => value.clone(),
+            HintValue::Int(value) => value.to_string(),
+            HintValue::Byte(value) => value.to_string(),
+        }
+    }
+}
+
+impl std::str::FromStr for HintValue {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let Some((kind, value)) = s.split_once(':') else {
+            return Err(format!(
+                "invalid hint {:?}, expected \"string:VALUE\", \"int:VALUE\" or \"byte:VALUE\"",
+                s
+            ));
+        };
+        match kind {
+            "string" => Ok(HintValue::String(value.to_string())),
+            "int" => value
+                .parse()
+                .map(HintValue::Int)
+                .map_err(|_| format!("invalid int hint {:?}, expected a 32-bit integer", value)),
+            "byte" => value
+                .parse()
+                .map(HintValue::Byte)
+                .map_err(|_| format!("invalid byte hint {:?}, expected 0 to 255", value)),
+            _ => Err(format!(
+                "unknown hint type {:?}, expected string, int or byte",
+                kind
+            )),
+        }
+    }
+}
+
+impl fmt::Display for HintValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.type_name(), self.value_string())
+    }
+}
+
+impl Serialize for HintValue {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for HintValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+fn deserialize_hints<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<String, HintValue>, D::Error> {
+    let hints = BTreeMap::<String, HintValue>::deserialize(deserializer)?;
+    // notify-send splits `--hint` on colons
+    if let Some(name) = hints
+        .keys()
+        .find(|name| name.is_empty() || name.contains(':'))
+    {
+        return Err(serde::de::Error::custom(format!(
+            "invalid hint name {:?}, it must be non-empty and contain no ':'",
+            name
+        )));
+    }
+    Ok(hints)
+}
+
+/// Turn a configured icon into what notification servers handle best: an
+/// absolute path to an existing file becomes a `file://` URI, since some
+/// servers only load images given that way, while theme names (and paths
+/// that don't exist) pass through unchanged
+pub fn normalize_icon(icon: &str) -> String {
+    let path = std::path::Path::new(icon);
+    if !path.is_absolute() || !path.is_file() {
+        return icon.to_string();
+    }
+
+    let mut uri = String::from("file://");
+    for byte in icon.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+/// Give up on an unreachable `ssh_target` after this long
+const SSH_CONNECT_TIMEOUT: &str = "ConnectTimeout=5";
+
+/// What runs on `ssh_target`, with the same placeholders as `command`
+const SSH_REMOTE_COMMAND: &[&str] = &[
+    "notify-send",
+    "--urgency={urgency}",
+    "--app-name={app_name}",
+    "{title}",
+    "{body}",
+];
+
+/// Quote an argument for a POSIX shell
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+fn deserialize_ssh_target<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    let target = Option::<String>::deserialize(deserializer)?;
+    if let Some(target) = &target {
+        if target.is_empty() || target.starts_with('-') || target.contains(char::is_whitespace) {
+            return Err(serde::de::Error::custom(format!(
+                "invalid ssh_target {:?}, expected [user@]host",
+                target
+            )));
+        }
+    }
+    Ok(target)
+}
+
+/// Placeholders a notification command may use
+pub const COMMAND_PLACEHOLDERS: &[&str] = &["title", "body", "urgency", "app_name"];
+
+/// Placeholders a notification command must use, so nothing is lost
+const REQUIRED_COMMAND_PLACEHOLDERS: &[&str] = &["title", "body"];
+
+/// Check a notification command template: a program, only known
+/// placeholders, and all the required ones
+pub fn validate_command_template(template: &[String]) -> Result<(), String> {
+    if template.is_empty() {
+        return Ok(());
+    }
+    if template[0].trim().is_empty() {
+        return Err("notification command has no program".to_string());
+    }
+
+    let mut used = Vec::new();
+    for arg in template {
+        for name in placeholder_names(arg) {
+            if !COMMAND_PLACEHOLDERS.contains(&name) {
+                return Err(format!(
+                    "unknown placeholder {{{}}} in notification command (expected one of {})",
+                    name,
+                    placeholder_list(COMMAND_PLACEHOLDERS)
+                ));
+            }
+            used.push(name);
+        }
+    }
+
+    let missing: Vec<&str> = REQUIRED_COMMAND_PLACEHOLDERS
+        .iter()
+        .copied()
+        .filter(|name| !used.contains(name))
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!(
+            "notification command must use {}",
+            placeholder_list(&missing)
+        ));
+    }
+    Ok(())
+}
+
+fn deserialize_command<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<String>, D::Error> {
+    let template = Vec::<String>::deserialize(deserializer)?;
+    validate_command_template(&template).map_err(serde::de::Error::custom)?;
+    Ok(template)
+}
+
+fn placeholder_list(names: &[&str]) -> String {
+    names
+        .iter()
+        .map(|name| format!("{{{}}}", name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Names of the `{name}` placeholders in an argument; braces around
+/// anything but a lowercase identifier are literal text
+fn placeholder_names(arg: &str) -> impl Iterator<Item = &str> {
+    arg.split('{').skip(1).filter_map(|rest| {
+        let name = &rest[..rest.find('}')?];
+        is_placeholder_name(name).then_some(name)
+    })
+}
+
+fn is_placeholder_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c == '_')
+}
+
+/// Substitute known placeholders in one pass, so substituted text that
+/// itself looks like a placeholder is left alone
+fn expand_placeholders<'a>(arg: &str, values: impl Fn(&str) -> Option<&'a str>) -> String {
+    let mut out = String::with_capacity(arg.len());
+    let mut rest = arg;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match after
+            .find('}')
+            .and_then(|end| Some((end, values(&after[..end])?)))
+        {
+            Some((end, value)) => {
+                out.push_str(value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn default_app_name() -> String {
+    DEFAULT_APP_NAME.into()
+}
+
+fn default_urgency_failure() -> Urgency {
+    Urgency::Critical
+}
+
+/// How to interpret the exit codes of a specific command
+///
+/// ```toml
+/// [exit_messages.grep]
+/// success_codes = [0, 1]
+/// 1 = "no matches"
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExitMessages {
+    /// Exit codes that count as success (default: 0 only)
+    #[serde(default = "default_success_codes")]
+    pub success_codes: Vec<i32>,
+
+    /// Labels for specific exit codes
+    #[serde(flatten)]
+    pub labels: HashMap<String, String>,
+}
+
+impl ExitMessages {
+    pub fn is_success(&self, exit_code: i32) -> bool {
+        self.success_codes.contains(&exit_code)
+    }
+
+    pub fn label(&self, exit_code: i32) -> Option<&str> {
+        self.labels.get(&exit_code.to_string()).map(String::as_str)
+    }
+}
+
+fn default_success_codes() -> Vec<i32> {
+    vec![0]
+}
+
+/// Describe a config parse error as "line L, column C: message" followed by
+/// the offending line with a caret under the error
+pub fn format_parse_error(content: &str, span: Option<Range<usize>>, message: &str) -> String {
+    let Some(span) = span else {
+        return message.to_string();
+    };
+
+    let offset = span.start.min(content.len());
+    let line_start = content[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = content[offset..]
+        .find('\n')
+        .map_or(content.len(), |i| offset + i);
+    let line_no = content[..line_start].matches('\n').count() + 1;
+    let column = content[line_start..offset].chars().count() + 1;
+
+    let gutter = " ".repeat(line_no.to_string().len());
+    format!(
+        "line {}, column {}: {}\n{} |\n{} | {}\n{} | {}^",
+        line_no,
+        column,
+        message.trim_end(),
+        gutter,
+        line_no,
+        &content[line_start..line_end],
+        gutter,
+        " ".repeat(column - 1)
+    )
+}
+
+/// Whether `path` is one of `dirs` or lies under one; "/opt" covers
+/// "/opt/x" but not "/optional/x"
+pub fn is_under_any(path: &str, dirs: &[String]) -> bool {
+    dirs.iter().any(|dir| {
+        let dir = dir.trim_end_matches('/');
+        path.strip_prefix(dir)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+/// Match a command name against a pattern with at most one `*` wildcard
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        Some((prefix, suffix)) if !suffix.contains('*') => {
+            name.len() >= prefix.len() + suffix.len()
+                && name.starts_with(prefix)
+                && name.ends_with(suffix)
+        }
+        _ => name == pattern,
+    }
+}
+
+/// Minimum expected runtime for `name` from a pattern → seconds map; an
+/// exact entry wins, otherwise the longest matching pattern (the larger
+/// value between patterns of the same length)
+pub fn min_expected_for(min_expected: &HashMap<String, u64>, name: &str) -> Option<u64> {
+    min_expected.get(name).copied().or_else(|| {
+        min_expected
+            .iter()
+            .filter(|(pattern, _)| matches_pattern(pattern, name))
+            .max_by_key(|(pattern, &secs)| (pattern.len(), secs))
+            .map(|(_, &secs)| secs)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_error_points_at_the_offending_line() {
+        let content = "threshold_seconds = 10\nignore_patterns = [\"vim\",\n";
+        let offset = content.find("[\"vim\"").unwrap();
+        let message = format_parse_error(content, Some(offset..offset + 1), "unclosed array");
+        let expected = [
+            "line 2, column 19: unclosed array",
+            "  |",
+            "2 | ignore_patterns = [\"vim\",",
+            "  |                   ^",
+        ];
+        assert_eq!(message, expected.join("\n"));
+    }
+
+    #[test]
+    fn parse_error_without_a_span_is_just_the_message() {
+        assert_eq!(format_parse_error("x = ", None, "oops"), "oops");
+    }
+
+    #[test]
+    fn command_placeholders_are_expanded_once() {
+        let config = NotificationConfig {
+            command: [
+                "notify",
+                "--level={urgency}",
+                "{app_name}: {title}",
+                "{body}",
+                "{other}",
+            ]
+            .map(String::from)
+            .to_vec(),
+            ..Default::default()
+        };
+        let argv = config
+            .command_argv("make done", "took {title}", Urgency::Critical, "nd")
+            .unwrap();
+        assert_eq!(
+            argv,
+            [
+                "notify",
+                "--level=critical",
+                "nd: make done",
+                "took {title}",
+                "{other}"
+            ]
+        );
+        assert_eq!(
+            NotificationConfig::default().command_argv("t", "b", Urgency::Low, "nd"),
+            None
+        );
+    }
+
+    #[test]
+    fn durations_at_each_precision() {
+        let cases = [
+            (340, "0s", "340ms", "340ms"),
+            (3_412, "3s", "3.412s", "3.4s"),
+            (65_020, "1m 5s", "1m 5.020s", "1m 5s"),
+            (3_612_500, "1h 0m 12s", "1h 0m 12.500s", "1h 0m 12s"),
+        ];
+        for (millis, seconds, millis_precision, auto) in cases {
+            let d = Duration::from_millis(millis);
+            assert_eq!(format_duration(d, DurationPrecision::Seconds), seconds);
+            assert_eq!(
+                format_duration(d, DurationPrecision::Millis),
+                millis_precision
+            );
+            assert_eq!(format_duration(d, DurationPrecision::Auto), auto);
+        }
+    }
+
+    #[test]
+    fn min_expected_prefers_the_most_specific_pattern() {
+        let min_expected = HashMap::from([
+            ("cargo*".to_string(), 300),
+            ("cargo-*".to_string(), 30),
+            ("cargo-nextest".to_string(), 60),
+            ("*-tests".to_string(), 5),
+        ]);
+        assert_eq!(min_expected_for(&min_expected, "cargo"), Some(300));
+        assert_eq!(min_expected_for(&min_expected, "cargo-watch"), Some(30));
+        assert_eq!(min_expected_for(&min_expected, "cargo-nextest"), Some(60));
+        assert_eq!(min_expected_for(&min_expected, "unit-tests"), Some(5));
+        // "cargo-*" and "*-tests" are equally specific
+        assert_eq!(min_expected_for(&min_expected, "cargo-tests"), Some(30));
+        assert_eq!(min_expected_for(&min_expected, "make"), None);
+    }
+
+    #[test]
+    fn per_command_timeout_wins() {
+        let config = NotificationConfig {
+            timeout_ms: Some(5000),
+            command_timeouts_ms: HashMap::from([
+                ("deploy".to_string(), 0),
+                ("cargo*".to_string(), 10_000),
+                ("cargo-*".to_string(), 20_000),
+                ("cargo-nextest".to_string(), 30_000),
+            ]),
+            ..Default::default()
+        };
+        let timeout = |command| config.for_command(command, true).1;
+        assert_eq!(timeout(Some("deploy")), Some(0));
+        assert_eq!(timeout(Some("cargo")), Some(10_000));
+        // The longest matching pattern, unless the name is listed exactly
+        assert_eq!(timeout(Some("cargo-watch")), Some(20_000));
+        assert_eq!(timeout(Some("cargo-nextest")), Some(30_000));
+        assert_eq!(timeout(Some("make")), Some(5000));
+        assert_eq!(timeout(None), Some(5000));
+
+        // Even over the never-expiring default of critical failures
+        assert_eq!(
+            config.for_command(Some("make"), false),
+            (Urgency::Critical, Some(0))
+        );
+        assert_eq!(
+            config.for_command(Some("cargo"), false),
+            (Urgency::Critical, Some(10_000))
+        );
+    }
+
+    #[test]
+    fn hints_parse_and_render_for_notify_send() {
+        let hint: HintValue = "int:42".parse().unwrap();
+        assert_eq!(hint, HintValue::Int(42));
+        assert_eq!(hint.notify_send_arg("value"), "--hint=int:value:42");
+
+        // Only the first colon separates the type
+        let hint: HintValue = "string:a:b".parse().unwrap();
+        assert_eq!(hint.notify_send_arg("x-tag"), "--hint=string:x-tag:a:b");
+        assert_eq!(hint.to_string(), "string:a:b");
+
+        assert_eq!("byte:255".parse(), Ok(HintValue::Byte(255)));
+        assert!("byte:256".parse::<HintValue>().is_err());
+        assert!("int:x".parse::<HintValue>().is_err());
+        assert!("double:1.5".parse::<HintValue>().is_err());
+        assert!("42".parse::<HintValue>().is_err());
+    }
+
+    #[test]
+    fn ssh_target_quotes_the_remote_arguments() {
+        let config = NotificationConfig {
+            ssh_target: Some("me@desk".to_string()),
+            ..Default::default()
+        };
+        let argv = config
+            .command_argv("it's done", "ok", Urgency::Normal, "nd")
+            .unwrap();
+        assert_eq!(argv[6], "me@desk");
+        assert_eq!(
+            argv[7..],
+            [
+                "'notify-send'",
+                "'--urgency=normal'",
+                "'--app-name=nd'",
+                "'it'\\''s done'",
+                "'ok'",
+            ]
+        );
+    }
+}
@@ -0,0 +1,133 @@
+//! Claims on completion notifications, shared by `nd` and the daemon
+//!
+//! The daemon skips commands run under `nd`, but only once it has seen the
+//! wrapper, so both can still go to notify for the same exit. Whichever
+//! notifies first creates a marker for that exit with `O_EXCL`; the other
+//! finds it and stays quiet.
+
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+/// Where the markers live, shared by all users like /tmp (mode 1777)
+///
+/// The daemon creates it in its RuntimeDirectory: users' own runtime
+/// directories are out of reach of the hardened unit, which has no
+/// CAP_DAC_OVERRIDE. Without the daemon there is nothing to dedup against,
+/// and `nd` finds no directory.
+pub const DEDUP_DIR: &str = "/run/notify-done/dedup";
+
+/// Markers older than this are removed on the next claim
+pub const DEDUP_TTL_SECS: u64 = 60;
+
+/// Create the shared marker directory (daemon side)
+pub fn create_dir() -> io::Result<()> {
+    fs::create_dir_all(DEDUP_DIR)?;
+    fs::set_permissions(DEDUP_DIR, fs::Permissions::from_mode(0o1777))
+}
+
+/// Claim the notification for the exit of process `pid` of user `uid`,
+/// seen at Unix time `completed_at`; false if the other side claimed it
+/// first
+///
+/// `comm` is the process name as the kernel reports it. Both sides see the
+/// exit at slightly different times, so a marker from the second before
+/// counts too. Markers owned by neither `uid` nor root are ignored, so a
+/// user can't silence someone else's notifications.
+pub fn claim(uid: u32, pid: u32, comm: &str, completed_at: u64) -> io::Result<bool> {
+    claim_in(Path::new(DEDUP_DIR), uid, pid, comm, completed_at)
+}
+
+fn claim_in(dir: &Path, uid: u32, pid: u32, comm: &str, completed_at: u64) -> io::Result<bool> {
+    sweep(dir, completed_at);
+
+    let previous = marker_path(dir, uid, pid, comm, completed_at.saturating_sub(1));
+    if is_claimed(&previous, uid) {
+        return Ok(false);
+    }
+    let path = marker_path(dir, uid, pid, comm, completed_at);
+    match OpenOptions::new().write(true).create_new(true).open(&path) {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(!is_claimed(&path, uid)),
+        Err(e) => Err(e),
+    }
+}
+
+/// `<uid>-<pid>-<second>-<hash of comm>`; the name is hashed since it may
+/// contain anything, '/' included
+fn marker_path(dir: &Path, uid: u32, pid: u32, comm: &str, second: u64) -> PathBuf {
+    dir.join(format!("{}-{}-{}-{:016x}", uid, pid, second, fnv1a(comm)))
+}
+
+fn is_claimed(path: &Path, uid: u32) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|meta| meta.uid() == uid || meta.uid() == 0)
+}
+
+/// Remove expired markers; in the sticky directory only our own go away
+fn sweep(dir: &Path, now: u64) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let second = name
+            .to_str()
+            .and_then(|name| name.split('-').nth(2))
+            .and_then(|second| second.parse::<u64>().ok());
+        if second.is_some_and(|second| second + DEDUP_TTL_SECS < now) {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+/// 64-bit FNV-1a, stable across builds unlike std's hasher, since `nd`
+/// and the daemon must agree on it
+fn fnv1a(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh marker directory and the uid that owns it
+    fn temp_dir(test: &str) -> (PathBuf, u32) {
+        let dir = std::env::temp_dir().join(format!("nd-dedup-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let uid = fs::metadata(&dir).unwrap().uid();
+        (dir, uid)
+    }
+
+    #[test]
+    fn only_the_first_claim_wins() {
+        let (dir, uid) = temp_dir("first");
+        assert!(claim_in(&dir, uid, 42, "make", 1000).unwrap());
+        assert!(!claim_in(&dir, uid, 42, "make", 1000).unwrap());
+        // The other side may see the exit a second later
+        assert!(!claim_in(&dir, uid, 42, "make", 1001).unwrap());
+        // A different process, or the same pid reused, is its own claim
+        assert!(claim_in(&dir, uid, 43, "make", 1000).unwrap());
+        assert!(claim_in(&dir, uid, 42, "cargo", 1000).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn expired_markers_are_swept() {
+        let (dir, uid) = temp_dir("sweep");
+        assert!(claim_in(&dir, uid, 42, "make", 1000).unwrap());
+        assert!(claim_in(&dir, uid, 43, "make", 1000 + DEDUP_TTL_SECS + 1).unwrap());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn comm_with_a_slash_stays_in_the_directory() {
+        let dir = Path::new("/run/notify-done/dedup");
+        let path = marker_path(dir, 1000, 42, "../../etc/x", 1000);
+        assert_eq!(path.parent(), Some(dir));
+    }
+}
@@ -0,0 +1,46 @@
+//! Daemon health snapshot, written by the daemon and read by `nd status`
+
+use serde::{Deserialize, Serialize};
+
+/// Where the daemon writes its health snapshot (systemd RuntimeDirectory)
+pub const HEALTH_PATH: &str = "/run/notify-done/health.json";
+
+/// Where the daemon writes its recent log lines, if enabled
+pub const RECENT_LOG_PATH: &str = "/run/notify-done/recent-log.json";
+
+/// How often the daemon refreshes the snapshot and recent log
+pub const HEALTH_INTERVAL_SECS: u64 = 10;
+
+/// Uptime and counters of a running daemon
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonHealth {
+    /// Daemon version
+    pub version: String,
+    /// Unix time the daemon started
+    pub started_at: u64,
+    /// Unix time this snapshot was written
+    pub updated_at: u64,
+    /// Processes currently being tracked
+    pub active_processes: usize,
+    /// Completed processes kept in history
+    pub history_len: usize,
+    pub exec_events: u64,
+    pub exit_events: u64,
+    /// Ring buffer records that were too short or had an unknown type
+    pub malformed_events: u64,
+    pub notifications_sent: u64,
+    pub notifications_failed: u64,
+    /// Notifications given up on after retrying
+    pub notifications_dropped: u64,
+    /// Notifications waiting to be retried
+    pub pending_retries: usize,
+}
+
+/// A line from the daemon's in-memory recent log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecentLogLine {
+    /// Unix time the line was logged
+    pub at: u64,
+    pub level: String,
+    pub message: String,
+}
@@ -1,4 +1,11 @@
-#![cfg_attr(not(feature = "user"), no_std)]
+#![cfg_attr(not(any(feature = "user", test)), no_std)]
+
+#[cfg(feature = "user")]
+pub mod config;
+#[cfg(feature = "user")]
+pub mod dedup;
+#[cfg(feature = "user")]
+pub mod health;
 
 /// Maximum length of the command name
 pub const COMM_LEN: usize = 16;
@@ -52,7 +59,7 @@ pub struct ProcessExitEvent {
     pub tgid: u32,
     /// User ID
     pub uid: u32,
-    /// Exit code
+    /// Raw wait status (the kernel's task->exit_code), see `WaitStatus`
     pub exit_code: i32,
     /// Timestamp in nanoseconds (monotonic)
     pub timestamp_ns: u64,
@@ -60,6 +67,44 @@ pub struct ProcessExitEvent {
     pub comm: [u8; COMM_LEN],
 }
 
+/// Decoded wait status of an exited process
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaitStatus {
+    /// Exited normally with this code
+    Exited(i32),
+    /// Killed by a signal
+    Signaled { signal: i32, core_dumped: bool },
+}
+
+impl WaitStatus {
+    /// Decode a raw wait status: the low 7 bits are the terminating signal
+    /// (0 for a normal exit), bit 7 is WCOREDUMP and bits 8-15 the exit code
+    pub fn from_raw(status: i32) -> Self {
+        match status & 0x7f {
+            0 => WaitStatus::Exited((status >> 8) & 0xff),
+            signal => WaitStatus::Signaled {
+                signal,
+                core_dumped: status & 0x80 != 0,
+            },
+        }
+    }
+
+    /// Exit code as a shell reports it (128 + signal when killed)
+    pub fn code(&self) -> i32 {
+        match *self {
+            WaitStatus::Exited(code) => code,
+            WaitStatus::Signaled { signal, .. } => 128 + signal,
+        }
+    }
+
+    pub fn core_dumped(&self) -> bool {
+        match *self {
+            WaitStatus::Exited(_) => false,
+            WaitStatus::Signaled { core_dumped, .. } => core_dumped,
+        }
+    }
+}
+
 /// Filter configuration stored in eBPF map
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -85,28 +130,30 @@ impl Default for FilterConfig {
 // Implementations for userspace only
 #[cfg(feature = "user")]
 mod user_impl {
+    use std::borrow::Cow;
+
     use super::*;
 
+    /// Decode a NUL-terminated kernel string, replacing invalid UTF-8
+    /// (e.g. non-UTF-8 filenames) with U+FFFD instead of discarding it
+    fn lossy_str(bytes: &[u8]) -> Cow<'_, str> {
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..len])
+    }
+
     impl ProcessExecEvent {
-        pub fn comm_str(&self) -> &str {
-            let len = self.comm.iter().position(|&b| b == 0).unwrap_or(COMM_LEN);
-            core::str::from_utf8(&self.comm[..len]).unwrap_or("<invalid>")
+        pub fn comm_str(&self) -> Cow<'_, str> {
+            lossy_str(&self.comm)
         }
 
-        pub fn filename_str(&self) -> &str {
-            let len = self
-                .filename
-                .iter()
-                .position(|&b| b == 0)
-                .unwrap_or(FILENAME_LEN);
-            core::str::from_utf8(&self.filename[..len]).unwrap_or("<invalid>")
+        pub fn filename_str(&self) -> Cow<'_, str> {
+            lossy_str(&self.filename)
         }
     }
 
     impl ProcessExitEvent {
-        pub fn comm_str(&self) -> &str {
-            let len = self.comm.iter().position(|&b| b == 0).unwrap_or(COMM_LEN);
-            core::str::from_utf8(&self.comm[..len]).unwrap_or("<invalid>")
+        pub fn comm_str(&self) -> Cow<'_, str> {
+            lossy_str(&self.comm)
         }
     }
 
@@ -146,3 +193,88 @@ pub const MAX_TRACKED_PIDS: u32 = 65536;
 
 /// Maximum number of tracked UIDs
 pub const MAX_TRACKED_UIDS: u32 = 256;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "user")]
+    fn exec_event(comm: &[u8], filename: &[u8]) -> ProcessExecEvent {
+        let mut event = ProcessExecEvent {
+            event_type: EventType::Exec as u8,
+            _pad: [0; 3],
+            pid: 1,
+            tgid: 1,
+            ppid: 0,
+            uid: 1000,
+            timestamp_ns: 0,
+            comm: [0; COMM_LEN],
+            filename: [0; FILENAME_LEN],
+        };
+        event.comm[..comm.len()].copy_from_slice(comm);
+        event.filename[..filename.len()].copy_from_slice(filename);
+        event
+    }
+
+    #[cfg(feature = "user")]
+    #[test]
+    fn names_stop_at_the_nul() {
+        let event = exec_event(b"make", b"/usr/bin/make");
+        assert_eq!(event.comm_str(), "make");
+        assert_eq!(event.filename_str(), "/usr/bin/make");
+    }
+
+    #[cfg(feature = "user")]
+    #[test]
+    fn invalid_utf8_is_replaced_not_dropped() {
+        let event = exec_event(b"caf\xe9", b"/tmp/caf\xe9/run");
+        assert_eq!(event.comm_str(), "caf\u{fffd}");
+        assert_eq!(event.filename_str(), "/tmp/caf\u{fffd}/run");
+    }
+
+    #[cfg(feature = "user")]
+    #[test]
+    fn full_length_names_have_no_nul() {
+        let event = exec_event(b"abcdefghijklmnop", b"");
+        assert_eq!(event.comm_str(), "abcdefghijklmnop");
+        assert_eq!(event.filename_str(), "");
+    }
+
+    #[test]
+    fn normal_exit_decodes_the_code() {
+        assert_eq!(WaitStatus::from_raw(0), WaitStatus::Exited(0));
+        let status = WaitStatus::from_raw(3 << 8);
+        assert_eq!(status, WaitStatus::Exited(3));
+        assert_eq!(status.code(), 3);
+        assert!(!status.core_dumped());
+    }
+
+    #[test]
+    fn signal_decodes_like_a_shell() {
+        let status = WaitStatus::from_raw(15);
+        assert_eq!(
+            status,
+            WaitStatus::Signaled {
+                signal: 15,
+                core_dumped: false
+            }
+        );
+        assert_eq!(status.code(), 143);
+        assert!(!status.core_dumped());
+    }
+
+    #[test]
+    fn core_dump_bit_is_kept() {
+        // SIGSEGV with WCOREDUMP set
+        let status = WaitStatus::from_raw(0x80 | 11);
+        assert_eq!(
+            status,
+            WaitStatus::Signaled {
+                signal: 11,
+                core_dumped: true
+            }
+        );
+        assert_eq!(status.code(), 139);
+        assert!(status.core_dumped());
+    }
+}
@@ -0,0 +1,28 @@
+use std::path::PathBuf;
+
+/// Where `cargo xtask build-ebpf` copies the compiled eBPF object
+const EBPF_OBJECT: &str = "../target/ebpf/notify-done-ebpf";
+
+fn main() {
+    let manifest_dir = PathBuf::from(std::env::var_os("CARGO_MANIFEST_DIR").unwrap());
+    let object = manifest_dir.join(EBPF_OBJECT);
+    println!("cargo:rerun-if-changed={}", object.display());
+
+    // Without the object (e.g. checking the workspace before building the
+    // eBPF side) embed nothing; the daemon then needs NOTIFY_DONE_EBPF_PATH
+    let embedded = if object.exists() {
+        object
+    } else {
+        println!(
+            "cargo:warning=eBPF object not found at {}, run `cargo xtask build-ebpf` to embed it",
+            object.display()
+        );
+        let empty = PathBuf::from(std::env::var_os("OUT_DIR").unwrap()).join("no-ebpf-object");
+        std::fs::write(&empty, []).unwrap();
+        empty
+    };
+    println!(
+        "cargo:rustc-env=NOTIFY_DONE_EBPF_OBJECT={}",
+        embedded.display()
+    );
+}
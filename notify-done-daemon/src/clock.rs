@@ -0,0 +1,39 @@
+use std::time::Instant;
+
+/// Source of monotonic time for durations and expiries, so callers can be
+/// driven by a controlled clock instead of the real one
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+/// The real monotonic clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when told to, for tests
+#[cfg(test)]
+pub struct ManualClock(std::sync::Mutex<Instant>);
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn starting_now() -> Self {
+        Self(std::sync::Mutex::new(Instant::now()))
+    }
+
+    pub fn advance(&self, by: std::time::Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.0.lock().unwrap()
+    }
+}
@@ -1,7 +1,19 @@
 use anyhow::Result;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
-use std::path::PathBuf;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub use notify_done_common::config::{
+    format_duration, normalize_icon, DurationPrecision, ExitMessages, NotificationConfig,
+    ThresholdMode, UserConfig, DEFAULT_APP_NAME,
+};
+use notify_done_common::config::{
+    format_parse_error, is_under_any, matches_pattern, min_expected_for, DEFAULT_MIN_DURATION_MS,
+};
+use notify_done_common::MAX_TRACKED_PIDS;
 
 /// System-wide daemon configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -14,15 +26,215 @@ pub struct DaemonConfig {
     #[serde(default = "default_threshold_seconds")]
     pub threshold_seconds: u64,
 
+    /// Never notify commands that ran fewer milliseconds than this, whatever
+    /// the threshold, `always_notify`, `notify_failures_regardless` or
+    /// `min_expected_seconds` say
+    #[serde(default = "default_min_duration_ms")]
+    pub min_duration_ms: u64,
+
+    /// "fixed" applies threshold_seconds to every command; "dynamic" also
+    /// requires a run to be longer than dynamic_percentile of the command's
+    /// recent runs (the last 50 since the daemon started, 5 at least)
+    #[serde(default)]
+    pub threshold_mode: ThresholdMode,
+
+    /// Percentile (1-100) of recent runs a command must exceed in dynamic
+    /// threshold mode
+    #[serde(default = "default_dynamic_percentile")]
+    pub dynamic_percentile: u8,
+
     /// Command patterns to ignore (glob-style)
     #[serde(default)]
     pub ignore_patterns: Vec<String>,
 
+    /// Only track executables under these directories, e.g. "/home" or
+    /// "/opt" (empty = track all)
+    #[serde(default)]
+    pub track_path_prefixes: Vec<String>,
+
+    /// Only notify commands whose working directory (read at exec) lies
+    /// under one of these absolute directories (empty = anywhere; users can
+    /// set their own list)
+    #[serde(default)]
+    pub notify_cwd_prefixes: Vec<String>,
+
+    /// Never notify commands with a shorter name (default 1: skip empty names)
+    #[serde(default = "default_min_comm_len")]
+    pub min_comm_len: usize,
+
+    /// Command patterns that are never notified, not even by a user's
+    /// always_notify (kernel threads like "[kworker]" always are)
+    #[serde(default)]
+    pub junk_patterns: Vec<String>,
+
+    /// Match ignore, always-notify and min-expected patterns ignoring case
+    #[serde(default)]
+    pub case_insensitive: bool,
+
+    /// UIDs allowed to receive notifications (empty = all tracked users)
+    #[serde(default)]
+    pub notify_uids: Vec<u32>,
+
+    /// UIDs that never receive notifications (takes precedence over notify_uids)
+    #[serde(default)]
+    pub ignore_uids: Vec<u32>,
+
+    /// File listing more users allowed to receive notifications, one UID or
+    /// username per line, added to notify_uids; re-read when it changes, so
+    /// the list can change without a restart
+    #[serde(default)]
+    pub tracked_uids_file: Option<PathBuf>,
+
+    /// Per-command exit code conventions, keyed by command name
+    #[serde(default)]
+    pub exit_messages: HashMap<String, ExitMessages>,
+
+    /// Maximum notifications per user per minute (unset = unlimited)
+    #[serde(default)]
+    pub max_notifications_per_minute: Option<u32>,
+
+    /// Offer a "Mute" action on failure notifications that silences the
+    /// command for this many seconds (unset = no action)
+    #[serde(default)]
+    pub mute_action_secs: Option<u64>,
+
+    /// Alert when a command matching the pattern finishes in fewer seconds
+    /// than this, even if it succeeded
+    #[serde(default)]
+    pub min_expected_seconds: HashMap<String, u64>,
+
+    /// Notify failed commands even below the threshold (ignore patterns
+    /// still apply)
+    #[serde(default)]
+    pub notify_failures_regardless: bool,
+
+    /// Drop repeat notifications for the same command within this many
+    /// seconds of the last one (unset = notify every completion)
+    #[serde(default)]
+    pub dedup_window_seconds: Option<u64>,
+
+    /// Notification urgency and timeouts
+    #[serde(default)]
+    pub notification: NotificationConfig,
+
+    /// Name the parent process in notifications, e.g. "make (from: bash)"
+    #[serde(default)]
+    pub show_parent: bool,
+
+    /// Leave commands run under `nd` (and everything they spawn) to the
+    /// wrapper's own notification instead of notifying each of them
+    #[serde(default)]
+    pub suppress_wrapped: bool,
+
+    /// Skip commands that had no controlling terminal (services, cron
+    /// jobs, daemons' children); commands whose terminal couldn't be read
+    /// because they exited right away are still notified
+    #[serde(default)]
+    pub skip_no_tty: bool,
+
+    /// Name the user and uid that ran the command, for shared machines
+    #[serde(default)]
+    pub show_user: bool,
+
+    /// Most processes tracked at once; the oldest are dropped beyond this,
+    /// e.g. when exit events are lost during a fork storm
+    #[serde(default = "default_max_tracked_processes")]
+    pub max_tracked_processes: usize,
+
+    /// Clock used for command durations: "auto", "kernel" or "userspace"
+    /// (counts time suspended, see DurationSource)
+    #[serde(default)]
+    pub duration_source: DurationSource,
+
+    /// Most ring buffer events handled per pass before going back to the
+    /// main loop, so signals and timers are served during event floods
+    #[serde(default = "default_events_per_batch")]
+    pub events_per_batch: usize,
+
+    /// Push per-completion duration and count metrics, tagged by command,
+    /// to this StatsD address, e.g. "127.0.0.1:8125" (unset = off)
+    #[serde(default)]
+    pub statsd_addr: Option<String>,
+
+    /// Export every completion as an OpenTelemetry span to this OTLP/HTTP
+    /// endpoint, e.g. "http://127.0.0.1:4318" (unset = off; plain http only,
+    /// spans are JSON-encoded and posted to /v1/traces unless a path is given)
+    #[serde(default)]
+    pub otlp_endpoint: Option<String>,
+
+    /// Tell the user of the active session when the daemon starts and stops
+    #[serde(default)]
+    pub notify_on_lifecycle: bool,
+
+    /// Replace the previous notification for the same command instead of stacking
+    #[serde(default)]
+    pub replace_notifications: bool,
+
+    /// Log level filter, e.g. "info" or "notify_done_daemon=debug" (RUST_LOG overrides)
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+
+    /// Keep this many recent debug-level log lines in memory for
+    /// `nd debug log`, regardless of log_level (unset = off)
+    #[serde(default)]
+    pub recent_log_lines: Option<usize>,
+
+    /// Log output format
+    #[serde(default)]
+    pub log_format: LogFormat,
+
     /// Whether to log all events (debug mode)
     #[serde(default)]
     pub debug: bool,
 }
 
+/// Which clock measures command durations
+///
+/// The kernel timestamps (`bpf_ktime_get_ns`, CLOCK_MONOTONIC) are taken the
+/// moment the process execs and exits, but don't advance while the machine
+/// is suspended. The userspace clock (CLOCK_BOOTTIME) does count suspend,
+/// but is read when the daemon handles each event, so durations are off by
+/// however long events wait in the ring buffer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DurationSource {
+    /// Kernel timestamps, falling back to the daemon's monotonic clock when
+    /// they're out of order
+    #[default]
+    Auto,
+    /// Kernel timestamps only
+    Kernel,
+    /// The daemon's boot-time clock only, counting suspend
+    Userspace,
+}
+
+/// Daemon log output format
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per line, for log aggregators
+    Json,
+}
+
+fn default_log_level() -> String {
+    "info".into()
+}
+
+fn default_events_per_batch() -> usize {
+    1024
+}
+
+fn default_min_comm_len() -> usize {
+    1
+}
+
+fn default_max_tracked_processes() -> usize {
+    MAX_TRACKED_PIDS as usize
+}
+
 fn default_min_uid() -> u32 {
     1000
 }
@@ -31,12 +243,52 @@ fn default_threshold_seconds() -> u64 {
     10
 }
 
+fn default_min_duration_ms() -> u64 {
+    DEFAULT_MIN_DURATION_MS
+}
+
+fn default_dynamic_percentile() -> u8 {
+    90
+}
+
 impl Default for DaemonConfig {
     fn default() -> Self {
         Self {
             min_uid: default_min_uid(),
             threshold_seconds: default_threshold_seconds(),
+            min_duration_ms: default_min_duration_ms(),
+            threshold_mode: ThresholdMode::default(),
+            dynamic_percentile: default_dynamic_percentile(),
             ignore_patterns: default_ignore_patterns(),
+            track_path_prefixes: Vec::new(),
+            notify_cwd_prefixes: Vec::new(),
+            min_comm_len: default_min_comm_len(),
+            junk_patterns: Vec::new(),
+            case_insensitive: false,
+            notify_uids: Vec::new(),
+            ignore_uids: Vec::new(),
+            tracked_uids_file: None,
+            exit_messages: HashMap::new(),
+            max_notifications_per_minute: None,
+            mute_action_secs: None,
+            min_expected_seconds: HashMap::new(),
+            notify_failures_regardless: false,
+            dedup_window_seconds: None,
+            notification: NotificationConfig::default(),
+            show_parent: false,
+            show_user: false,
+            suppress_wrapped: false,
+            skip_no_tty: false,
+            max_tracked_processes: default_max_tracked_processes(),
+            duration_source: DurationSource::default(),
+            events_per_batch: default_events_per_batch(),
+            statsd_addr: None,
+            otlp_endpoint: None,
+            notify_on_lifecycle: false,
+            replace_notifications: false,
+            log_level: default_log_level(),
+            recent_log_lines: None,
+            log_format: LogFormat::default(),
             debug: false,
         }
     }
@@ -76,32 +328,13 @@ fn default_ignore_patterns() -> Vec<String> {
     ]
 }
 
-/// Per-user configuration (overrides system config)
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct UserConfig {
-    /// Override threshold for this user
-    pub threshold_seconds: Option<u64>,
-
-    /// Additional patterns to ignore
-    #[serde(default)]
-    pub ignore_patterns: Vec<String>,
-
-    /// Patterns to always notify (even if in system ignore list)
-    #[serde(default)]
-    pub always_notify: Vec<String>,
-
-    /// Disable notifications entirely
-    #[serde(default)]
-    pub disabled: bool,
-}
-
 impl DaemonConfig {
     /// Load configuration from system path
     pub fn load() -> Result<Self> {
         let path = Self::system_config_path();
         if path.exists() {
             let content = std::fs::read_to_string(&path)?;
-            Ok(toml::from_str(&content)?)
+            parse_toml(&path, &content)
         } else {
             Ok(Self::default())
         }
@@ -112,61 +345,105 @@ impl DaemonConfig {
         PathBuf::from("/etc/notify-done/config.toml")
     }
 
+    /// Check if a user should receive notifications (denylist wins);
+    /// `file_uids` come from `tracked_uids_file` and extend notify_uids
+    pub fn should_notify_uid(&self, uid: u32, file_uids: Option<&HashSet<u32>>) -> bool {
+        if self.ignore_uids.contains(&uid) {
+            return false;
+        }
+        let file_uids = file_uids.filter(|uids| !uids.is_empty());
+        if self.notify_uids.is_empty() && file_uids.is_none() {
+            return true;
+        }
+        self.notify_uids.contains(&uid) || file_uids.is_some_and(|uids| uids.contains(&uid))
+    }
+
+    /// Check if an executable lies under one of the tracked directories
+    pub fn tracks_path(&self, filename: &str) -> bool {
+        self.track_path_prefixes.is_empty() || is_under_any(filename, &self.track_path_prefixes)
+    }
+
+    /// Check if a command name is too short, a kernel thread, or junk
+    pub fn is_junk_comm(&self, comm: &str) -> bool {
+        if comm.chars().count() < self.min_comm_len || comm.starts_with('[') {
+            return true;
+        }
+        let comm = fold_case(comm, self.case_insensitive);
+        self.junk_patterns
+            .iter()
+            .any(|p| matches_pattern(&fold_case(p, self.case_insensitive), &comm))
+    }
+
     /// Check if a command should be ignored
     pub fn should_ignore(&self, comm: &str) -> bool {
-        self.ignore_patterns.iter().any(|p| {
-            if p.contains('*') {
-                // Simple glob matching
-                let parts: Vec<&str> = p.split('*').collect();
-                if parts.len() == 2 {
-                    comm.starts_with(parts[0]) && comm.ends_with(parts[1])
-                } else {
-                    comm == p
-                }
-            } else {
-                comm == p
-            }
-        })
+        let comm = fold_case(comm, self.case_insensitive);
+        self.ignore_patterns
+            .iter()
+            .any(|p| matches_pattern(&fold_case(p, self.case_insensitive), &comm))
     }
 }
 
-impl UserConfig {
-    /// Load user configuration from their home directory
-    pub fn load_for_uid(uid: u32) -> Result<Option<Self>> {
-        let path = Self::user_config_path(uid)?;
-        if path.exists() {
-            let content = std::fs::read_to_string(&path)?;
-            Ok(Some(toml::from_str(&content)?))
-        } else {
-            Ok(None)
-        }
-    }
-
-    /// Get config path for a user
-    fn user_config_path(uid: u32) -> Result<PathBuf> {
-        // Read passwd to get home directory
-        let passwd_entry = std::fs::read_to_string("/etc/passwd")?;
-        for line in passwd_entry.lines() {
-            let parts: Vec<&str> = line.split(':').collect();
-            if parts.len() >= 6 {
-                if let Ok(entry_uid) = parts[2].parse::<u32>() {
-                    if entry_uid == uid {
-                        let home = parts[5];
-                        return Ok(PathBuf::from(home).join(".config/notify-done/config.toml"));
-                    }
+/// Load a user's configuration from their home directory
+pub fn load_user_config(uid: u32) -> Result<Option<UserConfig>> {
+    let path = user_config_path(uid)?;
+    if path.exists() {
+        let content = std::fs::read_to_string(&path)?;
+        parse_toml(&path, &content).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+/// Parse a TOML config file, pointing at the offending line on error
+fn parse_toml<T: DeserializeOwned>(path: &Path, content: &str) -> Result<T> {
+    toml::from_str(content).map_err(|e| {
+        anyhow::anyhow!(
+            "Invalid config {}: {}",
+            path.display(),
+            format_parse_error(content, e.span(), e.message())
+        )
+    })
+}
+
+/// Get config path for a user
+fn user_config_path(uid: u32) -> Result<PathBuf> {
+    // Read passwd to get home directory
+    let passwd_entry = std::fs::read_to_string("/etc/passwd")?;
+    for line in passwd_entry.lines() {
+        let parts: Vec<&str> = line.split(':').collect();
+        if parts.len() >= 6 {
+            if let Ok(entry_uid) = parts[2].parse::<u32>() {
+                if entry_uid == uid {
+                    let home = parts[5];
+                    return Ok(PathBuf::from(home).join(".config/notify-done/config.toml"));
                 }
             }
         }
-        Ok(PathBuf::from(format!("/home/{}", uid)).join(".config/notify-done/config.toml"))
     }
+    Ok(PathBuf::from(format!("/home/{}", uid)).join(".config/notify-done/config.toml"))
 }
 
 /// Combined configuration for a specific user
 pub struct EffectiveConfig {
     pub threshold_seconds: u64,
+    pub threshold_mode: ThresholdMode,
+    /// The command's typical duration in dynamic threshold mode, filled in
+    /// from recent runs (None = fixed threshold only)
+    pub dynamic_threshold: Option<Duration>,
+    pub min_duration: Duration,
     pub ignore_set: HashSet<String>,
     pub always_notify: HashSet<String>,
     pub disabled: bool,
+    pub exit_messages: HashMap<String, ExitMessages>,
+    pub min_expected_seconds: HashMap<String, u64>,
+    pub notify_failures_regardless: bool,
+    pub dedup_window_seconds: Option<u64>,
+    pub notification: NotificationConfig,
+    pub notify_cwd_prefixes: Vec<String>,
+    /// Patterns above are lowercased and commands are lowercased before lookup
+    pub case_insensitive: bool,
+    /// Commands temporarily muted from a notification action
+    pub muted: HashSet<String>,
 }
 
 impl EffectiveConfig {
@@ -174,40 +451,356 @@ impl EffectiveConfig {
         let threshold_seconds = user
             .and_then(|u| u.threshold_seconds)
             .unwrap_or(daemon.threshold_seconds);
+        let threshold_mode = user
+            .and_then(|u| u.threshold_mode)
+            .unwrap_or(daemon.threshold_mode);
+        let min_duration = Duration::from_millis(
+            user.and_then(|u| u.min_duration_ms)
+                .unwrap_or(daemon.min_duration_ms),
+        );
+        let notify_failures_regardless = user
+            .and_then(|u| u.notify_failures_regardless)
+            .unwrap_or(daemon.notify_failures_regardless);
+        let dedup_window_seconds = user
+            .and_then(|u| u.dedup_window_seconds)
+            .or(daemon.dedup_window_seconds);
 
         let mut ignore_set: HashSet<String> = daemon.ignore_patterns.iter().cloned().collect();
         let mut always_notify = HashSet::new();
         let mut disabled = false;
+        let mut exit_messages = daemon.exit_messages.clone();
+        let mut min_expected_seconds = daemon.min_expected_seconds.clone();
+        let mut notification = daemon.notification.clone();
+        let mut notify_cwd_prefixes = daemon.notify_cwd_prefixes.clone();
 
         if let Some(user) = user {
             ignore_set.extend(user.ignore_patterns.iter().cloned());
             always_notify.extend(user.always_notify.iter().cloned());
             disabled = user.disabled;
+            exit_messages.extend(user.exit_messages.clone());
+            min_expected_seconds.extend(user.min_expected_seconds.clone());
+            if let Some(n) = &user.notification {
+                notification = n.clone();
+            }
+            if !user.notify_cwd_prefixes.is_empty() {
+                notify_cwd_prefixes = user.notify_cwd_prefixes.clone();
+            }
+        }
+
+        let case_insensitive = daemon.case_insensitive;
+        if case_insensitive {
+            ignore_set = ignore_set.into_iter().map(|p| p.to_lowercase()).collect();
+            always_notify = always_notify
+                .into_iter()
+                .map(|p| p.to_lowercase())
+                .collect();
+            min_expected_seconds = min_expected_seconds
+                .into_iter()
+                .map(|(p, secs)| (p.to_lowercase(), secs))
+                .collect();
         }
 
         Self {
             threshold_seconds,
+            threshold_mode,
+            dynamic_threshold: None,
+            min_duration,
             ignore_set,
             always_notify,
             disabled,
+            exit_messages,
+            min_expected_seconds,
+            notify_failures_regardless,
+            dedup_window_seconds,
+            notification,
+            notify_cwd_prefixes,
+            case_insensitive,
+            muted: HashSet::new(),
         }
     }
 
-    pub fn should_notify(&self, comm: &str, duration_secs: u64) -> bool {
-        if self.disabled {
+    /// Exit code conventions for a command, if configured
+    pub fn exit_messages_for(&self, comm: &str) -> Option<&ExitMessages> {
+        self.exit_messages.get(comm)
+    }
+
+    /// Whether an exit code counts as success for a command
+    pub fn is_success(&self, comm: &str, exit_code: i32) -> bool {
+        match self.exit_messages_for(comm) {
+            Some(m) => m.is_success(exit_code),
+            None => exit_code == 0,
+        }
+    }
+
+    /// Minimum expected runtime of a command, if it finished faster than that
+    pub fn finished_too_fast(&self, comm: &str, duration_secs: u64) -> Option<u64> {
+        let comm = fold_case(comm, self.case_insensitive);
+        min_expected_for(&self.min_expected_seconds, &comm).filter(|&min| duration_secs < min)
+    }
+
+    /// Whether a command that ran in `cwd` (None if it couldn't be read)
+    /// should be notified
+    pub fn should_notify(
+        &self,
+        comm: &str,
+        cwd: Option<&str>,
+        duration: Duration,
+        success: bool,
+    ) -> bool {
+        if self.disabled || self.muted.contains(comm) || !self.in_notified_dir(cwd) {
             return false;
         }
 
+        // Instant commands never notify, whatever else is configured
+        if duration < self.min_duration {
+            return false;
+        }
+
+        // Finishing far sooner than expected usually means an early failure
+        let duration_secs = duration.as_secs();
+        if self.finished_too_fast(comm, duration_secs).is_some() {
+            return true;
+        }
+
+        let key = fold_case(comm, self.case_insensitive);
+
         // Check always_notify first
-        if self.always_notify.contains(comm) {
-            return duration_secs >= self.threshold_seconds;
+        if self.always_notify.contains(key.as_ref()) {
+            return self.meets_threshold(duration, success);
         }
 
         // Check ignore list
-        if self.ignore_set.contains(comm) {
+        if self.ignore_set.contains(key.as_ref()) {
             return false;
         }
 
-        duration_secs >= self.threshold_seconds
+        self.meets_threshold(duration, success)
+    }
+
+    /// Whether `cwd` lies under `notify_cwd_prefixes`; an unknown working
+    /// directory passes, so a lost /proc race never hides a notification
+    fn in_notified_dir(&self, cwd: Option<&str>) -> bool {
+        match cwd {
+            Some(cwd) => {
+                self.notify_cwd_prefixes.is_empty() || is_under_any(cwd, &self.notify_cwd_prefixes)
+            }
+            None => true,
+        }
+    }
+
+    fn meets_threshold(&self, duration: Duration, success: bool) -> bool {
+        let long_enough = duration.as_secs() >= self.threshold_seconds
+            && self
+                .dynamic_threshold
+                .is_none_or(|typical| duration > typical);
+        long_enough || (self.notify_failures_regardless && !success)
+    }
+}
+
+/// Lowercase a command or pattern when matching case-insensitively
+fn fold_case(s: &str, case_insensitive: bool) -> Cow<'_, str> {
+    if case_insensitive {
+        Cow::Owned(s.to_lowercase())
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn effective(daemon: DaemonConfig) -> EffectiveConfig {
+        EffectiveConfig::new(&daemon, None)
+    }
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn empty_uid_lists_notify_everyone() {
+        let config = DaemonConfig::default();
+        assert!(config.should_notify_uid(1000, None));
+        assert!(config.should_notify_uid(1001, None));
+    }
+
+    #[test]
+    fn notify_uids_is_an_allowlist() {
+        let config = DaemonConfig {
+            notify_uids: vec![1000],
+            ..Default::default()
+        };
+        assert!(config.should_notify_uid(1000, None));
+        assert!(!config.should_notify_uid(1001, None));
+    }
+
+    #[test]
+    fn ignore_uids_wins_over_notify_uids() {
+        let config = DaemonConfig {
+            notify_uids: vec![1000, 1001],
+            ignore_uids: vec![1001, 1002],
+            ..Default::default()
+        };
+        assert!(config.should_notify_uid(1000, None));
+        assert!(!config.should_notify_uid(1001, None));
+        assert!(!config.should_notify_uid(1002, None));
+    }
+
+    #[test]
+    fn malformed_toml_error_names_the_line() {
+        let content = "threshold_seconds = 10\nmin_uid = \"a thousand\"\n";
+        let err = parse_toml::<DaemonConfig>(Path::new("/etc/notify-done/config.toml"), content)
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("Invalid config /etc/notify-done/config.toml: line 2,"));
+        assert!(err.contains("2 | min_uid = \"a thousand\""));
+    }
+
+    #[test]
+    fn fast_failure_notifies_only_with_notify_failures_regardless() {
+        let daemon = DaemonConfig {
+            threshold_seconds: 30,
+            ..Default::default()
+        };
+        let config = effective(daemon.clone());
+        assert!(!config.should_notify("make", None, secs(2), false));
+
+        let config = effective(DaemonConfig {
+            notify_failures_regardless: true,
+            ..daemon
+        });
+        assert!(config.should_notify("make", None, secs(2), false));
+        // Fast successes still stay quiet
+        assert!(!config.should_notify("make", None, secs(2), true));
+    }
+
+    #[test]
+    fn ignore_patterns_fold_case_only_when_asked() {
+        let daemon = DaemonConfig {
+            threshold_seconds: 10,
+            ignore_patterns: vec!["cargo".to_string()],
+            ..Default::default()
+        };
+        assert!(!effective(daemon.clone()).should_notify("cargo", None, secs(60), true));
+        assert!(effective(daemon.clone()).should_notify("CARGO", None, secs(60), true));
+        assert!(!daemon.should_ignore("CARGO"));
+
+        let daemon = DaemonConfig {
+            case_insensitive: true,
+            ..daemon
+        };
+        assert!(!effective(daemon.clone()).should_notify("CARGO", None, secs(60), true));
+        assert!(daemon.should_ignore("CARGO"));
+        assert!(daemon.should_ignore("Cargo"));
+    }
+
+    #[test]
+    fn junk_comm_boundaries() {
+        let config = DaemonConfig::default();
+        assert!(config.is_junk_comm(""));
+        assert!(!config.is_junk_comm("x"));
+        assert!(config.is_junk_comm("[kworker/0:1]"));
+
+        let config = DaemonConfig {
+            min_comm_len: 3,
+            junk_patterns: vec!["tmp*".to_string(), "a.out".to_string()],
+            ..Default::default()
+        };
+        // Length counts characters, not bytes
+        assert!(config.is_junk_comm("ab"));
+        assert!(config.is_junk_comm("é"));
+        assert!(!config.is_junk_comm("abc"));
+        assert!(!config.is_junk_comm("ééé"));
+        assert!(config.is_junk_comm("tmp"));
+        assert!(config.is_junk_comm("tmp.X3a9"));
+        assert!(!config.is_junk_comm("xtmp"));
+        assert!(config.is_junk_comm("a.out"));
+        assert!(!config.is_junk_comm("a.outx"));
+    }
+
+    #[test]
+    fn dynamic_threshold_requires_beating_the_typical_run() {
+        let mut config = effective(DaemonConfig {
+            threshold_seconds: 10,
+            ..Default::default()
+        });
+        config.dynamic_threshold = Some(secs(60));
+        assert!(!config.should_notify("make", None, secs(30), true));
+        assert!(!config.should_notify("make", None, secs(60), true));
+        assert!(config.should_notify("make", None, secs(61), true));
+
+        // The static threshold still applies below a short typical run
+        config.dynamic_threshold = Some(secs(2));
+        assert!(!config.should_notify("make", None, secs(5), true));
+        assert!(config.should_notify("make", None, secs(10), true));
+    }
+
+    #[test]
+    fn nothing_below_min_duration_notifies() {
+        let daemon = DaemonConfig {
+            threshold_seconds: 0,
+            notify_failures_regardless: true,
+            min_expected_seconds: HashMap::from([("deploy".to_string(), 60)]),
+            ..Default::default()
+        };
+        let user = UserConfig {
+            always_notify: vec!["make".to_string()],
+            ..Default::default()
+        };
+        let config = EffectiveConfig::new(&daemon, Some(&user));
+        let millis = Duration::from_millis;
+        for comm in ["true", "make", "deploy"] {
+            assert!(!config.should_notify(comm, None, millis(100), true));
+            assert!(!config.should_notify(comm, None, millis(100), false));
+            assert!(config.should_notify(comm, None, millis(500), true));
+        }
+
+        let user = UserConfig {
+            min_duration_ms: Some(50),
+            ..user
+        };
+        let config = EffectiveConfig::new(&daemon, Some(&user));
+        assert!(config.should_notify("true", None, millis(100), true));
+    }
+
+    #[test]
+    fn notify_cwd_prefixes_filter_by_working_directory() {
+        let daemon = DaemonConfig {
+            threshold_seconds: 10,
+            notify_cwd_prefixes: vec!["/home/alice/src".to_string()],
+            ..Default::default()
+        };
+        let config = effective(daemon.clone());
+        assert!(config.should_notify("make", Some("/home/alice/src/app"), secs(60), true));
+        assert!(config.should_notify("make", Some("/home/alice/src"), secs(60), true));
+        assert!(!config.should_notify("make", Some("/home/alice/srcs"), secs(60), true));
+        assert!(!config.should_notify("make", Some("/tmp"), secs(60), true));
+        // A cwd that couldn't be read doesn't hide the notification
+        assert!(config.should_notify("make", None, secs(60), true));
+
+        // A user list replaces the system one
+        let user = UserConfig {
+            notify_cwd_prefixes: vec!["/tmp".to_string()],
+            ..Default::default()
+        };
+        let config = EffectiveConfig::new(&daemon, Some(&user));
+        assert!(config.should_notify("make", Some("/tmp/build"), secs(60), true));
+        assert!(!config.should_notify("make", Some("/home/alice/src/app"), secs(60), true));
+    }
+
+    #[test]
+    fn track_path_prefixes_match_whole_components() {
+        assert!(DaemonConfig::default().tracks_path("/usr/bin/make"));
+
+        let config = DaemonConfig {
+            track_path_prefixes: vec!["/opt".to_string(), "/home/".to_string()],
+            ..Default::default()
+        };
+        assert!(config.tracks_path("/opt/tool/bin/run"));
+        assert!(config.tracks_path("/home/alice/bin/build"));
+        assert!(config.tracks_path("/opt"));
+        assert!(!config.tracks_path("/optional/run"));
+        assert!(!config.tracks_path("/usr/bin/make"));
     }
 }
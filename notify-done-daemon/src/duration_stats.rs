@@ -0,0 +1,98 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+/// Recent runs kept per command
+const SAMPLES_PER_COMMAND: usize = 50;
+
+/// Fewer runs than this are too few for a meaningful percentile
+pub const MIN_SAMPLES: usize = 5;
+
+/// Commands tracked at most; new ones are left out beyond that
+const MAX_COMMANDS: usize = 4096;
+
+/// Rolling durations of recent runs, by (uid, comm), for the dynamic
+/// threshold; kept in memory only, so they start over on restart
+#[derive(Default)]
+pub struct DurationStats {
+    samples: HashMap<(u32, String), VecDeque<Duration>>,
+}
+
+impl DurationStats {
+    /// Add a completed run
+    pub fn record(&mut self, uid: u32, comm: &str, duration: Duration) {
+        let key = (uid, comm.to_string());
+        if !self.samples.contains_key(&key) && self.samples.len() >= MAX_COMMANDS {
+            return;
+        }
+        let runs = self.samples.entry(key).or_default();
+        if runs.len() == SAMPLES_PER_COMMAND {
+            runs.pop_front();
+        }
+        runs.push_back(duration);
+    }
+
+    /// The `percentile` (1-100, nearest rank) of the command's recent
+    /// durations, or None with fewer than MIN_SAMPLES runs
+    pub fn percentile(&self, uid: u32, comm: &str, percentile: u8) -> Option<Duration> {
+        let runs = self.samples.get(&(uid, comm.to_string()))?;
+        if runs.len() < MIN_SAMPLES {
+            return None;
+        }
+        let mut sorted: Vec<Duration> = runs.iter().copied().collect();
+        sorted.sort_unstable();
+        let percentile = usize::from(percentile.clamp(1, 100));
+        let rank = (percentile * sorted.len()).div_ceil(100);
+        Some(sorted[rank - 1])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats_with(durations: &[u64]) -> DurationStats {
+        let mut stats = DurationStats::default();
+        for &secs in durations {
+            stats.record(1000, "make", Duration::from_secs(secs));
+        }
+        stats
+    }
+
+    #[test]
+    fn percentile_is_nearest_rank() {
+        let stats = stats_with(&[50, 10, 40, 20, 30, 60, 70, 80, 90, 100]);
+        let secs = |percentile| {
+            stats
+                .percentile(1000, "make", percentile)
+                .unwrap()
+                .as_secs()
+        };
+        assert_eq!(secs(50), 50);
+        assert_eq!(secs(90), 90);
+        assert_eq!(secs(91), 100);
+        assert_eq!(secs(100), 100);
+        // Out of range percentiles are clamped
+        assert_eq!(secs(0), 10);
+        assert_eq!(secs(255), 100);
+    }
+
+    #[test]
+    fn too_few_runs_have_no_percentile() {
+        let stats = stats_with(&[10; MIN_SAMPLES - 1]);
+        assert_eq!(stats.percentile(1000, "make", 50), None);
+        assert_eq!(stats.percentile(1000, "cargo", 50), None);
+        assert_eq!(stats.percentile(1001, "make", 50), None);
+    }
+
+    #[test]
+    fn only_the_latest_runs_count() {
+        let mut stats = stats_with(&[1000; SAMPLES_PER_COMMAND]);
+        for _ in 0..SAMPLES_PER_COMMAND {
+            stats.record(1000, "make", Duration::from_secs(5));
+        }
+        assert_eq!(
+            stats.percentile(1000, "make", 100),
+            Some(Duration::from_secs(5))
+        );
+    }
+}
@@ -1,54 +1,100 @@
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
 use anyhow::{Context, Result};
 use aya::{
-    maps::{MapData, RingBuf},
-    programs::TracePoint,
-    Ebpf,
+    maps::{Map, MapData, RingBuf},
+    programs::{ProgramType, TracePoint},
+    Ebpf, VerifierLogLevel,
 };
 
+/// Tracepoints the eBPF programs attach to, as (category, name)
+const TRACEPOINTS: &[(&str, &str)] = &[
+    ("sched", "sched_process_exec"),
+    ("sched", "sched_process_exit"),
+];
+
+/// The compiled eBPF object, embedded at build time (empty if it wasn't
+/// built yet, see build.rs)
+static EMBEDDED_EBPF: &[u8] = aya::include_bytes_aligned!(env!("NOTIFY_DONE_EBPF_OBJECT"));
+
+/// Load the eBPF object from this file instead, for development
+const EBPF_PATH_ENV: &str = "NOTIFY_DONE_EBPF_PATH";
+
+/// Verifier log detail kept for program load errors: "debug" or "verbose"
+/// (unset = aya's default)
+const VERIFIER_LOG_ENV: &str = "NOTIFY_DONE_VERIFIER_LOG";
+
+/// Ring buffer map the programs send events through
+const EVENTS_MAP: &str = "EVENTS";
+
+/// Possible tracefs mount points, newest first
+const TRACEFS_PATHS: &[&str] = &["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+
+/// Remediation shown when the kernel refuses to load or attach the programs
+const PERMISSION_HINT: &str = "notify-done-daemon needs CAP_BPF and CAP_PERFMON (or root); \
+                               run via the provided systemd unit or `sudo`";
+
 /// Loads and manages the eBPF programs
 pub struct EbpfLoader {
     bpf: Ebpf,
+    /// Maps present in the loaded object
+    map_names: Vec<String>,
+    /// Tracepoints successfully attached, as "category:name"
+    attached: Vec<String>,
+}
+
+/// Runtime statistics for a loaded eBPF program
+#[derive(Debug, Clone)]
+pub struct ProgramStats {
+    pub name: String,
+    /// Number of times the program ran (requires kernel.bpf_stats_enabled)
+    pub run_count: u64,
+    /// Total time spent in the program (requires kernel.bpf_stats_enabled)
+    pub run_time: Duration,
 }
 
 impl EbpfLoader {
     /// Load the eBPF programs from embedded bytecode
     pub fn load() -> Result<Self> {
-        // Load the eBPF bytecode from the build output
-        let bpf_bytes = std::fs::read(concat!(
-            env!("CARGO_MANIFEST_DIR"),
-            "/../target/ebpf/notify-done-ebpf"
-        ))
-        .or_else(|_| {
-            std::fs::read(concat!(
-                env!("CARGO_MANIFEST_DIR"),
-                "/../notify-done-ebpf/target/bpfel-unknown-none/debug/notify-done-ebpf"
-            ))
-        })
-        .or_else(|_| {
-            std::fs::read(concat!(
-                env!("CARGO_MANIFEST_DIR"),
-                "/../notify-done-ebpf/target/bpfel-unknown-none/release/notify-done-ebpf"
-            ))
-        })
-        .context("Failed to load eBPF bytecode - run `cargo xtask build-ebpf` first")?;
-
-        let bpf = Ebpf::load(&bpf_bytes).context("Failed to load eBPF program")?;
+        let bpf_bytes = ebpf_bytecode()?;
+        let mut loader = aya::EbpfLoader::new();
+        if let Some(level) = verifier_log_level()? {
+            loader.verifier_log_level(level);
+        }
+        let bpf = loader
+            .load(&bpf_bytes)
+            .context("Failed to load eBPF program")
+            .map_err(with_permission_hint)?;
 
         // Debug: list all programs and maps
-        log::info!("Loaded BPF object. Programs:");
+        tracing::info!("Loaded BPF object. Programs:");
         for (name, _prog) in bpf.programs() {
-            log::info!("  - program: {}", name);
+            tracing::info!("  - program: {}", name);
         }
-        log::info!("Maps:");
+        tracing::info!("Maps:");
         for (name, _map) in bpf.maps() {
-            log::info!("  - map: {}", name);
+            tracing::info!("  - map: {}", name);
         }
 
-        Ok(Self { bpf })
+        validate_object(&bpf)?;
+
+        let map_names = bpf.maps().map(|(name, _)| name.to_string()).collect();
+
+        Ok(Self {
+            bpf,
+            map_names,
+            attached: Vec::new(),
+        })
     }
 
     /// Attach the tracepoints
     pub fn attach(&mut self) -> Result<()> {
+        self.attach_programs().map_err(with_permission_hint)
+    }
+
+    fn attach_programs(&mut self) -> Result<()> {
         // Attach sched_process_exec tracepoint
         let exec_prog: &mut TracePoint = self
             .bpf
@@ -59,7 +105,8 @@ impl EbpfLoader {
         exec_prog
             .attach("sched", "sched_process_exec")
             .context("Failed to attach sched_process_exec")?;
-        log::info!("Attached sched_process_exec tracepoint");
+        tracing::info!("Attached sched_process_exec tracepoint");
+        self.attached.push("sched:sched_process_exec".into());
 
         // Attach sched_process_exit tracepoint
         let exit_prog: &mut TracePoint = self
@@ -71,7 +118,8 @@ impl EbpfLoader {
         exit_prog
             .attach("sched", "sched_process_exit")
             .context("Failed to attach sched_process_exit")?;
-        log::info!("Attached sched_process_exit tracepoint");
+        tracing::info!("Attached sched_process_exit tracepoint");
+        self.attached.push("sched:sched_process_exit".into());
 
         Ok(())
     }
@@ -80,9 +128,169 @@ impl EbpfLoader {
     pub fn events_ring_buf(&mut self) -> Result<RingBuf<MapData>> {
         let ring_buf = self
             .bpf
-            .take_map("EVENTS")
+            .take_map(EVENTS_MAP)
             .context("Failed to find EVENTS ring buffer")?
             .try_into()?;
         Ok(ring_buf)
     }
+
+    /// Tracepoints attached so far, as "category:name"
+    pub fn attached_tracepoints(&self) -> &[String] {
+        &self.attached
+    }
+
+    /// Names of the maps in the loaded object
+    pub fn map_names(&self) -> &[String] {
+        &self.map_names
+    }
+
+    /// Read run statistics for each loaded program
+    pub fn program_stats(&self) -> Vec<ProgramStats> {
+        self.bpf
+            .programs()
+            .filter_map(|(name, prog)| {
+                let info = prog.info().ok()?;
+                Some(ProgramStats {
+                    name: name.to_string(),
+                    run_count: info.run_count(),
+                    run_time: info.run_time(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// The embedded eBPF object, or the file named by NOTIFY_DONE_EBPF_PATH
+fn ebpf_bytecode() -> Result<Cow<'static, [u8]>> {
+    if let Some(path) = std::env::var_os(EBPF_PATH_ENV) {
+        let bytes = std::fs::read(&path).with_context(|| {
+            format!(
+                "Failed to read eBPF object {} (from {})",
+                Path::new(&path).display(),
+                EBPF_PATH_ENV
+            )
+        })?;
+        return Ok(Cow::Owned(bytes));
+    }
+
+    if EMBEDDED_EBPF.is_empty() {
+        anyhow::bail!(
+            "This daemon was built without its eBPF object; run `cargo xtask build-ebpf` \
+             and rebuild it, or set {} to the object",
+            EBPF_PATH_ENV
+        );
+    }
+    Ok(Cow::Borrowed(EMBEDDED_EBPF))
+}
+
+/// Verifier log level requested through NOTIFY_DONE_VERIFIER_LOG; the log
+/// is part of the error when the kernel rejects a program
+fn verifier_log_level() -> Result<Option<VerifierLogLevel>> {
+    let Ok(level) = std::env::var(VERIFIER_LOG_ENV) else {
+        return Ok(None);
+    };
+    match level.as_str() {
+        "debug" => Ok(Some(VerifierLogLevel::DEBUG | VerifierLogLevel::STATS)),
+        "verbose" => Ok(Some(
+            VerifierLogLevel::VERBOSE | VerifierLogLevel::DEBUG | VerifierLogLevel::STATS,
+        )),
+        other => anyhow::bail!(
+            "Invalid {} {:?}, expected \"debug\" or \"verbose\"",
+            VERIFIER_LOG_ENV,
+            other
+        ),
+    }
+}
+
+/// Check the loaded object has the programs and maps this daemon expects,
+/// listing everything missing or mistyped at once (a stale or mismatched
+/// build otherwise only fails at the first lookup)
+fn validate_object(bpf: &Ebpf) -> Result<()> {
+    let mut problems = Vec::new();
+
+    // Each tracepoint is handled by a program of the same name
+    for (_, name) in TRACEPOINTS {
+        match bpf.programs().find(|(prog, _)| prog == name) {
+            None => problems.push(format!("program {} is missing", name)),
+            Some((_, prog)) if prog.prog_type() != ProgramType::TracePoint => {
+                problems.push(format!("program {} is not a tracepoint", name))
+            }
+            Some(_) => {}
+        }
+    }
+    match bpf.maps().find(|(map, _)| *map == EVENTS_MAP) {
+        None => problems.push(format!("map {} is missing", EVENTS_MAP)),
+        Some((_, Map::RingBuf(_))) => {}
+        Some(_) => problems.push(format!("map {} is not a ring buffer", EVENTS_MAP)),
+    }
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+    let programs: Vec<&str> = bpf.programs().map(|(name, _)| name).collect();
+    let maps: Vec<&str> = bpf.maps().map(|(name, _)| name).collect();
+    anyhow::bail!(
+        "eBPF object doesn't match this daemon (stale build? run `cargo xtask build-ebpf`): {}\n\
+         present programs: [{}], maps: [{}]",
+        problems.join("; "),
+        programs.join(", "),
+        maps.join(", ")
+    )
+}
+
+/// Add the capability remediation hint if the error was caused by EPERM/EACCES
+fn with_permission_hint(err: anyhow::Error) -> anyhow::Error {
+    let denied = err.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|io| matches!(io.raw_os_error(), Some(libc::EPERM) | Some(libc::EACCES)))
+    });
+    if denied {
+        err.context(PERMISSION_HINT)
+    } else {
+        err
+    }
+}
+
+/// Verify tracefs is mounted and the required tracepoints exist
+pub fn check_tracepoints() -> Result<()> {
+    let tracefs = find_tracefs()?;
+
+    let missing: Vec<String> = TRACEPOINTS
+        .iter()
+        .filter(|(category, name)| !tracefs.join("events").join(category).join(name).exists())
+        .map(|(category, name)| format!("{}:{}", category, name))
+        .collect();
+
+    if !missing.is_empty() {
+        anyhow::bail!(
+            "Required tracepoints not found under {}: {}",
+            tracefs.display(),
+            missing.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Locate the tracefs mount point
+fn find_tracefs() -> Result<PathBuf> {
+    for path in TRACEFS_PATHS {
+        let events = Path::new(path).join("events");
+        match std::fs::metadata(&events) {
+            Ok(_) => return Ok(PathBuf::from(path)),
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                return Err(anyhow::Error::new(e)
+                    .context(format!("Cannot access {}", events.display()))
+                    .context(PERMISSION_HINT));
+            }
+            Err(_) => continue,
+        }
+    }
+
+    anyhow::bail!(
+        "tracefs is not mounted (looked in {}); mount it with \
+         `mount -t tracefs nodev /sys/kernel/tracing`",
+        TRACEFS_PATHS.join(", ")
+    )
 }
@@ -1,15 +1,69 @@
-use std::collections::HashMap;
-use std::time::Duration;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::Result;
 use aya::maps::{MapData, RingBuf};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
 
+use notify_done_common::dedup;
 use notify_done_common::{EventType, ProcessExecEvent, ProcessExitEvent};
 
-use crate::config::{DaemonConfig, EffectiveConfig, UserConfig};
-use crate::notifier::Notifier;
-use crate::process_tracker::{CompletedProcess, ProcessTracker};
-use crate::user_session::SessionDiscovery;
+use crate::clock::{Clock, SystemClock};
+use crate::config::{self, DaemonConfig, EffectiveConfig, ThresholdMode, UserConfig};
+use crate::duration_stats::DurationStats;
+use crate::notifier::{MuteRequest, Notifier, SessionNotReady};
+use crate::otlp::OtlpExporter;
+use crate::process_tracker::{CompletedProcess, ProcessTracker, Tty};
+use crate::rate_limiter::{RateDecision, RateLimiter};
+use crate::repeat_filter::RepeatFilter;
+use crate::statsd::StatsdClient;
+use crate::trace::TraceWriter;
+use crate::uid_file::UidFile;
+use crate::user_session::{SessionDiscovery, UserSession};
+
+/// Delays between attempts for notifications that failed because the
+/// session wasn't ready yet (about 30s in total)
+const RETRY_DELAYS: [Duration; 5] = [
+    Duration::from_secs(1),
+    Duration::from_secs(2),
+    Duration::from_secs(4),
+    Duration::from_secs(8),
+    Duration::from_secs(15),
+];
+
+/// How often the "no session" warning is repeated for a user while their
+/// notifications keep being dropped
+const NO_SESSION_WARN_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Counters describing what the processor has done since startup
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProcessorStats {
+    pub exec_events: u64,
+    pub exit_events: u64,
+    /// Records that were too short or had an unknown type
+    pub malformed_events: u64,
+    pub notifications_sent: u64,
+    pub notifications_failed: u64,
+    /// Notifications given up on after retrying (included in `notifications_failed`)
+    pub notifications_dropped: u64,
+}
+
+/// A notification waiting to be retried
+struct PendingNotification {
+    process: CompletedProcess,
+    /// Attempts made so far
+    attempts: usize,
+    retry_at: Instant,
+}
+
+/// A user whose notifications are dropped for lack of a session
+struct MissingSession {
+    /// Notifications dropped since the last warning
+    dropped: u64,
+    next_warning_at: Instant,
+}
 
 /// Processes events from the eBPF ring buffer
 pub struct EventProcessor {
@@ -18,58 +72,193 @@ pub struct EventProcessor {
     notifier: Notifier,
     config: DaemonConfig,
     user_configs: HashMap<u32, Option<UserConfig>>,
+    /// Commands muted from a notification action, by (uid, comm), with expiry
+    mutes: HashMap<(u32, String), Instant>,
+    mute_rx: UnboundedReceiver<MuteRequest>,
+    /// Recent durations by (uid, comm), for the dynamic threshold
+    duration_stats: DurationStats,
+    /// Dedup windows of recent notifications
+    repeats: RepeatFilter,
+    rate_limiter: Option<RateLimiter>,
+    statsd: Option<StatsdClient>,
+    otlp: Option<OtlpExporter>,
+    clock: Arc<dyn Clock>,
+    /// Notifications that failed because the session wasn't ready yet
+    retry_queue: Vec<PendingNotification>,
+    /// Users without a session, to warn about them once in a while
+    /// instead of for every dropped notification
+    missing_sessions: HashMap<u32, MissingSession>,
+    /// Where live records are dumped for later replay (`--record`)
+    recorder: Option<TraceWriter>,
+    /// Users from `tracked_uids_file`
+    uid_file: Option<UidFile>,
+    /// Claim each notification in the dedup directory shared with `nd`
+    shared_dedup: bool,
+    stats: ProcessorStats,
 }
 
 impl EventProcessor {
     pub fn new(config: DaemonConfig) -> Self {
+        let (mute_tx, mute_rx) = mpsc::unbounded_channel();
+        let mut notifier = Notifier::new(&config);
+        if let Some(secs) = config.mute_action_secs {
+            notifier = notifier.with_mute_action(Duration::from_secs(secs), mute_tx);
+        }
+
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let mut tracker = ProcessTracker::new(1000, clock.clone()) // Keep last 1000 completed processes
+            .with_max_tracked(config.max_tracked_processes)
+            .with_duration_source(config.duration_source);
+        if config.show_parent {
+            tracker = tracker.with_parent_resolution();
+        }
+        if config.suppress_wrapped {
+            tracker = tracker.with_wrapper_detection();
+        }
+        if config.skip_no_tty {
+            tracker = tracker.with_tty_capture();
+        }
+        if !config.notify_cwd_prefixes.is_empty() {
+            tracker = tracker.with_cwd_capture();
+        }
+
+        let statsd = config.statsd_addr.as_deref().and_then(|addr| {
+            StatsdClient::connect(addr)
+                .inspect_err(|e| {
+                    tracing::warn!("StatsD metrics disabled, can't use {}: {}", addr, e)
+                })
+                .ok()
+        });
+        let otlp = config.otlp_endpoint.as_deref().and_then(|endpoint| {
+            OtlpExporter::start(endpoint)
+                .inspect_err(|e| tracing::warn!("OTLP export disabled: {:#}", e))
+                .ok()
+        });
+        let uid_file = config.tracked_uids_file.as_deref().map(UidFile::load);
+
         Self {
-            tracker: ProcessTracker::new(1000), // Keep last 1000 completed processes
+            tracker,
             sessions: SessionDiscovery::new(),
-            notifier: Notifier::new(),
+            notifier,
+            rate_limiter: config.max_notifications_per_minute.map(RateLimiter::new),
+            statsd,
+            otlp,
+            clock,
             config,
             user_configs: HashMap::new(),
+            mutes: HashMap::new(),
+            mute_rx,
+            duration_stats: DurationStats::default(),
+            repeats: RepeatFilter::default(),
+            retry_queue: Vec::new(),
+            missing_sessions: HashMap::new(),
+            recorder: None,
+            uid_file,
+            shared_dedup: false,
+            stats: ProcessorStats::default(),
         }
     }
 
-    /// Process events from the ring buffer
-    pub async fn process_events(&mut self, ring_buf: &mut RingBuf<MapData>) -> Result<()> {
-        while let Some(event) = ring_buf.next() {
+    /// Also write every live ring-buffer record to `recorder`
+    pub fn with_recorder(mut self, recorder: TraceWriter) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Skip exits `nd` already notified for, and claim the others, in
+    /// the shared dedup directory
+    pub fn with_shared_dedup(mut self) -> Self {
+        self.shared_dedup = true;
+        self
+    }
+
+    /// Process up to `events_per_batch` events from the ring buffer,
+    /// returning whether that budget ran out with events possibly left over
+    pub async fn process_events(&mut self, ring_buf: &mut RingBuf<MapData>) -> Result<bool> {
+        let budget = self.config.events_per_batch.max(1);
+        let mut handled = 0;
+        while handled < budget {
+            let Some(event) = ring_buf.next() else {
+                self.flush_recorder();
+                return Ok(false);
+            };
+            handled += 1;
+
             let data: &[u8] = &event;
-            if data.is_empty() {
-                continue;
+            if let Some(recorder) = &mut self.recorder {
+                if let Err(e) = recorder.write(data) {
+                    tracing::warn!("Failed to record event, recording stopped: {}", e);
+                    self.recorder = None;
+                }
             }
+            self.handle_record(data).await;
+        }
+        self.flush_recorder();
 
-            // First byte is event type
-            let event_type = data[0];
+        Ok(true)
+    }
 
-            match event_type {
-                t if t == EventType::Exec as u8 => {
-                    if data.len() >= size_of::<ProcessExecEvent>() {
-                        let exec_event: ProcessExecEvent =
-                            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const _) };
-                        self.handle_exec(&exec_event);
-                    }
-                }
-                t if t == EventType::Exit as u8 => {
-                    if data.len() >= size_of::<ProcessExitEvent>() {
-                        let exit_event: ProcessExitEvent =
-                            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const _) };
-                        self.handle_exit(&exit_event).await;
-                    }
+    fn flush_recorder(&mut self) {
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(e) = recorder.flush() {
+                tracing::warn!("Failed to record events, recording stopped: {}", e);
+                self.recorder = None;
+            }
+        }
+    }
+
+    /// Feed the records of a recorded trace through the same path as live
+    /// ring-buffer events
+    pub async fn replay(&mut self, records: &[Vec<u8>]) {
+        for record in records {
+            self.handle_record(record).await;
+        }
+    }
+
+    /// Decode one raw ring-buffer record and dispatch it
+    async fn handle_record(&mut self, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+
+        // First byte is event type
+        let event_type = data[0];
+
+        match event_type {
+            t if t == EventType::Exec as u8 => {
+                if data.len() >= size_of::<ProcessExecEvent>() {
+                    let exec_event: ProcessExecEvent =
+                        unsafe { std::ptr::read_unaligned(data.as_ptr() as *const _) };
+                    self.stats.exec_events += 1;
+                    self.handle_exec(&exec_event);
+                } else {
+                    self.stats.malformed_events += 1;
                 }
-                _ => {
-                    log::warn!("Unknown event type: {}", event_type);
+            }
+            t if t == EventType::Exit as u8 => {
+                if data.len() >= size_of::<ProcessExitEvent>() {
+                    let exit_event: ProcessExitEvent =
+                        unsafe { std::ptr::read_unaligned(data.as_ptr() as *const _) };
+                    self.stats.exit_events += 1;
+                    self.handle_exit(&exit_event).await;
+                } else {
+                    self.stats.malformed_events += 1;
                 }
             }
+            _ => {
+                self.stats.malformed_events += 1;
+                tracing::warn!("Unknown event type: {}", event_type);
+            }
         }
-
-        Ok(())
     }
 
     /// Handle an exec event
     fn handle_exec(&mut self, event: &ProcessExecEvent) {
         if self.config.debug {
-            log::debug!("Exec event: {:?}", event);
+            tracing::debug!("Exec event: {:?}", event);
+        }
+        if !self.config.tracks_path(&event.filename_str()) {
+            return;
         }
         self.tracker.on_exec(event);
     }
@@ -77,7 +266,7 @@ impl EventProcessor {
     /// Handle an exit event
     async fn handle_exit(&mut self, event: &ProcessExitEvent) {
         if self.config.debug {
-            log::debug!("Exit event: {:?}", event);
+            tracing::debug!("Exit event: {:?}", event);
         }
 
         match self.tracker.on_exit(event) {
@@ -85,7 +274,7 @@ impl EventProcessor {
                 self.maybe_notify(&completed).await;
             }
             None => {
-                log::debug!(
+                tracing::debug!(
                     "Exit event for untracked process: tgid={} comm={}",
                     event.tgid,
                     event.comm_str()
@@ -96,18 +285,92 @@ impl EventProcessor {
 
     /// Check if we should send a notification and do so if needed
     async fn maybe_notify(&mut self, process: &CompletedProcess) {
+        if !self.should_notify_uid(process.uid) {
+            tracing::debug!(
+                "Skipping notification for {} (uid {} filtered by config)",
+                process.comm,
+                process.uid
+            );
+            return;
+        }
+
+        if process.wrapped {
+            tracing::debug!(
+                "Skipping notification for {} (pid {}), nd notifies for it",
+                process.comm,
+                process.pid
+            );
+            return;
+        }
+
+        if self.config.skip_no_tty && process.tty == Tty::Detached {
+            tracing::debug!(
+                "Skipping notification for {} (pid {}), it had no terminal",
+                process.comm,
+                process.pid
+            );
+            return;
+        }
+
+        if self.config.is_junk_comm(&process.comm) {
+            tracing::debug!(
+                "Skipping notification for junk command {:?} (pid {})",
+                process.comm,
+                process.pid
+            );
+            return;
+        }
+
         // Get or load user config
         let user_config = self.get_user_config(process.uid);
-        let effective = EffectiveConfig::new(&self.config, user_config.as_ref());
+        let mut effective = EffectiveConfig::new(&self.config, user_config.as_ref());
+        effective.muted = self.active_mutes(process.uid);
+        // Compared against the runs before this one
+        if effective.threshold_mode == ThresholdMode::Dynamic {
+            effective.dynamic_threshold = self.duration_stats.percentile(
+                process.uid,
+                &process.comm,
+                self.config.dynamic_percentile,
+            );
+        }
+        self.duration_stats
+            .record(process.uid, &process.comm, process.duration);
 
         // Check if we should notify
         let duration_secs = process.duration.as_secs();
-        if !effective.should_notify(&process.comm, duration_secs) {
-            log::debug!(
-                "Skipping notification for {} (duration={}s, threshold={}s)",
-                process.comm,
+        let success = effective.is_success(&process.comm, process.exit_code);
+        if let Some(statsd) = &self.statsd {
+            statsd.completion(&process.comm, process.duration, success);
+        }
+        if let Some(otlp) = &self.otlp {
+            otlp.completion(process, success);
+        }
+        if !effective.should_notify(
+            &process.comm,
+            process.cwd.as_deref(),
+            process.duration,
+            success,
+        ) {
+            tracing::debug!(
+                uid = process.uid,
+                comm = %process.comm,
                 duration_secs,
-                effective.threshold_seconds
+                threshold_secs = effective.threshold_seconds,
+                dynamic_threshold = ?effective.dynamic_threshold,
+                "Skipping notification"
+            );
+            return;
+        }
+
+        // Drop repeats of a command that notified recently
+        if self
+            .repeats
+            .is_repeat(process.uid, &process.comm, self.clock.now())
+        {
+            tracing::debug!(
+                uid = process.uid,
+                comm = %process.comm,
+                "Repeat within dedup window, skipping notification"
             );
             return;
         }
@@ -116,47 +379,315 @@ impl EventProcessor {
         let session = match self.sessions.get_session(process.uid) {
             Some(s) => s.clone(),
             None => {
-                log::warn!(
-                    "No session found for uid {}, skipping notification",
-                    process.uid
-                );
+                self.warn_missing_session(process);
                 return;
             }
         };
+        if let Some(missing) = self.missing_sessions.remove(&process.uid) {
+            tracing::info!(
+                uid = process.uid,
+                dropped_since_warning = missing.dropped,
+                "Session found, notifying again"
+            );
+        }
+
+        // Enforce the per-user rate limit
+        if let Some(limiter) = &mut self.rate_limiter {
+            match limiter.check(process.uid, self.clock.now()) {
+                RateDecision::Allow { suppressed: 0 } => {}
+                RateDecision::Allow { suppressed } => {
+                    if let Err(e) = self.notifier.notify_suppressed(&session, suppressed).await {
+                        tracing::error!(
+                            uid = process.uid,
+                            error = %e,
+                            "Failed to send suppression summary"
+                        );
+                    }
+                }
+                RateDecision::Deny => {
+                    tracing::debug!(
+                        uid = process.uid,
+                        comm = %process.comm,
+                        "Rate limit reached, dropping notification"
+                    );
+                    return;
+                }
+            }
+        }
+
+        // `nd` may have seen the same exit before its wrapper was noticed
+        if !self.claim_notification(process) {
+            tracing::debug!(
+                uid = process.uid,
+                comm = %process.comm,
+                pid = process.pid,
+                "nd already notified, skipping notification"
+            );
+            return;
+        }
 
         // Send notification
-        if let Err(e) = self.notifier.notify(&session, process).await {
-            log::error!(
-                "Failed to send notification to user {}: {}",
-                session.username,
-                e
+        self.send(process, &session, &effective, 0).await;
+    }
+
+    fn should_notify_uid(&self, uid: u32) -> bool {
+        self.config
+            .should_notify_uid(uid, self.uid_file.as_ref().map(UidFile::uids))
+    }
+
+    /// Pick up changes to `tracked_uids_file`
+    pub fn refresh_uid_file(&mut self) {
+        if let Some(file) = &mut self.uid_file {
+            file.refresh();
+        }
+    }
+
+    /// Claim the notification for `process` against `nd`; false if it got
+    /// there first
+    fn claim_notification(&self, process: &CompletedProcess) -> bool {
+        if !self.shared_dedup {
+            return true;
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        dedup::claim(process.uid, process.tgid, &process.comm, now)
+            .inspect_err(|e| tracing::debug!("Failed to claim notification: {}", e))
+            .unwrap_or(true)
+    }
+
+    /// Send a notification, queueing it for a retry if the user's session
+    /// isn't ready yet
+    async fn send(
+        &mut self,
+        process: &CompletedProcess,
+        session: &UserSession,
+        effective: &EffectiveConfig,
+        attempts: usize,
+    ) {
+        match self.notifier.notify(session, process, effective).await {
+            Ok(()) => {
+                self.stats.notifications_sent += 1;
+                if let Some(window) = effective.dedup_window_seconds {
+                    self.repeats.record(
+                        process.uid,
+                        &process.comm,
+                        self.clock.now(),
+                        Duration::from_secs(window),
+                    );
+                }
+                tracing::info!(
+                    uid = process.uid,
+                    user = %session.username,
+                    comm = %process.comm,
+                    duration_secs = process.duration.as_secs(),
+                    exit_code = process.exit_code,
+                    "Sent notification"
+                );
+            }
+            Err(e) if e.is::<SessionNotReady>() && attempts < RETRY_DELAYS.len() => {
+                // The cached session may have been discovered too early
+                self.sessions.invalidate(process.uid);
+                let delay = RETRY_DELAYS[attempts];
+                tracing::debug!(
+                    uid = process.uid,
+                    comm = %process.comm,
+                    error = %e,
+                    retry_in_secs = delay.as_secs(),
+                    "Session not ready, will retry notification"
+                );
+                self.retry_queue.push(PendingNotification {
+                    process: process.clone(),
+                    attempts: attempts + 1,
+                    retry_at: self.clock.now() + delay,
+                });
+            }
+            Err(e) => {
+                self.stats.notifications_failed += 1;
+                if attempts > 0 {
+                    self.stats.notifications_dropped += 1;
+                }
+                tracing::error!(
+                    uid = process.uid,
+                    user = %session.username,
+                    comm = %process.comm,
+                    attempts = attempts + 1,
+                    error = %e,
+                    "Failed to send notification"
+                );
+            }
+        }
+    }
+
+    /// Retry queued notifications that are due
+    pub async fn retry_notifications(&mut self) {
+        if self.retry_queue.is_empty() {
+            return;
+        }
+
+        let now = self.clock.now();
+        let (due, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.retry_queue)
+            .into_iter()
+            .partition(|pending| pending.retry_at <= now);
+        self.retry_queue = waiting;
+
+        for pending in due {
+            let process = pending.process;
+            let Some(session) = self.sessions.get_session(process.uid).cloned() else {
+                // The user logged out in the meantime; no point retrying
+                self.stats.notifications_failed += 1;
+                self.stats.notifications_dropped += 1;
+                tracing::warn!(
+                    uid = process.uid,
+                    comm = %process.comm,
+                    "Session gone, dropping queued notification"
+                );
+                continue;
+            };
+
+            let user_config = self.get_user_config(process.uid);
+            let mut effective = EffectiveConfig::new(&self.config, user_config.as_ref());
+            effective.muted = self.active_mutes(process.uid);
+            self.send(&process, &session, &effective, pending.attempts)
+                .await;
+        }
+    }
+
+    /// Log a notification dropped for lack of a session, warning on the
+    /// first one for a user and then at most every NO_SESSION_WARN_INTERVAL
+    fn warn_missing_session(&mut self, process: &CompletedProcess) {
+        let now = self.clock.now();
+        let missing = self
+            .missing_sessions
+            .entry(process.uid)
+            .or_insert(MissingSession {
+                dropped: 0,
+                next_warning_at: now,
+            });
+        missing.dropped += 1;
+
+        if now < missing.next_warning_at {
+            tracing::debug!(
+                uid = process.uid,
+                comm = %process.comm,
+                "No session found, skipping notification"
             );
-        } else {
-            log::info!(
-                "Sent notification to {} for '{}' ({}s, exit {})",
-                session.username,
-                process.comm,
-                duration_secs,
-                process.exit_code
+            return;
+        }
+        tracing::warn!(
+            uid = process.uid,
+            comm = %process.comm,
+            dropped = missing.dropped,
+            "No session found, skipping notifications (warning again in {}s if this continues)",
+            NO_SESSION_WARN_INTERVAL.as_secs()
+        );
+        missing.dropped = 0;
+        missing.next_warning_at = now + NO_SESSION_WARN_INTERVAL;
+    }
+
+    /// Tell the user of the active session about a daemon start or stop,
+    /// if enabled; failures are only logged
+    pub async fn notify_lifecycle(&mut self, body: &str) {
+        if !self.config.notify_on_lifecycle {
+            return;
+        }
+
+        let Some(uid) = self
+            .sessions
+            .active_uid()
+            .filter(|&uid| uid >= self.config.min_uid && self.should_notify_uid(uid))
+        else {
+            tracing::debug!("No active session to notify: {}", body);
+            return;
+        };
+        let Some(session) = self.sessions.get_session(uid).cloned() else {
+            tracing::debug!(uid, "No session found for lifecycle notification");
+            return;
+        };
+
+        if let Err(e) = self.notifier.notify_lifecycle(&session, body).await {
+            tracing::warn!(uid, error = %e, "Failed to send lifecycle notification");
+        }
+    }
+
+    /// Commands currently muted for a user, picking up any new mute requests
+    fn active_mutes(&mut self, uid: u32) -> HashSet<String> {
+        while let Ok(request) = self.mute_rx.try_recv() {
+            tracing::info!(
+                "Muting '{}' for uid {} for {}s",
+                request.comm,
+                request.uid,
+                request.duration.as_secs()
+            );
+            self.mutes.insert(
+                (request.uid, request.comm),
+                self.clock.now() + request.duration,
             );
         }
+
+        let now = self.clock.now();
+        self.mutes.retain(|_, expires| *expires > now);
+        self.mutes
+            .keys()
+            .filter(|(muted_uid, _)| *muted_uid == uid)
+            .map(|(_, comm)| comm.clone())
+            .collect()
     }
 
     /// Get user config, loading if necessary
     fn get_user_config(&mut self, uid: u32) -> Option<UserConfig> {
-        self.user_configs.entry(uid).or_insert_with(|| {
-            let config = match UserConfig::load_for_uid(uid) {
+        if !self.user_configs.contains_key(&uid) {
+            let config = match config::load_user_config(uid) {
                 Ok(c) => c,
                 Err(e) => {
-                    log::debug!("Failed to load user config for uid {}: {}", uid, e);
+                    tracing::debug!("Failed to load user config for uid {}: {}", uid, e);
                     None
                 }
             };
-            config
-        });
+            // Commands already running have no cwd recorded, and pass the
+            // filter
+            if config
+                .as_ref()
+                .is_some_and(|c| !c.notify_cwd_prefixes.is_empty())
+            {
+                self.tracker.enable_cwd_capture();
+            }
+            self.user_configs.insert(uid, config);
+        }
         self.user_configs.get(&uid).cloned().flatten()
     }
 
+    /// Counters since startup
+    pub fn stats(&self) -> ProcessorStats {
+        self.stats
+    }
+
+    /// Number of processes currently being tracked
+    pub fn active_count(&self) -> usize {
+        self.tracker.active_count()
+    }
+
+    /// Number of notifications waiting to be retried
+    pub fn pending_retries(&self) -> usize {
+        self.retry_queue.len()
+    }
+
+    /// Number of completed processes kept in history
+    pub fn history_len(&self) -> usize {
+        self.tracker.history().len()
+    }
+
+    /// Save in-flight processes so they survive a daemon restart
+    pub fn save_state(&self, path: &Path) -> Result<()> {
+        self.tracker.save_state(path)
+    }
+
+    /// Restore in-flight processes saved by a previous daemon
+    pub fn restore_state(&mut self, path: &Path) -> Result<usize> {
+        self.tracker.restore_state(path)
+    }
+
     /// Clean up stale processes periodically
     pub fn cleanup(&mut self) {
         // Remove processes that have been running for more than 24 hours without exit
@@ -167,5 +698,12 @@ impl EventProcessor {
 
         // Clear user config cache
         self.user_configs.clear();
+
+        let now = self.clock.now();
+        self.repeats.cleanup(now);
+
+        if let Some(limiter) = &mut self.rate_limiter {
+            limiter.cleanup(now);
+        }
     }
 }
@@ -0,0 +1,70 @@
+use std::ffi::CStr;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::ebpf_loader;
+
+/// Oldest kernel with BPF ring buffer support
+const MIN_KERNEL_VERSION: (u32, u32) = (5, 8);
+
+/// Kernel BTF exposed by CONFIG_DEBUG_INFO_BTF
+const VMLINUX_BTF_PATH: &str = "/sys/kernel/btf/vmlinux";
+
+/// Check that the running kernel supports everything the eBPF programs use,
+/// reporting every problem at once
+pub fn check_compatibility() -> Result<()> {
+    let release = kernel_release().unwrap_or_else(|| "unknown".into());
+    let mut problems = Vec::new();
+
+    match parse_version(&release) {
+        Some(version) if version < MIN_KERNEL_VERSION => problems.push(format!(
+            "kernel {}.{}+ is required for the BPF ring buffer",
+            MIN_KERNEL_VERSION.0, MIN_KERNEL_VERSION.1
+        )),
+        Some(_) => {}
+        None => tracing::warn!(release = %release, "Could not parse kernel version"),
+    }
+
+    if let Err(e) = ebpf_loader::check_tracepoints() {
+        problems.push(format!("{:#}", e));
+    }
+
+    // The current programs only use tracepoint arguments, so missing BTF
+    // is not fatal yet
+    if !Path::new(VMLINUX_BTF_PATH).exists() {
+        tracing::warn!(
+            "Kernel BTF not found at {} (kernel built without CONFIG_DEBUG_INFO_BTF?)",
+            VMLINUX_BTF_PATH
+        );
+    }
+
+    if !problems.is_empty() {
+        anyhow::bail!(
+            "Kernel {} is not compatible with notify-done:\n  - {}",
+            release,
+            problems.join("\n  - ")
+        );
+    }
+
+    tracing::info!(release = %release, "Kernel compatibility check passed");
+    Ok(())
+}
+
+/// Running kernel release, as reported by uname
+fn kernel_release() -> Option<String> {
+    let mut uts: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return None;
+    }
+    let release = unsafe { CStr::from_ptr(uts.release.as_ptr()) };
+    Some(release.to_string_lossy().into_owned())
+}
+
+/// Parse "6.8.0-45-generic" into (6, 8)
+fn parse_version(release: &str) -> Option<(u32, u32)> {
+    let mut parts = release.split(|c: char| !c.is_ascii_digit());
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
@@ -1,75 +1,357 @@
+mod clock;
 mod config;
+mod duration_stats;
 mod ebpf_loader;
 mod event_processor;
+mod kernel_check;
 mod notifier;
+mod otlp;
 mod process_tracker;
+mod rate_limiter;
+mod recent_log;
+mod repeat_filter;
+mod statsd;
+mod trace;
+mod uid_file;
 mod user_session;
 
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use tokio::io::unix::AsyncFd;
 use tokio::signal;
-use tokio::time::interval;
+use tokio::signal::unix::{signal as unix_signal, SignalKind};
+use tokio::time::{interval, MissedTickBehavior};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter, Layer};
 
-use config::DaemonConfig;
+use notify_done_common::dedup;
+use notify_done_common::health::{
+    DaemonHealth, HEALTH_INTERVAL_SECS, HEALTH_PATH, RECENT_LOG_PATH,
+};
+
+use config::{DaemonConfig, LogFormat};
 use ebpf_loader::EbpfLoader;
 use event_processor::EventProcessor;
+use recent_log::RecentLog;
+use trace::TraceWriter;
+
+/// How often queued notification retries are checked while any are pending
+const RETRY_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// In-flight processes saved across restarts (systemd StateDirectory)
+const STATE_PATH: &str = "/var/lib/notify-done/processes.json";
+
+/// Command-line flags; the daemon is normally configured through its
+/// config file, these are debugging aids
+#[derive(Default)]
+struct Args {
+    /// Process the records of this trace instead of attaching to the kernel
+    replay: Option<PathBuf>,
+    /// Dump live ring-buffer records to this trace
+    record: Option<PathBuf>,
+}
+
+impl Args {
+    fn parse() -> Result<Self> {
+        let mut args = Self::default();
+        let mut iter = std::env::args_os().skip(1);
+        while let Some(arg) = iter.next() {
+            let slot = match arg.to_str() {
+                Some("--replay") => &mut args.replay,
+                Some("--record") => &mut args.record,
+                _ => bail!(
+                    "Unknown argument {:?} (usage: notify-done-daemon [--replay FILE | --record FILE])",
+                    arg
+                ),
+            };
+            let Some(path) = iter.next() else {
+                bail!("{:?} needs a file argument", arg);
+            };
+            *slot = Some(PathBuf::from(path));
+        }
+        if args.replay.is_some() && args.record.is_some() {
+            bail!("--replay and --record can't be combined");
+        }
+        Ok(args)
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize logging
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
-        .format_timestamp_secs()
-        .init();
-
-    log::info!("notify-done daemon starting");
+    let started_at = unix_now();
+    let args = Args::parse()?;
 
     // Load configuration
     let config = DaemonConfig::load().context("Failed to load configuration")?;
-    log::info!(
+
+    // Initialize logging (RUST_LOG overrides the configured level)
+    let filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(&config.log_level))
+        .context("Invalid log_level in configuration")?;
+    let fmt_layer = match config.log_format {
+        LogFormat::Text => fmt::layer().boxed(),
+        LogFormat::Json => fmt::layer().json().boxed(),
+    };
+    // The recent log has its own filter so it sees debug lines even when
+    // the journal doesn't
+    let recent_log = config.recent_log_lines.map(RecentLog::new);
+    tracing_subscriber::registry()
+        .with(fmt_layer.with_filter(filter))
+        .with(
+            recent_log
+                .clone()
+                .map(|log| log.with_filter(EnvFilter::new("notify_done_daemon=debug"))),
+        )
+        .init();
+
+    tracing::info!("notify-done daemon starting");
+    tracing::info!(
         "Configuration: threshold={}s",
         config.threshold_seconds
     );
 
+    if let Some(path) = &args.replay {
+        return replay(config, path).await;
+    }
+
+    // Fail fast on kernels missing features we depend on
+    kernel_check::check_compatibility().context("Kernel compatibility check failed")?;
+
     // Load and attach eBPF programs
     let mut ebpf = EbpfLoader::load().context("Failed to load eBPF programs")?;
     ebpf.attach().context("Failed to attach eBPF programs")?;
 
-    // Get the events ring buffer
-    let mut ring_buf = ebpf.events_ring_buf()?;
+    // Get the events ring buffer; its fd becomes readable when the eBPF
+    // programs submit records
+    let mut ring_buf = AsyncFd::new(ebpf.events_ring_buf()?)
+        .context("Failed to register the ring buffer with the runtime")?;
 
     // Create event processor
     let mut processor = EventProcessor::new(config);
+    if let Some(path) = &args.record {
+        processor = processor.with_recorder(TraceWriter::create(path)?);
+        tracing::info!("Recording ring-buffer records to {}", path.display());
+    }
+    match dedup::create_dir() {
+        Ok(()) => processor = processor.with_shared_dedup(),
+        Err(e) => tracing::warn!(
+            "Can't create {}, nd and the daemon may both notify: {}",
+            dedup::DEDUP_DIR,
+            e
+        ),
+    }
+
+    // Pick up processes that started before a restart
+    match processor.restore_state(Path::new(STATE_PATH)) {
+        Ok(0) => {}
+        Ok(count) => tracing::info!("Restored {} in-flight processes", count),
+        Err(e) => tracing::warn!("Failed to restore process state: {:#}", e),
+    }
 
-    log::info!("notify-done daemon running");
+    tracing::info!("notify-done daemon running");
+    processor
+        .notify_lifecycle("Started, tracking commands")
+        .await;
 
     // Set up cleanup interval (every hour)
     let mut cleanup_interval = interval(Duration::from_secs(3600));
 
+    // Retry queued notifications; a timer made inside the loop would be
+    // reset by every other branch firing
+    let mut retry_interval = interval(RETRY_CHECK_INTERVAL);
+    retry_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    // Refresh the health snapshot for `nd status`
+    let mut health_interval = interval(Duration::from_secs(HEALTH_INTERVAL_SECS));
+
+    // systemd stops the service with SIGTERM
+    let mut sigterm =
+        unix_signal(SignalKind::terminate()).context("Failed to install SIGTERM handler")?;
+
+    // `kill -USR1` dumps diagnostics to the log
+    let mut sigusr1 =
+        unix_signal(SignalKind::user_defined1()).context("Failed to install SIGUSR1 handler")?;
+
     // Main event loop
-    loop {
+    let reason = loop {
         tokio::select! {
-            // Process events from ring buffer
-            _ = tokio::time::sleep(Duration::from_millis(100)) => {
-                if let Err(e) = processor.process_events(&mut ring_buf).await {
-                    log::error!("Error processing events: {}", e);
+            // Process events as soon as the ring buffer has some
+            guard = ring_buf.readable_mut() => {
+                let mut guard = guard.context("Failed to wait for ring buffer events")?;
+                match processor.process_events(guard.get_inner_mut()).await {
+                    // Stay ready while a flood exceeds the per-pass budget,
+                    // so the rest is handled on the next turn of the loop
+                    Ok(true) => {}
+                    Ok(false) => guard.clear_ready(),
+                    Err(e) => {
+                        tracing::error!("Error processing events: {}", e);
+                        guard.clear_ready();
+                    }
                 }
             }
 
+            // Queued notifications, checked only while there are some
+            _ = retry_interval.tick(), if processor.pending_retries() > 0 => {
+                processor.retry_notifications().await;
+            }
+
             // Periodic cleanup
             _ = cleanup_interval.tick() => {
-                log::debug!("Running periodic cleanup");
+                tracing::debug!("Running periodic cleanup");
                 processor.cleanup();
+                // Also checkpoint, so a crash loses at most an hour of state
+                if let Err(e) = processor.save_state(Path::new(STATE_PATH)) {
+                    tracing::warn!("Failed to save process state: {:#}", e);
+                }
+            }
+
+            // Health snapshot
+            _ = health_interval.tick() => {
+                processor.refresh_uid_file();
+                if let Err(e) = write_health(Path::new(HEALTH_PATH), started_at, &processor) {
+                    tracing::debug!("Failed to write health snapshot: {:#}", e);
+                }
+                if let Some(log) = &recent_log {
+                    if let Err(e) = write_json(Path::new(RECENT_LOG_PATH), &log.snapshot()) {
+                        tracing::debug!("Failed to write recent log: {:#}", e);
+                    }
+                }
+            }
+
+            // Diagnostics dump
+            _ = sigusr1.recv() => {
+                log_diagnostics(&ebpf, &processor);
             }
 
             // Handle shutdown signals
             _ = signal::ctrl_c() => {
-                log::info!("Received SIGINT, shutting down");
-                break;
+                tracing::info!("Received SIGINT, shutting down");
+                break "SIGINT";
+            }
+            _ = sigterm.recv() => {
+                tracing::info!("Received SIGTERM, shutting down");
+                break "SIGTERM";
             }
         }
+    };
+
+    if let Err(e) = processor.save_state(Path::new(STATE_PATH)) {
+        tracing::warn!("Failed to save process state: {:#}", e);
     }
+    // A stale snapshot would make a stopped daemon look alive
+    let _ = std::fs::remove_file(HEALTH_PATH);
+    let _ = std::fs::remove_file(RECENT_LOG_PATH);
+
+    processor
+        .notify_lifecycle(&format!(
+            "Stopped ({}), commands are no longer tracked",
+            reason
+        ))
+        .await;
 
-    log::info!("notify-done daemon stopped");
+    tracing::info!("notify-done daemon stopped");
     Ok(())
 }
+
+/// Run a recorded trace through a fresh event processor, then exit
+///
+/// Nothing is attached to the kernel and no daemon state is read or
+/// written, so this can run next to a live daemon without disturbing it.
+async fn replay(config: DaemonConfig, path: &Path) -> Result<()> {
+    let records = trace::read_trace(path)?;
+    tracing::info!(
+        "Replaying {} records from {}",
+        records.len(),
+        path.display()
+    );
+
+    let mut processor = EventProcessor::new(config);
+    processor.replay(&records).await;
+    processor.retry_notifications().await;
+
+    let stats = processor.stats();
+    tracing::info!(
+        exec_events = stats.exec_events,
+        exit_events = stats.exit_events,
+        malformed_events = stats.malformed_events,
+        notifications_sent = stats.notifications_sent,
+        notifications_failed = stats.notifications_failed,
+        notifications_dropped = stats.notifications_dropped,
+        active_processes = processor.active_count(),
+        pending_retries = processor.pending_retries(),
+        "Replay finished"
+    );
+    Ok(())
+}
+
+/// Write the uptime and counters read by `nd status`
+fn write_health(path: &Path, started_at: u64, processor: &EventProcessor) -> Result<()> {
+    let stats = processor.stats();
+    let health = DaemonHealth {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        started_at,
+        updated_at: unix_now(),
+        active_processes: processor.active_count(),
+        history_len: processor.history_len(),
+        exec_events: stats.exec_events,
+        exit_events: stats.exit_events,
+        malformed_events: stats.malformed_events,
+        notifications_sent: stats.notifications_sent,
+        notifications_failed: stats.notifications_failed,
+        notifications_dropped: stats.notifications_dropped,
+        pending_retries: processor.pending_retries(),
+    };
+    write_json(path, &health)
+}
+
+/// Write `value` as JSON, then rename it into place so readers never see
+/// a partial file
+fn write_json(path: &Path, value: &impl Serialize) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, serde_json::to_vec(value)?)
+        .with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Log the state of the eBPF programs and the event processor
+fn log_diagnostics(ebpf: &EbpfLoader, processor: &EventProcessor) {
+    tracing::info!(
+        tracepoints = ?ebpf.attached_tracepoints(),
+        maps = ?ebpf.map_names(),
+        "eBPF diagnostics"
+    );
+    for prog in ebpf.program_stats() {
+        tracing::info!(
+            program = %prog.name,
+            run_count = prog.run_count,
+            run_time_ns = prog.run_time.as_nanos() as u64,
+            "eBPF program stats"
+        );
+    }
+
+    let stats = processor.stats();
+    tracing::info!(
+        active_processes = processor.active_count(),
+        history_len = processor.history_len(),
+        pending_retries = processor.pending_retries(),
+        exec_events = stats.exec_events,
+        exit_events = stats.exit_events,
+        malformed_events = stats.malformed_events,
+        notifications_sent = stats.notifications_sent,
+        notifications_failed = stats.notifications_failed,
+        notifications_dropped = stats.notifications_dropped,
+        "Event processor diagnostics"
+    );
+}
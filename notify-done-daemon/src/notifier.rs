@@ -1,44 +1,284 @@
 use anyhow::{Context, Result};
+use std::fmt;
 use std::process::Command;
 use std::time::Duration;
 
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::config::{
+    format_duration, normalize_icon, DaemonConfig, DurationPrecision, EffectiveConfig,
+    ExitMessages, DEFAULT_APP_NAME,
+};
 use crate::process_tracker::CompletedProcess;
 use crate::user_session::{SessionType, UserSession};
 
+/// Action identifier reported by notify-send when "Mute" is clicked
+const MUTE_ACTION: &str = "mute";
+
+/// A user asked to mute a command from a notification action
+#[derive(Debug, Clone)]
+pub struct MuteRequest {
+    pub uid: u32,
+    pub comm: String,
+    pub duration: Duration,
+}
+
+/// notify-send stderr fragments meaning the user's bus or notification
+/// server isn't up yet, typically right after login
+const NOT_READY_MARKERS: &[&str] = &[
+    "Failed to connect to bus",
+    "Connection refused",
+    "No such file or directory",
+    "ServiceUnknown",
+    "was not provided by any .service files",
+];
+
+/// A notification failed because the session isn't ready yet; worth retrying
+#[derive(Debug)]
+pub struct SessionNotReady(String);
+
+impl fmt::Display for SessionNotReady {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "session not ready: {}", self.0)
+    }
+}
+
+impl std::error::Error for SessionNotReady {}
+
 /// Sends desktop notifications to users
-pub struct Notifier;
+pub struct Notifier {
+    /// Replace the previous notification for the same command
+    replace_notifications: bool,
+    /// Name the parent process in the summary
+    show_parent: bool,
+    /// Name the user in the body
+    show_user: bool,
+    /// Offer a "Mute" action on failure notifications
+    mute_action: Option<(Duration, UnboundedSender<MuteRequest>)>,
+}
 
 impl Notifier {
-    pub fn new() -> Self {
-        Self
+    pub fn new(config: &DaemonConfig) -> Self {
+        Self {
+            replace_notifications: config.replace_notifications,
+            show_parent: config.show_parent,
+            show_user: config.show_user,
+            mute_action: None,
+        }
+    }
+
+    /// Offer a "Mute for <duration>" action on failure notifications,
+    /// reporting clicks on `tx`
+    pub fn with_mute_action(
+        mut self,
+        duration: Duration,
+        tx: UnboundedSender<MuteRequest>,
+    ) -> Self {
+        self.mute_action = Some((duration, tx));
+        self
     }
 
     /// Send a notification for a completed process
-    pub async fn notify(&self, session: &UserSession, process: &CompletedProcess) -> Result<()> {
-        let summary = format!("Command completed: {}", process.comm);
-        let body = self.format_body(process);
+    pub async fn notify(
+        &self,
+        session: &UserSession,
+        process: &CompletedProcess,
+        config: &EffectiveConfig,
+    ) -> Result<()> {
+        let exit_messages = config.exit_messages_for(&process.comm);
+        let success = config.is_success(&process.comm, process.exit_code);
+        let min_expected = config.finished_too_fast(&process.comm, process.duration.as_secs());
+        let summary = match &process.parent_comm {
+            Some(parent) if self.show_parent => {
+                format!("Command completed: {} (from: {})", process.comm, parent)
+            }
+            _ => format!("Command completed: {}", process.comm),
+        };
+        let body = self.format_body(
+            session,
+            process,
+            success,
+            exit_messages,
+            min_expected,
+            config.notification.duration_precision,
+        );
+
+        // An unexpectedly fast success is shown like a failure
+        let (urgency, timeout_ms) = config
+            .notification
+            .for_command(Some(&process.comm), success && min_expected.is_none());
+        let mut options = vec![format!("--urgency={}", urgency.as_str())];
+        if let Some(ms) = timeout_ms {
+            // notify-send passes 0 on as the spec's "never expire"
+            options.push(format!("--expire-time={}", ms));
+        }
+        if config.notification.transient {
+            options.push("--hint=boolean:transient:true".to_string());
+        }
+        if let Some(entry) = &config.notification.desktop_entry {
+            options.push(format!("--hint=string:desktop-entry:{}", entry));
+        }
+        if let Some(icon) = &config.notification.icon {
+            options.push(format!("--icon={}", normalize_icon(icon)));
+        }
+        for (name, value) in &config.notification.hints {
+            options.push(value.notify_send_arg(name));
+        }
+        let app_name = config.notification.app_name.as_str();
 
-        // Use notify-send via sudo to send notification as the user
-        self.send_notify_send(session, &summary, &body)
+        // The user's own notifier replaces notify-send entirely
+        if let Some(argv) = config
+            .notification
+            .command_argv(&summary, &body, urgency, app_name)
+        {
+            let mut cmd = self.session_command(session);
+            cmd.args(&argv);
+            return Self::run_checked(cmd, &argv[0]);
+        }
+
+        if self.replace_notifications {
+            // Tag notifications by command so the server replaces the previous one.
+            // dunst and the Canonical-derived servers each use their own hint.
+            let tag = format!("notify-done-{}", process.comm);
+            options.push(format!("--hint=string:x-dunst-stack-tag:{}", tag));
+            options.push(format!(
+                "--hint=string:x-canonical-private-synchronous:{}",
+                tag
+            ));
+        }
+
+        match &self.mute_action {
+            Some((duration, tx)) if !success => {
+                options.push(format!(
+                    "--action={}=Mute for {}",
+                    MUTE_ACTION,
+                    format_duration(*duration, DurationPrecision::Seconds)
+                ));
+                let cmd = self.notify_send_command(session, app_name, &summary, &body, &options);
+                let request = MuteRequest {
+                    uid: process.uid,
+                    comm: process.comm.clone(),
+                    duration: *duration,
+                };
+                Self::spawn_with_action(cmd, request, tx.clone());
+                Ok(())
+            }
+            // Use notify-send via sudo to send notification as the user
+            _ => self.send_notify_send(session, app_name, &summary, &body, &options),
+        }
     }
 
     /// Format the notification body
-    fn format_body(&self, process: &CompletedProcess) -> String {
-        let duration = format_duration(process.duration);
-        let status = if process.exit_code == 0 {
+    fn format_body(
+        &self,
+        session: &UserSession,
+        process: &CompletedProcess,
+        success: bool,
+        exit_messages: Option<&ExitMessages>,
+        min_expected: Option<u64>,
+        precision: DurationPrecision,
+    ) -> String {
+        let duration = format_duration(process.duration, precision);
+        let status = if process.core_dumped {
+            "crashed (core dumped)"
+        } else if success {
             "succeeded"
         } else {
             "failed"
         };
+        let mut status = match exit_messages.and_then(|m| m.label(process.exit_code)) {
+            Some(label) => format!("{} ({})", status, label),
+            None => status.to_string(),
+        };
+        if let Some(min) = min_expected {
+            status.push_str(&format!(
+                ", but finished unexpectedly fast (expected at least {})",
+                format_duration(Duration::from_secs(min), DurationPrecision::Seconds)
+            ));
+        }
 
-        format!(
+        let mut body = format!(
             "{}\nDuration: {}\nExit code: {}",
             status, duration, process.exit_code
-        )
+        );
+        if self.show_user {
+            body.push_str(&format!(
+                "\nUser: {} (uid {})",
+                session.username, process.uid
+            ));
+        }
+        body
     }
 
     /// Send notification using notify-send command as the target user
-    fn send_notify_send(&self, session: &UserSession, summary: &str, body: &str) -> Result<()> {
+    fn send_notify_send(
+        &self,
+        session: &UserSession,
+        app_name: &str,
+        summary: &str,
+        body: &str,
+        options: &[String],
+    ) -> Result<()> {
+        let cmd = self.notify_send_command(session, app_name, summary, body, options);
+        Self::run_checked(cmd, "notify-send")
+    }
+
+    /// Run a command built by `session_command`, failing with its stderr
+    fn run_checked(mut cmd: Command, program: &str) -> Result<()> {
+        let output = cmd.output().context("Failed to run systemd-run")?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if NOT_READY_MARKERS.iter().any(|m| stderr.contains(m)) {
+                return Err(SessionNotReady(stderr.trim().to_string()).into());
+            }
+            anyhow::bail!("{} failed (exit {}): {}", program, output.status, stderr);
+        }
+
+        Ok(())
+    }
+
+    /// Run a notify-send that waits for an action in the background, so the
+    /// event loop isn't blocked until the user dismisses the notification
+    fn spawn_with_action(cmd: Command, request: MuteRequest, tx: UnboundedSender<MuteRequest>) {
+        let mut cmd = tokio::process::Command::from(cmd);
+        tokio::spawn(async move {
+            match cmd.output().await {
+                Ok(output) if output.status.success() => {
+                    let stdout = String::from_utf8_lossy(&output.stdout);
+                    if stdout.trim() == MUTE_ACTION {
+                        let _ = tx.send(request);
+                    }
+                }
+                Ok(output) => {
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    tracing::error!("notify-send failed (exit {}): {}", output.status, stderr);
+                }
+                Err(e) => tracing::error!("Failed to run systemd-run: {}", e),
+            }
+        });
+    }
+
+    /// Build the notify-send command, run as the target user
+    fn notify_send_command(
+        &self,
+        session: &UserSession,
+        app_name: &str,
+        summary: &str,
+        body: &str,
+        options: &[String],
+    ) -> Command {
+        let mut cmd = self.session_command(session);
+        cmd.arg("notify-send");
+        cmd.arg(format!("--app-name={}", app_name));
+        cmd.args(options);
+        cmd.args([summary, body]);
+        cmd
+    }
+
+    /// Start a command that runs whatever arguments are added next as the
+    /// target user, in their session
+    fn session_command(&self, session: &UserSession) -> Command {
         // Build environment variables
         let xdg_runtime_dir = format!("/run/user/{}", session.uid);
 
@@ -69,40 +309,37 @@ impl Notifier {
         for env_var in &env_vars {
             cmd.args(["--setenv", env_var]);
         }
-        cmd.args(["notify-send", "--app-name=notify-done", summary, body]);
-
-        let output = cmd.output().context("Failed to run systemd-run")?;
+        cmd
+    }
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            anyhow::bail!("notify-send failed (exit {}): {}", output.status, stderr);
-        }
+    /// Tell the user that notifications were dropped by the rate limiter
+    pub async fn notify_suppressed(&self, session: &UserSession, count: u32) -> Result<()> {
+        let summary = "notify-done: notifications suppressed";
+        let body = format!(
+            "{} notification{} dropped by the rate limit",
+            count,
+            if count == 1 { " was" } else { "s were" }
+        );
+        self.send_notify_send(session, DEFAULT_APP_NAME, summary, &body, &[])
+    }
 
-        Ok(())
+    /// Tell the user the daemon started or stopped
+    pub async fn notify_lifecycle(&self, session: &UserSession, body: &str) -> Result<()> {
+        let summary = "notify-done daemon";
+        let options = ["--urgency=low".to_string()];
+        self.send_notify_send(session, DEFAULT_APP_NAME, summary, body, &options)
     }
 
     /// Send a test notification
     pub async fn send_test(&self, session: &UserSession) -> Result<()> {
         let summary = "notify-done test";
         let body = "If you see this, notifications are working!";
-        self.send_notify_send(session, summary, body)
+        self.send_notify_send(session, DEFAULT_APP_NAME, summary, body, &[])
     }
 }
 
 impl Default for Notifier {
     fn default() -> Self {
-        Self::new()
-    }
-}
-
-/// Format duration in human-readable form
-fn format_duration(d: Duration) -> String {
-    let secs = d.as_secs();
-    if secs < 60 {
-        format!("{}s", secs)
-    } else if secs < 3600 {
-        format!("{}m {}s", secs / 60, secs % 60)
-    } else {
-        format!("{}h {}m {}s", secs / 3600, (secs % 3600) / 60, secs % 60)
+        Self::new(&DaemonConfig::default())
     }
 }
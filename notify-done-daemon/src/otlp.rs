@@ -0,0 +1,253 @@
+//! Completed commands exported as OpenTelemetry spans
+//!
+//! Spans are sent in OTLP/HTTP's JSON encoding, hand-rolled like the
+//! StatsD client: one span per completion, batched by a background task so
+//! the event loop never waits on the collector. Only plain `http://`
+//! endpoints are supported, typically a local collector that forwards on.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver, Sender};
+use tokio::time::{interval, timeout, MissedTickBehavior};
+
+use crate::process_tracker::CompletedProcess;
+
+/// Path of the traces endpoint when the configured one has none
+const DEFAULT_TRACES_PATH: &str = "/v1/traces";
+
+/// Spans waiting for the export task; more are dropped, not queued
+const QUEUE_CAPACITY: usize = 4096;
+
+/// Most spans in one export request
+const MAX_BATCH: usize = 256;
+
+/// How often a partial batch is exported
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Give up on an unresponsive collector after this long
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where export requests go, parsed from an `http://host:port/path` URL
+#[derive(Debug, Clone)]
+struct Endpoint {
+    /// `host:port`, for connecting and the Host header
+    authority: String,
+    path: String,
+}
+
+impl Endpoint {
+    fn parse(url: &str) -> Result<Self> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            bail!(
+                "{} is not an http:// URL (https isn't supported, export to a local collector)",
+                url
+            );
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, ""),
+        };
+        if authority.is_empty() {
+            bail!("{} has no host", url);
+        }
+        let has_port = authority
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()));
+        let authority = if has_port {
+            authority.to_string()
+        } else {
+            format!("{}:80", authority)
+        };
+        let path = if path.is_empty() || path == "/" {
+            DEFAULT_TRACES_PATH
+        } else {
+            path
+        };
+        Ok(Self {
+            authority,
+            path: path.to_string(),
+        })
+    }
+}
+
+/// Queues spans for the background export task
+pub struct OtlpExporter {
+    tx: Sender<Value>,
+}
+
+impl OtlpExporter {
+    /// Parse `endpoint` (e.g. "http://127.0.0.1:4318") and start the
+    /// export task; must be called inside the Tokio runtime
+    pub fn start(endpoint: &str) -> Result<Self> {
+        let endpoint = Endpoint::parse(endpoint).context("Invalid otlp_endpoint")?;
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(export_loop(endpoint, rx));
+        Ok(Self { tx })
+    }
+
+    /// Queue a span for `process`, which ended just now
+    pub fn completion(&self, process: &CompletedProcess, success: bool) {
+        match self.tx.try_send(span(process, success)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                tracing::debug!("OTLP export queue full, dropping span for {}", process.comm)
+            }
+            Err(TrySendError::Closed(_)) => {}
+        }
+    }
+}
+
+/// Export whenever a batch fills up or the interval passes
+async fn export_loop(endpoint: Endpoint, mut rx: Receiver<Value>) {
+    let mut batch = Vec::with_capacity(MAX_BATCH);
+    let mut ticker = interval(EXPORT_INTERVAL);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            span = rx.recv() => match span {
+                Some(span) => {
+                    batch.push(span);
+                    if batch.len() < MAX_BATCH {
+                        continue;
+                    }
+                }
+                // The processor is gone: send what's left and stop
+                None => {
+                    export(&endpoint, std::mem::take(&mut batch)).await;
+                    return;
+                }
+            },
+            _ = ticker.tick() => {}
+        }
+        export(&endpoint, std::mem::take(&mut batch)).await;
+    }
+}
+
+/// Send `spans` in one request; spans are best effort, so failures are
+/// only logged
+async fn export(endpoint: &Endpoint, spans: Vec<Value>) {
+    if spans.is_empty() {
+        return;
+    }
+    let count = spans.len();
+    match timeout(REQUEST_TIMEOUT, post(endpoint, &request_body(spans))).await {
+        Ok(Ok(())) => tracing::debug!("Exported {} spans", count),
+        Ok(Err(e)) => tracing::warn!("Failed to export {} spans: {:#}", count, e),
+        Err(_) => tracing::warn!(
+            "Failed to export {} spans: {} timed out",
+            count,
+            endpoint.authority
+        ),
+    }
+}
+
+/// A minimal HTTP/1.1 POST, one connection per request
+async fn post(endpoint: &Endpoint, body: &str) -> Result<()> {
+    let mut stream = TcpStream::connect(&endpoint.authority)
+        .await
+        .with_context(|| format!("Failed to connect to {}", endpoint.authority))?;
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        endpoint.path,
+        endpoint.authority,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    // Only the status line matters
+    let mut response = vec![0u8; 512];
+    let read = stream.read(&mut response).await?;
+    let response = String::from_utf8_lossy(&response[..read]);
+    let status_line = response.lines().next().unwrap_or_default();
+    match status_line.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => bail!("Collector answered {:?}", status_line),
+    }
+}
+
+/// An ExportTraceServiceRequest holding `spans`
+fn request_body(spans: Vec<Value>) -> String {
+    let host = std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|name| name.trim().to_string())
+        .unwrap_or_default();
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    string_attribute("service.name", "notify-done"),
+                    string_attribute("host.name", &host),
+                ]
+            },
+            "scopeSpans": [{
+                "scope": {
+                    "name": "notify-done-daemon",
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+                "spans": spans,
+            }]
+        }]
+    })
+    .to_string()
+}
+
+/// One root span per completion, named after the command
+fn span(process: &CompletedProcess, success: bool) -> Value {
+    let end = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let start = end.saturating_sub(process.duration);
+    json!({
+        "traceId": hex(&random_bytes::<16>()),
+        "spanId": hex(&random_bytes::<8>()),
+        "name": process.comm,
+        // SPAN_KIND_INTERNAL
+        "kind": 1,
+        // 64-bit integers are strings in OTLP's JSON encoding
+        "startTimeUnixNano": start.as_nanos().to_string(),
+        "endTimeUnixNano": end.as_nanos().to_string(),
+        "attributes": [
+            int_attribute("process.pid", process.tgid.into()),
+            int_attribute("process.exit_code", process.exit_code.into()),
+            int_attribute("user.id", process.uid.into()),
+            string_attribute("process.executable.path", &process.filename),
+        ],
+        // STATUS_CODE_OK or STATUS_CODE_ERROR
+        "status": { "code": if success { 1 } else { 2 } },
+    })
+}
+
+fn string_attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+fn int_attribute(key: &str, value: i64) -> Value {
+    json!({ "key": key, "value": { "intValue": value.to_string() } })
+}
+
+/// Trace and span IDs; all zeros is invalid, so that's never returned
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    let filled = unsafe { libc::getrandom(bytes.as_mut_ptr().cast(), N, 0) };
+    if filled != N as isize || bytes.iter().all(|&b| b == 0) {
+        // Unreachable in practice; any non-zero ID beats dropping the span
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+            | 1;
+        for (byte, source) in bytes.iter_mut().zip(nanos.to_le_bytes().iter().cycle()) {
+            *byte = *source;
+        }
+    }
+    bytes
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -1,7 +1,25 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use notify_done_common::{ProcessExecEvent, ProcessExitEvent};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use notify_done_common::{ProcessExecEvent, ProcessExitEvent, WaitStatus};
+
+use crate::clock::Clock;
+use crate::config::DurationSource;
+
+/// Command name of the `nd` wrapper, which notifies for its own commands
+const WRAPPER_COMM: &str = "nd";
+
+/// How far up the process tree to look for a wrapper
+const MAX_ANCESTRY_DEPTH: usize = 32;
+
+/// Identifies the current boot; kernel timestamps are only comparable within one
+const BOOT_ID_PATH: &str = "/proc/sys/kernel/random/boot_id";
 
 /// Information about a tracked process
 #[derive(Debug, Clone)]
@@ -12,8 +30,60 @@ pub struct TrackedProcess {
     pub uid: u32,
     pub comm: String,
     pub filename: String,
+    /// Parent's command name, if resolved at exec time
+    pub parent_comm: Option<String>,
+    /// This is the `nd` wrapper or runs under one
+    pub wrapped: bool,
+    pub tty: Tty,
+    /// Working directory at exec, if it could be read
+    pub cwd: Option<String>,
     pub start_time: Instant,
     pub start_timestamp_ns: u64,
+    /// CLOCK_BOOTTIME when the exec was handled, for userspace durations
+    pub start_boottime_ns: u64,
+}
+
+/// On-disk form of a tracked process (`Instant` can't be serialized, so the
+/// start is kept as the kernel's monotonic timestamp)
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedProcess {
+    pid: u32,
+    tgid: u32,
+    ppid: u32,
+    uid: u32,
+    comm: String,
+    filename: String,
+    start_timestamp_ns: u64,
+    #[serde(default)]
+    wrapped: bool,
+}
+
+/// Active processes saved across a daemon restart
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedState {
+    boot_id: String,
+    processes: Vec<SavedProcess>,
+}
+
+/// Controlling terminal of a process, read from /proc at exec
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tty {
+    /// e.g. "pts/3"
+    Terminal(String),
+    /// No controlling terminal: a daemon, service or cron job
+    Detached,
+    /// Not captured, or the process was gone before /proc could be read
+    Unknown,
+}
+
+impl fmt::Display for Tty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tty::Terminal(name) => f.write_str(name),
+            Tty::Detached => f.write_str("none"),
+            Tty::Unknown => f.write_str("unknown"),
+        }
+    }
 }
 
 /// A process that has completed execution
@@ -24,7 +94,16 @@ pub struct CompletedProcess {
     pub uid: u32,
     pub comm: String,
     pub filename: String,
+    pub parent_comm: Option<String>,
+    /// This is the `nd` wrapper or ran under one
+    pub wrapped: bool,
+    pub tty: Tty,
+    /// Working directory at exec, if it could be read
+    pub cwd: Option<String>,
+    /// Exit code, or 128 + signal if killed
     pub exit_code: i32,
+    /// The process was killed by a signal and dumped core
+    pub core_dumped: bool,
     pub duration: Duration,
 }
 
@@ -36,31 +115,123 @@ pub struct ProcessTracker {
     history: Vec<CompletedProcess>,
     /// Maximum history size
     max_history: usize,
+    /// Look up the parent's name on exec
+    resolve_parents: bool,
+    /// Mark processes belonging to an `nd` wrapper on exec
+    detect_wrapped: bool,
+    /// Read the controlling terminal on exec
+    capture_tty: bool,
+    /// Read the working directory on exec
+    capture_cwd: bool,
+    /// Maximum number of active processes
+    max_tracked: usize,
+    /// Clock durations are measured with
+    duration_source: DurationSource,
+    /// Source of start times and ages
+    clock: Arc<dyn Clock>,
 }
 
 impl ProcessTracker {
-    pub fn new(max_history: usize) -> Self {
+    pub fn new(max_history: usize, clock: Arc<dyn Clock>) -> Self {
         Self {
             processes: HashMap::new(),
             history: Vec::new(),
             max_history,
+            resolve_parents: false,
+            detect_wrapped: false,
+            capture_tty: false,
+            capture_cwd: false,
+            max_tracked: usize::MAX,
+            duration_source: DurationSource::Auto,
+            clock,
         }
     }
 
+    /// Bound the number of active processes, dropping the oldest beyond it
+    pub fn with_max_tracked(mut self, max_tracked: usize) -> Self {
+        self.max_tracked = max_tracked.max(1);
+        self
+    }
+
+    /// Measure durations with the given clock instead of picking one per process
+    pub fn with_duration_source(mut self, source: DurationSource) -> Self {
+        self.duration_source = source;
+        self
+    }
+
+    /// Resolve each process's parent name when it execs, while the parent
+    /// is still around
+    pub fn with_parent_resolution(mut self) -> Self {
+        self.resolve_parents = true;
+        self
+    }
+
+    /// Mark `nd` wrappers and their descendants on exec, so the daemon can
+    /// leave their notifications to the wrapper
+    pub fn with_wrapper_detection(mut self) -> Self {
+        self.detect_wrapped = true;
+        self
+    }
+
+    /// Record each process's controlling terminal on exec
+    pub fn with_tty_capture(mut self) -> Self {
+        self.capture_tty = true;
+        self
+    }
+
+    /// Record each process's working directory on exec
+    pub fn with_cwd_capture(mut self) -> Self {
+        self.capture_cwd = true;
+        self
+    }
+
+    /// Start recording working directories, for a user config loaded after
+    /// startup that filters on them
+    pub fn enable_cwd_capture(&mut self) {
+        self.capture_cwd = true;
+    }
+
     /// Handle a process exec event
     pub fn on_exec(&mut self, event: &ProcessExecEvent) {
+        // The eBPF program doesn't fill in ppid yet, so fall back to /proc
+        let ppid = match event.ppid {
+            0 if self.resolve_parents || self.detect_wrapped => read_ppid(event.tgid).unwrap_or(0),
+            ppid => ppid,
+        };
+        let parent_comm = if self.resolve_parents && ppid != 0 {
+            Some(self.parent_comm(ppid))
+        } else {
+            None
+        };
+        let comm = event.comm_str();
+        let wrapped = self.detect_wrapped && (comm == WRAPPER_COMM || self.under_wrapper(ppid));
+
         let tracked = TrackedProcess {
             pid: event.pid,
             tgid: event.tgid,
-            ppid: event.ppid,
+            ppid,
             uid: event.uid,
-            comm: event.comm_str().to_string(),
-            filename: event.filename_str().to_string(),
-            start_time: Instant::now(),
+            comm: comm.into_owned(),
+            filename: event.filename_str().into_owned(),
+            parent_comm,
+            wrapped,
+            tty: if self.capture_tty {
+                read_tty(event.tgid)
+            } else {
+                Tty::Unknown
+            },
+            // /proc/<pid> is usually gone by the time the exit is handled
+            cwd: if self.capture_cwd {
+                read_cwd(event.tgid)
+            } else {
+                None
+            },
+            start_time: self.clock.now(),
             start_timestamp_ns: event.timestamp_ns,
+            start_boottime_ns: boottime_now_ns(),
         };
 
-        log::debug!(
+        tracing::debug!(
             "Tracking process: pid={} comm={} filename={}",
             tracked.pid,
             tracked.comm,
@@ -68,36 +239,113 @@ impl ProcessTracker {
         );
 
         self.processes.insert(event.tgid, tracked);
+        if self.processes.len() > self.max_tracked {
+            self.evict_oldest();
+        }
+    }
+
+    /// Drop the oldest tenth of the tracked processes, so a full table
+    /// isn't rescanned on every exec
+    fn evict_oldest(&mut self) {
+        let keep = self.max_tracked - self.max_tracked / 10;
+        let mut by_age: Vec<(Instant, u32)> = self
+            .processes
+            .values()
+            .map(|p| (p.start_time, p.tgid))
+            .collect();
+        let evict = by_age.len() - keep;
+        by_age.select_nth_unstable(evict - 1);
+
+        for (_, tgid) in &by_age[..evict] {
+            self.processes.remove(tgid);
+        }
+        tracing::warn!(
+            "Tracking more than {} processes, dropped the {} oldest",
+            self.max_tracked,
+            evict
+        );
+    }
+
+    /// Name of a parent process: tracked processes first, then /proc
+    fn parent_comm(&self, ppid: u32) -> String {
+        if let Some(parent) = self.processes.get(&ppid) {
+            return parent.comm.clone();
+        }
+        std::fs::read_to_string(format!("/proc/{}/comm", ppid))
+            .map(|comm| comm.trim_end().to_string())
+            .unwrap_or_else(|_| "unknown".into())
+    }
+
+    /// Whether `pid` is an `nd` wrapper or runs under one. Tracked
+    /// processes were classified at their own exec; untracked ones (forks
+    /// that never exec'd) are looked up in /proc.
+    fn under_wrapper(&self, mut pid: u32) -> bool {
+        for _ in 0..MAX_ANCESTRY_DEPTH {
+            if pid <= 1 {
+                return false;
+            }
+            if let Some(process) = self.processes.get(&pid) {
+                return process.wrapped;
+            }
+            if is_same_process(pid, WRAPPER_COMM) {
+                return true;
+            }
+            match read_ppid(pid) {
+                Some(ppid) => pid = ppid,
+                None => return false,
+            }
+        }
+        false
     }
 
     /// Handle a process exit event, returns CompletedProcess if we were tracking it
     pub fn on_exit(&mut self, event: &ProcessExitEvent) -> Option<CompletedProcess> {
         let tracked = self.processes.remove(&event.tgid)?;
 
-        // Calculate duration using kernel timestamps if available,
-        // otherwise fall back to userspace timing
-        let duration = if event.timestamp_ns > tracked.start_timestamp_ns {
-            Duration::from_nanos(event.timestamp_ns - tracked.start_timestamp_ns)
-        } else {
-            tracked.start_time.elapsed()
+        let duration = match self.duration_source {
+            // Use kernel timestamps if available, otherwise fall back to
+            // userspace timing
+            DurationSource::Auto if event.timestamp_ns > tracked.start_timestamp_ns => {
+                Duration::from_nanos(event.timestamp_ns - tracked.start_timestamp_ns)
+            }
+            DurationSource::Auto => self
+                .clock
+                .now()
+                .saturating_duration_since(tracked.start_time),
+            DurationSource::Kernel => Duration::from_nanos(
+                event
+                    .timestamp_ns
+                    .saturating_sub(tracked.start_timestamp_ns),
+            ),
+            DurationSource::Userspace => {
+                Duration::from_nanos(boottime_now_ns().saturating_sub(tracked.start_boottime_ns))
+            }
         };
 
+        let status = WaitStatus::from_raw(event.exit_code);
         let completed = CompletedProcess {
             pid: tracked.pid,
             tgid: tracked.tgid,
             uid: tracked.uid,
             comm: tracked.comm,
             filename: tracked.filename,
-            exit_code: event.exit_code,
+            parent_comm: tracked.parent_comm,
+            wrapped: tracked.wrapped,
+            tty: tracked.tty,
+            cwd: tracked.cwd,
+            exit_code: status.code(),
+            core_dumped: status.core_dumped(),
             duration,
         };
 
-        log::debug!(
-            "Process completed: pid={} comm={} duration={:?} exit_code={}",
-            completed.pid,
-            completed.comm,
-            completed.duration,
-            completed.exit_code
+        tracing::debug!(
+            pid = completed.pid,
+            uid = completed.uid,
+            comm = %completed.comm,
+            tty = %completed.tty,
+            duration_ms = completed.duration.as_millis() as u64,
+            exit_code = completed.exit_code,
+            "Process completed"
         );
 
         // Add to history
@@ -124,13 +372,97 @@ impl ProcessTracker {
         self.processes.len()
     }
 
+    /// Write the active processes to `path` so a restarted daemon can pick
+    /// them up
+    pub fn save_state(&self, path: &Path) -> Result<()> {
+        let state = SavedState {
+            boot_id: boot_id()?,
+            processes: self
+                .processes
+                .values()
+                .map(|p| SavedProcess {
+                    pid: p.pid,
+                    tgid: p.tgid,
+                    ppid: p.ppid,
+                    uid: p.uid,
+                    comm: p.comm.clone(),
+                    filename: p.filename.clone(),
+                    start_timestamp_ns: p.start_timestamp_ns,
+                    wrapped: p.wrapped,
+                })
+                .collect(),
+        };
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        // Write then rename so a crash never leaves a truncated file
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec(&state)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Load processes saved by a previous daemon, keeping only those still
+    /// running. Returns how many were restored.
+    pub fn restore_state(&mut self, path: &Path) -> Result<usize> {
+        let content = match std::fs::read(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        let state: SavedState = serde_json::from_slice(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+        // Monotonic timestamps from before a reboot are meaningless
+        if state.boot_id != boot_id()? {
+            tracing::info!("Discarding process state from a previous boot");
+            return Ok(0);
+        }
+
+        let now_ns = monotonic_now_ns();
+        let mut restored = 0;
+        for saved in state.processes {
+            if !is_same_process(saved.tgid, &saved.comm) {
+                continue;
+            }
+
+            // Rebuild the userspace start times from the kernel timestamp
+            let age = Duration::from_nanos(now_ns.saturating_sub(saved.start_timestamp_ns));
+            let now = self.clock.now();
+            let start_time = now.checked_sub(age).unwrap_or(now);
+            let start_boottime_ns = boottime_now_ns().saturating_sub(age.as_nanos() as u64);
+
+            self.processes.entry(saved.tgid).or_insert(TrackedProcess {
+                pid: saved.pid,
+                tgid: saved.tgid,
+                ppid: saved.ppid,
+                uid: saved.uid,
+                comm: saved.comm,
+                filename: saved.filename,
+                parent_comm: None,
+                wrapped: saved.wrapped,
+                tty: Tty::Unknown,
+                cwd: None,
+                start_time,
+                start_timestamp_ns: saved.start_timestamp_ns,
+                start_boottime_ns,
+            });
+            restored += 1;
+        }
+
+        Ok(restored)
+    }
+
     /// Clean up stale processes (those that have been running for too long without exit)
     pub fn cleanup_stale(&mut self, max_age: Duration) {
-        let now = Instant::now();
+        let now = self.clock.now();
         self.processes.retain(|_, p| {
             let age = now.duration_since(p.start_time);
             if age > max_age {
-                log::warn!(
+                tracing::warn!(
                     "Cleaning up stale process: pid={} comm={} age={:?}",
                     p.pid,
                     p.comm,
@@ -143,3 +475,260 @@ impl ProcessTracker {
         });
     }
 }
+
+fn boot_id() -> Result<String> {
+    let id = std::fs::read_to_string(BOOT_ID_PATH)
+        .with_context(|| format!("Failed to read {}", BOOT_ID_PATH))?;
+    Ok(id.trim().to_string())
+}
+
+/// CLOCK_MONOTONIC in nanoseconds, the clock behind bpf_ktime_get_ns
+fn monotonic_now_ns() -> u64 {
+    let mut ts: libc::timespec = unsafe { std::mem::zeroed() };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+/// CLOCK_BOOTTIME in nanoseconds, which keeps counting while suspended
+fn boottime_now_ns() -> u64 {
+    let mut ts: libc::timespec = unsafe { std::mem::zeroed() };
+    unsafe { libc::clock_gettime(libc::CLOCK_BOOTTIME, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+/// Whether `tgid` is still alive and runs the same command (not a reused pid)
+fn is_same_process(tgid: u32, comm: &str) -> bool {
+    std::fs::read_to_string(format!("/proc/{}/comm", tgid))
+        .is_ok_and(|current| current.trim_end() == comm)
+}
+
+/// Parent pid from /proc/<pid>/stat (field 4, after the parenthesised comm)
+fn read_ppid(pid: u32) -> Option<u32> {
+    stat_field(pid, 4)?.parse().ok()
+}
+
+/// Working directory from /proc/<pid>/cwd
+fn read_cwd(pid: u32) -> Option<String> {
+    std::fs::read_link(format!("/proc/{}/cwd", pid))
+        .ok()
+        .map(|cwd| cwd.to_string_lossy().into_owned())
+}
+
+/// Controlling terminal from /proc/<pid>/stat (tty_nr, field 7)
+fn read_tty(pid: u32) -> Tty {
+    match stat_field(pid, 7).and_then(|tty_nr| tty_nr.parse::<i64>().ok()) {
+        Some(0) => Tty::Detached,
+        Some(tty_nr) => Tty::Terminal(tty_name(tty_nr as u32)),
+        // Short-lived processes can exit before we get to read them
+        None => Tty::Unknown,
+    }
+}
+
+/// Field `n` (1-based, as in proc(5)) of /proc/<pid>/stat; the comm in
+/// field 2 may contain spaces, so fields are counted from its closing paren
+fn stat_field(pid: u32, n: usize) -> Option<String> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    parse_stat_field(&stat, n).map(str::to_string)
+}
+
+fn parse_stat_field(stat: &str, n: usize) -> Option<&str> {
+    let rest = &stat[stat.rfind(')')? + 1..];
+    rest.split_whitespace().nth(n - 3)
+}
+
+/// Name a device number the way ps does: "pts/3", "tty2", "ttyS0", or
+/// "major:minor" for anything else
+fn tty_name(tty_nr: u32) -> String {
+    let major = (tty_nr >> 8) & 0xfff;
+    let minor = (tty_nr & 0xff) | ((tty_nr >> 12) & 0xfff00);
+    match major {
+        // Unix98 pseudo-terminals span eight majors
+        136..=143 => format!("pts/{}", (major - 136) * 256 + minor),
+        4 if minor < 64 => format!("tty{}", minor),
+        4 => format!("ttyS{}", minor - 64),
+        _ => format!("{}:{}", major, minor),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use notify_done_common::{EventType, COMM_LEN, FILENAME_LEN};
+
+    use super::*;
+    use crate::clock::ManualClock;
+
+    fn exec_event(pid: u32, comm: &str) -> ProcessExecEvent {
+        let mut event = ProcessExecEvent {
+            event_type: EventType::Exec as u8,
+            _pad: [0; 3],
+            pid,
+            tgid: pid,
+            ppid: 1,
+            uid: 1000,
+            timestamp_ns: 0,
+            comm: [0; COMM_LEN],
+            filename: [0; FILENAME_LEN],
+        };
+        event.comm[..comm.len()].copy_from_slice(comm.as_bytes());
+        event
+    }
+
+    fn exit_event(pid: u32, timestamp_ns: u64) -> ProcessExitEvent {
+        ProcessExitEvent {
+            event_type: EventType::Exit as u8,
+            _pad: [0; 3],
+            pid,
+            tgid: pid,
+            uid: 1000,
+            exit_code: 0,
+            timestamp_ns,
+            comm: [0; COMM_LEN],
+        }
+    }
+
+    fn tracked_pids(tracker: &ProcessTracker) -> Vec<u32> {
+        let mut pids: Vec<u32> = tracker.active_processes().map(|p| p.pid).collect();
+        pids.sort_unstable();
+        pids
+    }
+
+    #[test]
+    fn over_the_cap_evicts_the_oldest_tenth() {
+        let clock = Arc::new(ManualClock::starting_now());
+        let mut tracker = ProcessTracker::new(10, clock.clone()).with_max_tracked(20);
+        for pid in 1..=20 {
+            tracker.on_exec(&exec_event(pid, "job"));
+            clock.advance(Duration::from_secs(1));
+        }
+        assert_eq!(tracker.active_count(), 20);
+
+        tracker.on_exec(&exec_event(21, "job"));
+        assert_eq!(tracked_pids(&tracker), (4..=21).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn cleanup_stale_drops_only_expired_processes() {
+        let clock = Arc::new(ManualClock::starting_now());
+        let mut tracker = ProcessTracker::new(10, clock.clone());
+        tracker.on_exec(&exec_event(100, "old"));
+        clock.advance(Duration::from_secs(50));
+        tracker.on_exec(&exec_event(200, "new"));
+
+        clock.advance(Duration::from_secs(20));
+        tracker.cleanup_stale(Duration::from_secs(60));
+        assert_eq!(tracked_pids(&tracker), [200]);
+
+        clock.advance(Duration::from_secs(60));
+        tracker.cleanup_stale(Duration::from_secs(60));
+        assert!(tracked_pids(&tracker).is_empty());
+    }
+
+    /// Duration of a process that ran 10s by the daemon's clock, with
+    /// kernel timestamps 3s apart at exec and `exit_ns` at exit
+    fn measured(source: DurationSource, exit_ns: u64) -> Duration {
+        let clock = Arc::new(ManualClock::starting_now());
+        let mut tracker = ProcessTracker::new(10, clock.clone()).with_duration_source(source);
+        tracker.on_exec(&ProcessExecEvent {
+            timestamp_ns: 1_000_000_000,
+            ..exec_event(100, "make")
+        });
+        clock.advance(Duration::from_secs(10));
+        tracker.on_exit(&exit_event(100, exit_ns)).unwrap().duration
+    }
+
+    #[test]
+    fn duration_source_picks_the_clock() {
+        let secs = Duration::from_secs;
+        assert_eq!(measured(DurationSource::Auto, 4_000_000_000), secs(3));
+        assert_eq!(measured(DurationSource::Kernel, 4_000_000_000), secs(3));
+
+        // Out-of-order kernel timestamps
+        assert_eq!(measured(DurationSource::Auto, 500_000_000), secs(10));
+        assert_eq!(measured(DurationSource::Kernel, 500_000_000), secs(0));
+
+        // The boot-time clock ignores both the kernel timestamps and the
+        // daemon's monotonic clock
+        assert!(measured(DurationSource::Userspace, 4_000_000_000) < secs(1));
+    }
+
+    #[test]
+    fn stat_fields_count_from_the_end_of_comm() {
+        let stat = "4242 (my (odd) job) S 4200 4242 4200 34819 4242 4194304";
+        assert_eq!(parse_stat_field(stat, 3), Some("S"));
+        assert_eq!(parse_stat_field(stat, 4), Some("4200"));
+        assert_eq!(parse_stat_field(stat, 7), Some("34819"));
+        assert_eq!(parse_stat_field(stat, 20), None);
+        assert_eq!(parse_stat_field("truncated", 4), None);
+    }
+
+    #[test]
+    fn tty_names_follow_ps() {
+        assert_eq!(tty_name(136 << 8 | 3), "pts/3");
+        // Past 255 the pty numbers continue on the next major
+        assert_eq!(tty_name(137 << 8 | 44), "pts/300");
+        assert_eq!(tty_name(4 << 8 | 2), "tty2");
+        assert_eq!(tty_name(4 << 8 | 64), "ttyS0");
+        assert_eq!(tty_name(5 << 8 | 1), "5:1");
+    }
+
+    #[test]
+    fn tty_of_a_missing_process_is_unknown() {
+        assert_eq!(read_tty(u32::MAX), Tty::Unknown);
+        assert_ne!(read_tty(std::process::id()), Tty::Unknown);
+    }
+
+    #[test]
+    fn cwd_is_read_only_when_capture_is_on() {
+        let pid = std::process::id();
+        let cwd = std::env::current_dir().unwrap();
+        let clock = Arc::new(ManualClock::starting_now());
+
+        let mut tracker = ProcessTracker::new(10, clock);
+        tracker.on_exec(&exec_event(pid, "test"));
+        assert_eq!(tracker.active_processes().next().unwrap().cwd, None);
+
+        tracker.enable_cwd_capture();
+        tracker.on_exec(&exec_event(pid, "test"));
+        let tracked = tracker.active_processes().next().unwrap();
+        assert_eq!(tracked.cwd.as_deref(), cwd.to_str());
+    }
+
+    #[test]
+    fn wrapper_detection_marks_the_nd_subtree() {
+        let clock = Arc::new(ManualClock::starting_now());
+        let mut tracker = ProcessTracker::new(10, clock).with_wrapper_detection();
+        // nd -> cargo -> rustc, beside an unrelated make
+        let tree = [
+            (100, 1, "nd"),
+            (101, 100, "cargo"),
+            (102, 101, "rustc"),
+            (200, 1, "make"),
+        ];
+        for (pid, ppid, comm) in tree {
+            tracker.on_exec(&ProcessExecEvent {
+                ppid,
+                ..exec_event(pid, comm)
+            });
+        }
+
+        let mut wrapped: Vec<u32> = tracker
+            .active_processes()
+            .filter(|p| p.wrapped)
+            .map(|p| p.pid)
+            .collect();
+        wrapped.sort_unstable();
+        assert_eq!(wrapped, [100, 101, 102]);
+    }
+
+    #[test]
+    fn wrapper_detection_is_off_by_default() {
+        let clock = Arc::new(ManualClock::starting_now());
+        let mut tracker = ProcessTracker::new(10, clock);
+        tracker.on_exec(&exec_event(100, "nd"));
+        tracker.on_exec(&ProcessExecEvent {
+            ppid: 100,
+            ..exec_event(101, "cargo")
+        });
+        assert!(tracker.active_processes().all(|p| !p.wrapped));
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// Outcome of a rate limit check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateDecision {
+    /// Send the notification; `suppressed` notifications were dropped since
+    /// the last one that got through
+    Allow { suppressed: u32 },
+    /// Drop the notification
+    Deny,
+}
+
+/// Token bucket state for one user
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+    suppressed: u32,
+}
+
+/// Per-user token bucket limiting notifications per minute
+pub struct RateLimiter {
+    per_minute: u32,
+    buckets: HashMap<u32, Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32) -> Self {
+        Self {
+            per_minute,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Take a token for `uid` at time `now`
+    pub fn check(&mut self, uid: u32, now: Instant) -> RateDecision {
+        let capacity = self.per_minute as f64;
+        let bucket = self.buckets.entry(uid).or_insert(Bucket {
+            tokens: capacity,
+            last_refill: now,
+            suppressed: 0,
+        });
+
+        // Refill continuously at `per_minute` tokens per 60s
+        let elapsed = now.saturating_duration_since(bucket.last_refill);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * capacity / 60.0).min(capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            let suppressed = std::mem::take(&mut bucket.suppressed);
+            RateDecision::Allow { suppressed }
+        } else {
+            bucket.suppressed += 1;
+            RateDecision::Deny
+        }
+    }
+
+    /// Forget users whose bucket is full and has nothing pending
+    pub fn cleanup(&mut self, now: Instant) {
+        let capacity = self.per_minute as f64;
+        self.buckets.retain(|_, b| {
+            let elapsed = now.saturating_duration_since(b.last_refill);
+            b.suppressed > 0 || b.tokens + elapsed.as_secs_f64() * capacity / 60.0 < capacity
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const ALLOW: RateDecision = RateDecision::Allow { suppressed: 0 };
+
+    #[test]
+    fn burst_up_to_the_limit_then_deny() {
+        let mut limiter = RateLimiter::new(3);
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(limiter.check(1000, now), ALLOW);
+        }
+        assert_eq!(limiter.check(1000, now), RateDecision::Deny);
+        assert_eq!(limiter.check(1000, now), RateDecision::Deny);
+    }
+
+    #[test]
+    fn tokens_refill_over_time_and_report_what_was_dropped() {
+        let mut limiter = RateLimiter::new(6);
+        let start = Instant::now();
+        for _ in 0..6 {
+            limiter.check(1000, start);
+        }
+        assert_eq!(limiter.check(1000, start), RateDecision::Deny);
+        assert_eq!(limiter.check(1000, start), RateDecision::Deny);
+
+        // Six a minute is one every ten seconds
+        let later = start + Duration::from_secs(9);
+        assert_eq!(limiter.check(1000, later), RateDecision::Deny);
+        let later = start + Duration::from_secs(11);
+        assert_eq!(
+            limiter.check(1000, later),
+            RateDecision::Allow { suppressed: 3 }
+        );
+        assert_eq!(limiter.check(1000, later), RateDecision::Deny);
+    }
+
+    #[test]
+    fn refill_stops_at_capacity() {
+        let mut limiter = RateLimiter::new(2);
+        let start = Instant::now();
+        limiter.check(1000, start);
+        let later = start + Duration::from_secs(3600);
+        assert_eq!(limiter.check(1000, later), ALLOW);
+        assert_eq!(limiter.check(1000, later), ALLOW);
+        assert_eq!(limiter.check(1000, later), RateDecision::Deny);
+    }
+
+    #[test]
+    fn users_have_separate_buckets() {
+        let mut limiter = RateLimiter::new(1);
+        let now = Instant::now();
+        assert_eq!(limiter.check(1000, now), ALLOW);
+        assert_eq!(limiter.check(1000, now), RateDecision::Deny);
+        assert_eq!(limiter.check(1001, now), ALLOW);
+    }
+
+    #[test]
+    fn cleanup_keeps_buckets_with_pending_drops() {
+        let mut limiter = RateLimiter::new(1);
+        let start = Instant::now();
+        limiter.check(1000, start);
+        limiter.check(1000, start);
+        limiter.check(1001, start);
+
+        let later = start + Duration::from_secs(120);
+        limiter.cleanup(later);
+        assert!(limiter.buckets.contains_key(&1000));
+        assert!(!limiter.buckets.contains_key(&1001));
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+use notify_done_common::health::RecentLogLine;
+
+/// Keeps the daemon's most recent log lines in memory, whatever the
+/// journal's log level, so `nd debug log` can show why a notification did
+/// or didn't fire
+#[derive(Clone)]
+pub struct RecentLog {
+    lines: Arc<Mutex<VecDeque<RecentLogLine>>>,
+    capacity: usize,
+}
+
+impl RecentLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// The kept lines, oldest first
+    pub fn snapshot(&self) -> Vec<RecentLogLine> {
+        match self.lines.lock() {
+            Ok(lines) => lines.iter().cloned().collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for RecentLog {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = LineVisitor::default();
+        event.record(&mut visitor);
+
+        let line = RecentLogLine {
+            at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            level: event.metadata().level().to_string(),
+            message: visitor.line,
+        };
+
+        if let Ok(mut lines) = self.lines.lock() {
+            if lines.len() == self.capacity {
+                lines.pop_front();
+            }
+            lines.push_back(line);
+        }
+    }
+}
+
+/// Formats an event as its message followed by `key=value` fields
+#[derive(Default)]
+struct LineVisitor {
+    line: String,
+}
+
+impl Visit for LineVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        use std::fmt::Write;
+
+        if !self.line.is_empty() {
+            self.line.push(' ');
+        }
+        if field.name() == "message" {
+            let _ = write!(self.line, "{:?}", value);
+        } else {
+            let _ = write!(self.line, "{}={:?}", field.name(), value);
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Drops repeat notifications for a command within `dedup_window_seconds`
+/// of the last one sent, by (uid, comm)
+#[derive(Default)]
+pub struct RepeatFilter {
+    /// End of the window of the last notification sent
+    until: HashMap<(u32, String), Instant>,
+}
+
+impl RepeatFilter {
+    /// Whether a notification for `comm` at `now` repeats a recent one
+    pub fn is_repeat(&self, uid: u32, comm: &str, now: Instant) -> bool {
+        self.until
+            .get(&(uid, comm.to_string()))
+            .is_some_and(|until| now < *until)
+    }
+
+    /// Note a notification sent at `now`, opening a window of `window`
+    pub fn record(&mut self, uid: u32, comm: &str, now: Instant, window: Duration) {
+        self.until.insert((uid, comm.to_string()), now + window);
+    }
+
+    /// Forget windows that have ended
+    pub fn cleanup(&mut self, now: Instant) {
+        self.until.retain(|_, until| *until > now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(10);
+
+    /// Run completions at `offsets` (seconds) through the filter, as the
+    /// event processor does, and return how many notified
+    fn notified(filter: &mut RepeatFilter, start: Instant, offsets: &[u64]) -> usize {
+        let mut sent = 0;
+        for &offset in offsets {
+            let now = start + Duration::from_secs(offset);
+            if !filter.is_repeat(1000, "make", now) {
+                filter.record(1000, "make", now, WINDOW);
+                sent += 1;
+            }
+        }
+        sent
+    }
+
+    #[test]
+    fn three_rapid_completions_notify_once() {
+        let mut filter = RepeatFilter::default();
+        assert_eq!(notified(&mut filter, Instant::now(), &[0, 1, 2]), 1);
+    }
+
+    #[test]
+    fn window_counts_from_the_last_notification_sent() {
+        let mut filter = RepeatFilter::default();
+        assert_eq!(notified(&mut filter, Instant::now(), &[0, 5, 9, 10, 15]), 2);
+    }
+
+    #[test]
+    fn other_commands_and_users_are_not_repeats() {
+        let mut filter = RepeatFilter::default();
+        let now = Instant::now();
+        filter.record(1000, "make", now, WINDOW);
+        assert!(filter.is_repeat(1000, "make", now));
+        assert!(!filter.is_repeat(1000, "cargo", now));
+        assert!(!filter.is_repeat(1001, "make", now));
+    }
+
+    #[test]
+    fn cleanup_forgets_ended_windows() {
+        let mut filter = RepeatFilter::default();
+        let now = Instant::now();
+        filter.record(1000, "make", now, WINDOW);
+        filter.record(1000, "cargo", now, WINDOW * 2);
+        filter.cleanup(now + WINDOW);
+        assert_eq!(filter.until.len(), 1);
+        assert!(filter.is_repeat(1000, "cargo", now + WINDOW));
+    }
+}
@@ -0,0 +1,70 @@
+use std::io;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+/// Prefix of every metric name
+const METRIC_PREFIX: &str = "notify_done";
+
+/// Fire-and-forget StatsD client pushing per-completion metrics over UDP,
+/// with DogStatsD-style tags
+pub struct StatsdClient {
+    socket: UdpSocket,
+}
+
+impl StatsdClient {
+    /// Resolve `addr` (e.g. "127.0.0.1:8125") and connect a non-blocking socket to it
+    pub fn connect(addr: &str) -> io::Result<Self> {
+        let target = addr.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve", addr))
+        })?;
+        let bind = if target.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(bind)?;
+        socket.connect(target)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket })
+    }
+
+    /// Record a completed command: its duration as a timing and a counter,
+    /// both tagged with the command name and outcome
+    pub fn completion(&self, comm: &str, duration: Duration, success: bool) {
+        let tags = format!(
+            "comm:{},outcome:{}",
+            sanitize_tag(comm),
+            if success { "success" } else { "failure" }
+        );
+        let payload = format!(
+            "{prefix}.command.duration:{ms}|ms|#{tags}\n{prefix}.command.completed:1|c|#{tags}",
+            prefix = METRIC_PREFIX,
+            ms = duration.as_millis(),
+            tags = tags,
+        );
+
+        // Metrics are best effort: never hold up notifications for them
+        if let Err(e) = self.socket.send(payload.as_bytes()) {
+            tracing::debug!("Failed to send StatsD metrics: {}", e);
+        }
+    }
+}
+
+/// Make a command name safe as a tag value: StatsD reserves `:|@#,` and
+/// newlines, so anything but ASCII alphanumerics, `_`, `-`, `.` and `/`
+/// becomes `_`
+fn sanitize_tag(value: &str) -> String {
+    let sanitized: String = value
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' | '_' | '-' | '.' | '/' => c,
+            'A'..='Z' => c.to_ascii_lowercase(),
+            _ => '_',
+        })
+        .collect();
+    if sanitized.is_empty() {
+        "unknown".to_string()
+    } else {
+        sanitized
+    }
+}
@@ -0,0 +1,89 @@
+//! Recorded traces of raw ring-buffer records, for reproducing bugs
+//!
+//! A trace file is the 8-byte magic `NDTRACE1` followed by one entry per
+//! record: its length as a little-endian `u32`, then the record bytes
+//! exactly as the eBPF programs wrote them (a `ProcessExecEvent` or
+//! `ProcessExitEvent` from notify-done-common, first byte the event type).
+//! Records are stored in native layout, so a trace only replays on a
+//! machine with the same endianness and the same common crate version.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+const MAGIC: &[u8; 8] = b"NDTRACE1";
+
+/// Anything longer is a corrupt length, not a real event
+const MAX_RECORD_LEN: usize = 64 * 1024;
+
+/// Appends live records to a trace file
+pub struct TraceWriter {
+    out: BufWriter<File>,
+}
+
+impl TraceWriter {
+    /// Create (or truncate) `path` and write the header
+    pub fn create(path: &Path) -> Result<Self> {
+        let file =
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut out = BufWriter::new(file);
+        out.write_all(MAGIC)?;
+        Ok(Self { out })
+    }
+
+    /// Append one record
+    pub fn write(&mut self, record: &[u8]) -> io::Result<()> {
+        self.out.write_all(&(record.len() as u32).to_le_bytes())?;
+        self.out.write_all(record)
+    }
+
+    /// Push buffered records to the file, so a crash keeps what came before
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Read every record of the trace at `path`
+pub fn read_trace(path: &Path) -> Result<Vec<Vec<u8>>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut input = BufReader::new(file);
+
+    let mut magic = [0u8; 8];
+    input
+        .read_exact(&mut magic)
+        .with_context(|| format!("{} is too short to be a trace", path.display()))?;
+    if &magic != MAGIC {
+        bail!("{} is not a notify-done trace", path.display());
+    }
+
+    let mut records = Vec::new();
+    loop {
+        let mut len = [0u8; 4];
+        match input.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let len = u32::from_le_bytes(len) as usize;
+        if len > MAX_RECORD_LEN {
+            bail!(
+                "Record {} of {} claims {} bytes, the trace is corrupt",
+                records.len(),
+                path.display(),
+                len
+            );
+        }
+        let mut record = vec![0u8; len];
+        input.read_exact(&mut record).with_context(|| {
+            format!(
+                "Record {} of {} is truncated",
+                records.len(),
+                path.display()
+            )
+        })?;
+        records.push(record);
+    }
+    Ok(records)
+}
@@ -0,0 +1,199 @@
+//! Extra notified users read from a file, re-read when it changes
+//!
+//! One user per line, as a UID or a username resolved through NSS (so
+//! LDAP and sssd users work too). Blank lines and `#` comments are
+//! skipped; malformed lines and unknown users are skipped with a warning.
+
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// The users listed in `tracked_uids_file`
+pub struct UidFile {
+    path: PathBuf,
+    /// mtime of the last read, None if the file was missing
+    modified: Option<SystemTime>,
+    uids: HashSet<u32>,
+}
+
+impl UidFile {
+    /// Read `path` now; a missing file lists nobody until it appears
+    pub fn load(path: &Path) -> Self {
+        let mut file = Self {
+            path: path.to_path_buf(),
+            modified: None,
+            uids: HashSet::new(),
+        };
+        file.read();
+        file
+    }
+
+    pub fn uids(&self) -> &HashSet<u32> {
+        &self.uids
+    }
+
+    /// Re-read the file if its mtime changed since the last read
+    pub fn refresh(&mut self) {
+        let modified = std::fs::metadata(&self.path)
+            .and_then(|meta| meta.modified())
+            .ok();
+        if modified != self.modified {
+            self.read();
+        }
+    }
+
+    fn read(&mut self) {
+        self.modified = std::fs::metadata(&self.path)
+            .and_then(|meta| meta.modified())
+            .ok();
+        let content = match std::fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) => {
+                tracing::warn!("Can't read {}: {}", self.path.display(), e);
+                self.uids.clear();
+                return;
+            }
+        };
+
+        let (uids, problems) = parse_uids(&content, lookup_username);
+        for problem in problems {
+            tracing::warn!("{}: {}, skipped", self.path.display(), problem);
+        }
+        tracing::info!(
+            "Loaded {} notified users from {}",
+            uids.len(),
+            self.path.display()
+        );
+        self.uids = uids;
+    }
+}
+
+/// The UIDs listed in `content`, and a description of each skipped line;
+/// `resolve` maps a username to its UID
+fn parse_uids(content: &str, resolve: impl Fn(&str) -> Option<u32>) -> (HashSet<u32>, Vec<String>) {
+    let mut uids = HashSet::new();
+    let mut problems = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let entry = line.split('#').next().unwrap_or_default().trim();
+        if entry.is_empty() {
+            continue;
+        }
+        if entry.contains(char::is_whitespace) {
+            problems.push(format!(
+                "line {}: expected one user, got {:?}",
+                index + 1,
+                entry
+            ));
+        } else if let Ok(uid) = entry.parse::<u32>() {
+            uids.insert(uid);
+        } else if entry.starts_with(|c: char| c.is_ascii_digit() || c == '-') {
+            problems.push(format!(
+                "line {}: {:?} is not a valid UID",
+                index + 1,
+                entry
+            ));
+        } else if let Some(uid) = resolve(entry) {
+            uids.insert(uid);
+        } else {
+            problems.push(format!("line {}: no user named {:?}", index + 1, entry));
+        }
+    }
+    (uids, problems)
+}
+
+/// Resolve a username through NSS
+fn lookup_username(name: &str) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    let status = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut passwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    (status == 0 && !result.is_null()).then_some(passwd.pw_uid)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn resolve(name: &str) -> Option<u32> {
+        match name {
+            "alice" => Some(1000),
+            "bob" => Some(1001),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn uids_and_names_are_both_accepted() {
+        let content = "# notified users\n1002\nalice  # the admin\n\n  bob\n";
+        let (uids, problems) = parse_uids(content, resolve);
+        assert_eq!(uids, HashSet::from([1000, 1001, 1002]));
+        assert!(problems.is_empty());
+    }
+
+    #[test]
+    fn malformed_lines_are_reported_with_their_number() {
+        let content = "alice bob\n-5\n12abc\nmallory\n1000\n";
+        let (uids, problems) = parse_uids(content, resolve);
+        assert_eq!(uids, HashSet::from([1000]));
+        assert_eq!(
+            problems,
+            [
+                "line 1: expected one user, got \"alice bob\"",
+                "line 2: \"-5\" is not a valid UID",
+                "line 3: \"12abc\" is not a valid UID",
+                "line 4: no user named \"mallory\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn refresh_rereads_a_changed_file() {
+        let dir = std::env::temp_dir().join(format!("nd-uid-file-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("uids");
+        let _ = std::fs::remove_file(&path);
+
+        let mut file = UidFile::load(&path);
+        assert!(file.uids().is_empty());
+
+        std::fs::write(&path, "1000\nroot\n").unwrap();
+        file.refresh();
+        assert_eq!(file.uids(), &HashSet::from([0, 1000]));
+
+        // Same mtime, so the new content isn't read yet
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        std::fs::write(&path, "1001\n").unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        file.refresh();
+        assert_eq!(file.uids(), &HashSet::from([0, 1000]));
+
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified + Duration::from_secs(1))
+            .unwrap();
+        file.refresh();
+        assert_eq!(file.uids(), &HashSet::from([1001]));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -46,17 +46,25 @@ impl SessionDiscovery {
         // Try to discover session
         match self.discover_session(uid) {
             Ok(session) => {
-                log::debug!("Discovered session for uid {}: {:?}", uid, session);
+                tracing::debug!("Discovered session for uid {}: {:?}", uid, session);
                 self.sessions.insert(uid, session);
                 return self.sessions.get(&uid);
             }
             Err(e) => {
-                log::debug!("Failed to discover session for uid {}: {}", uid, e);
+                tracing::debug!(uid, error = %e, "Failed to discover session");
                 return None;
             }
         }
     }
 
+    /// UID owning the active session on the primary seat, if any
+    pub fn active_uid(&self) -> Option<u32> {
+        let session_id = loginctl_value(&["show-seat", "seat0", "-p", "ActiveSession"])?;
+        loginctl_value(&["show-session", &session_id, "-p", "User"])?
+            .parse()
+            .ok()
+    }
+
     /// Discover session for a specific UID
     fn discover_session(&mut self, uid: u32) -> Result<UserSession> {
         let username = self.get_username(uid)?;
@@ -65,7 +73,7 @@ impl SessionDiscovery {
         // we may not have permission to read /run/user/{uid} due to systemd
         // security restrictions, but systemd-run can still access it
         let dbus_address = format!("unix:path=/run/user/{}/bus", uid);
-        log::debug!("Using D-Bus address: {}", dbus_address);
+        tracing::debug!("Using D-Bus address: {}", dbus_address);
 
         // Try to determine session type from loginctl
         let session_type = self.detect_session_type(uid);
@@ -204,6 +212,11 @@ impl SessionDiscovery {
         anyhow::bail!("Username not found for uid {}", uid)
     }
 
+    /// Forget the cached session of one user, so it is rediscovered
+    pub fn invalidate(&mut self, uid: u32) {
+        self.sessions.remove(&uid);
+    }
+
     /// Clear all cached sessions
     pub fn clear_cache(&mut self) {
         self.sessions.clear();
@@ -215,3 +228,17 @@ impl Default for SessionDiscovery {
         Self::new()
     }
 }
+
+/// Run `loginctl <args> --value` and return its non-empty output
+fn loginctl_value(args: &[&str]) -> Option<String> {
+    let output = Command::new("loginctl")
+        .args(args)
+        .arg("--value")
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!value.is_empty()).then_some(value)
+}
@@ -4,13 +4,18 @@
 #![feature(asm_experimental_arch)]
 
 use aya_ebpf::{
-    helpers::{bpf_get_current_pid_tgid, bpf_get_current_uid_gid, bpf_ktime_get_ns},
+    helpers::{
+        bpf_get_current_pid_tgid, bpf_get_current_uid_gid, bpf_ktime_get_ns,
+        bpf_probe_read_kernel_str_bytes,
+    },
     macros::{map, tracepoint},
     maps::RingBuf,
     programs::TracePointContext,
     EbpfContext,
 };
-use notify_done_common::{EventType, ProcessExecEvent, ProcessExitEvent, RING_BUF_SIZE};
+use notify_done_common::{
+    EventType, ProcessExecEvent, ProcessExitEvent, FILENAME_LEN, RING_BUF_SIZE,
+};
 
 /// Ring buffer for sending events to userspace
 #[map]
@@ -19,6 +24,10 @@ static EVENTS: RingBuf = RingBuf::with_byte_size(RING_BUF_SIZE, 0);
 /// Minimum UID to track (system users are below 1000)
 const MIN_UID: u32 = 1000;
 
+/// Offset of `__data_loc char[] filename` in the sched_process_exec record
+/// (see /sys/kernel/tracing/events/sched/sched_process_exec/format)
+const EXEC_FILENAME_LOC_OFFSET: usize = 8;
+
 /// Tracepoint for sched:sched_process_exec
 #[tracepoint(category = "sched", name = "sched_process_exec")]
 pub fn sched_process_exec(ctx: TracePointContext) -> u32 {
@@ -126,6 +135,18 @@ fn try_sched_process_exec(ctx: &TracePointContext) -> Result<(), i64> {
             *fn_base.wrapping_add(29) = 0;
             *fn_base.wrapping_add(30) = 0;
             *fn_base.wrapping_add(31) = 0;
+
+            // Copy the exec'd path from the tracepoint's __data_loc field. The
+            // u32 packs (length << 16 | offset from the start of the record).
+            // The kernel length is ignored: the copy is bounded by the fixed
+            // FILENAME_LEN destination, which keeps the verifier happy, and
+            // stops at the NUL terminator.
+            if let Ok(loc) = ctx.read_at::<u32>(EXEC_FILENAME_LOC_OFFSET) {
+                let offset = (loc & 0xFFFF) as usize;
+                let src = (ctx.as_ptr() as *const u8).wrapping_add(offset);
+                let dest = core::slice::from_raw_parts_mut(base.wrapping_add(48), FILENAME_LEN);
+                let _ = bpf_probe_read_kernel_str_bytes(src, dest);
+            }
         }
         entry.submit(0);
     }
@@ -0,0 +1,185 @@
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+
+use crate::project_root;
+
+/// Binaries installed into `<prefix>/bin`
+const BINARIES: &[&str] = &["notify-done-daemon", "nd"];
+
+/// Where the unit is installed; local units go in /etc regardless of prefix
+const UNIT_PATH: &str = "/etc/systemd/system/notify-done.service";
+
+/// System config shared by the daemon and `nd`
+const CONFIG_PATH: &str = "/etc/notify-done/config.toml";
+
+/// Capabilities the daemon needs to load and attach its eBPF programs
+const DAEMON_CAPS: &str = "cap_bpf,cap_perfmon+ep";
+
+/// Written only if no system config exists yet
+const DEFAULT_CONFIG: &str = "\
+# notify-done system configuration; users can override parts of it in
+# ~/.config/notify-done/config.toml
+
+# Only track users with at least this UID
+min_uid = 1000
+
+# Notify commands that ran at least this many seconds
+threshold_seconds = 10
+
+# Commands never notified (glob-style)
+ignore_patterns = []
+";
+
+/// Copy the release binaries, unit and default config into place
+pub fn install(prefix: &Path) -> Result<()> {
+    let release = project_root().join("target").join("release");
+    let bin_dir = prefix.join("bin");
+    std::fs::create_dir_all(&bin_dir)
+        .with_context(|| format!("Failed to create {}", bin_dir.display()))?;
+
+    for name in BINARIES {
+        let dst = bin_dir.join(name);
+        let contents = std::fs::read(release.join(name))
+            .with_context(|| format!("Failed to read built {}", name))?;
+        report(&dst, write_file(&dst, &contents, 0o755)?);
+    }
+
+    let daemon = bin_dir.join("notify-done-daemon");
+    set_capabilities(&daemon)?;
+
+    let unit = generate_unit(&daemon)?;
+    let unit_path = Path::new(UNIT_PATH);
+    report(unit_path, write_file(unit_path, unit.as_bytes(), 0o644)?);
+
+    let config_path = Path::new(CONFIG_PATH);
+    if config_path.exists() {
+        println!("kept      {} (already exists)", config_path.display());
+    } else {
+        report(
+            config_path,
+            write_file(config_path, DEFAULT_CONFIG.as_bytes(), 0o644)?,
+        );
+    }
+
+    println!();
+    println!("Start the daemon with:");
+    println!("  systemctl daemon-reload && systemctl enable --now notify-done");
+    Ok(())
+}
+
+/// Remove what `install` put in place, keeping the system config
+pub fn uninstall(prefix: &Path) -> Result<()> {
+    let mut paths: Vec<PathBuf> = BINARIES
+        .iter()
+        .map(|name| prefix.join("bin").join(name))
+        .collect();
+    paths.push(PathBuf::from(UNIT_PATH));
+
+    for path in paths {
+        match std::fs::remove_file(&path) {
+            Ok(()) => println!("removed   {}", path.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                println!("absent    {}", path.display())
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to remove {}", path.display()))
+            }
+        }
+    }
+    if Path::new(CONFIG_PATH).exists() {
+        println!("kept      {} (remove it by hand if unwanted)", CONFIG_PATH);
+    }
+
+    println!();
+    println!("If the daemon was enabled, also run:");
+    println!("  systemctl disable --now notify-done && systemctl daemon-reload");
+    Ok(())
+}
+
+/// The repo's unit with ExecStart pointing at the installed daemon
+fn generate_unit(daemon: &Path) -> Result<String> {
+    let template_path = project_root().join("systemd").join("notify-done.service");
+    let template = std::fs::read_to_string(&template_path)
+        .with_context(|| format!("Failed to read {}", template_path.display()))?;
+
+    let mut found = false;
+    let unit: Vec<String> = template
+        .lines()
+        .map(|line| {
+            if line.starts_with("ExecStart=") {
+                found = true;
+                format!("ExecStart={}", daemon.display())
+            } else {
+                line.to_string()
+            }
+        })
+        .collect();
+    if !found {
+        bail!("{} has no ExecStart line", template_path.display());
+    }
+    Ok(unit.join("\n") + "\n")
+}
+
+/// Whether `write_file` changed anything
+enum Written {
+    Created,
+    Updated,
+    Unchanged,
+}
+
+fn report(path: &Path, written: Written) {
+    let action = match written {
+        Written::Created => "installed",
+        Written::Updated => "updated",
+        Written::Unchanged => "unchanged",
+    };
+    println!("{:<9} {}", action, path.display());
+}
+
+/// Write `contents` with `mode` unless the file already matches
+///
+/// The file is replaced by renaming a copy over it, so a running daemon's
+/// binary can be updated.
+fn write_file(path: &Path, contents: &[u8], mode: u32) -> Result<Written> {
+    let existing = std::fs::read(path).ok();
+    let mode_matches =
+        std::fs::metadata(path).is_ok_and(|meta| meta.permissions().mode() & 0o7777 == mode);
+    if existing.as_deref() == Some(contents) && mode_matches {
+        return Ok(Written::Unchanged);
+    }
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    let tmp = path.with_extension("xtask-tmp");
+    std::fs::write(&tmp, contents).with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(mode))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))?;
+
+    Ok(if existing.is_some() {
+        Written::Updated
+    } else {
+        Written::Created
+    })
+}
+
+/// Give the daemon its eBPF capabilities, so it also runs outside the unit
+fn set_capabilities(daemon: &Path) -> Result<()> {
+    let status = Command::new("setcap").arg(DAEMON_CAPS).arg(daemon).status();
+    match status {
+        Ok(status) if status.success() => {
+            println!("setcap    {} {}", DAEMON_CAPS, daemon.display());
+            Ok(())
+        }
+        Ok(status) => bail!("setcap {} failed ({})", daemon.display(), status),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            println!("skipped   setcap (not installed); the systemd unit grants the capabilities");
+            Ok(())
+        }
+        Err(e) => Err(e).context("Failed to run setcap"),
+    }
+}
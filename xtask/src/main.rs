@@ -1,8 +1,11 @@
-use std::path::PathBuf;
+mod install;
+
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::SystemTime;
 
 use anyhow::{bail, Context, Result};
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 
 #[derive(Parser)]
 #[command(name = "xtask")]
@@ -19,24 +22,159 @@ enum Commands {
         /// Build in release mode
         #[arg(long)]
         release: bool,
+
+        /// Architecture the programs will run on (default: the host's)
+        #[arg(long)]
+        arch: Option<String>,
+
+        /// Byte order of the target (default: the host's)
+        #[arg(long, value_enum)]
+        endian: Option<Endian>,
+
+        /// Rebuild even if the eBPF sources haven't changed
+        #[arg(long)]
+        force: bool,
     },
     /// Build everything (eBPF + userspace)
     Build {
         /// Build in release mode
         #[arg(long)]
         release: bool,
+
+        /// Rebuild the eBPF programs even if their sources haven't changed
+        #[arg(long)]
+        force: bool,
+    },
+    /// Build the eBPF programs and the daemon, then run the daemon against
+    /// the fresh object (as root, through sudo if needed)
+    RunEbpf {
+        /// Keep the kernel verifier's log for program load errors
+        #[arg(long, value_parser = ["debug", "verbose"])]
+        log_level: Option<String>,
+    },
+    /// Build in release mode and install the binaries, systemd unit and a
+    /// default system config (needs root)
+    Install {
+        /// Install the binaries into PREFIX/bin
+        #[arg(long, default_value = "/usr/local")]
+        prefix: PathBuf,
     },
+    /// Remove the installed binaries and systemd unit, keeping the config
+    Uninstall {
+        /// Prefix the binaries were installed under
+        #[arg(long, default_value = "/usr/local")]
+        prefix: PathBuf,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Endian {
+    Little,
+    Big,
+}
+
+impl Endian {
+    fn host() -> Self {
+        if cfg!(target_endian = "big") {
+            Endian::Big
+        } else {
+            Endian::Little
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Endian::Little => "little",
+            Endian::Big => "big",
+        }
+    }
+}
+
+/// Files and directories the eBPF object is built from, relative to the
+/// project root
+const EBPF_INPUTS: &[&str] = &[
+    "notify-done-ebpf/src",
+    "notify-done-ebpf/build.rs",
+    "notify-done-ebpf/Cargo.toml",
+    "notify-done-ebpf/Cargo.lock",
+    "notify-done-ebpf/.cargo",
+    "notify-done-common/src",
+    "notify-done-common/Cargo.toml",
+];
+
+/// Values of aya's `bpf_target_arch` cfg, with the byte orders each can use
+const BPF_ARCHES: &[(&str, &[Endian])] = &[
+    ("x86_64", &[Endian::Little]),
+    ("aarch64", &[Endian::Little, Endian::Big]),
+    ("arm", &[Endian::Little, Endian::Big]),
+    ("riscv64", &[Endian::Little]),
+    ("loongarch64", &[Endian::Little]),
+    ("powerpc64", &[Endian::Little, Endian::Big]),
+    ("mips", &[Endian::Little, Endian::Big]),
+    ("s390x", &[Endian::Big]),
+];
+
+/// Architecture and byte order the eBPF programs are built for
+#[derive(Debug, Clone, Copy)]
+struct EbpfTarget {
+    arch: &'static str,
+    endian: Endian,
+}
+
+impl EbpfTarget {
+    /// Check an architecture and byte order, each defaulting to the host's
+    fn new(arch: Option<&str>, endian: Option<Endian>) -> Result<Self> {
+        let arch = arch.unwrap_or(std::env::consts::ARCH);
+        let Some(&(arch, endians)) = BPF_ARCHES.iter().find(|(name, _)| *name == arch) else {
+            let supported: Vec<&str> = BPF_ARCHES.iter().map(|(name, _)| *name).collect();
+            bail!(
+                "Unsupported eBPF target arch {:?}, expected one of: {}",
+                arch,
+                supported.join(", ")
+            );
+        };
+
+        let endian = endian.unwrap_or_else(Endian::host);
+        if !endians.contains(&endian) {
+            bail!("{} has no {}-endian eBPF target", arch, endian.as_str());
+        }
+        Ok(Self { arch, endian })
+    }
+
+    fn host() -> Result<Self> {
+        Self::new(None, None)
+    }
+
+    /// Rust target triple of the BPF backend
+    fn triple(&self) -> &'static str {
+        match self.endian {
+            Endian::Little => "bpfel-unknown-none",
+            Endian::Big => "bpfeb-unknown-none",
+        }
+    }
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::BuildEbpf { release } => build_ebpf(release),
-        Commands::Build { release } => {
-            build_ebpf(release)?;
+        Commands::BuildEbpf {
+            release,
+            arch,
+            endian,
+            force,
+        } => build_ebpf(release, EbpfTarget::new(arch.as_deref(), endian)?, force),
+        Commands::Build { release, force } => {
+            build_ebpf(release, EbpfTarget::host()?, force)?;
             build_userspace(release)
         }
+        Commands::RunEbpf { log_level } => run_ebpf(log_level.as_deref()),
+        Commands::Install { prefix } => {
+            build_ebpf(true, EbpfTarget::host()?, false)?;
+            build_userspace(true)?;
+            install::install(&prefix)
+        }
+        Commands::Uninstall { prefix } => install::uninstall(&prefix),
     }
 }
 
@@ -47,15 +185,14 @@ fn project_root() -> PathBuf {
         .to_path_buf()
 }
 
-fn build_ebpf(release: bool) -> Result<()> {
+fn build_ebpf(release: bool, target: EbpfTarget, force: bool) -> Result<()> {
     let root = project_root();
     let ebpf_dir = root.join("notify-done-ebpf");
-
-    // Set BPF target arch based on host architecture
-    let bpf_arch = std::env::consts::ARCH;
+    let profile = if release { "release" } else { "debug" };
+    let dst = root.join("target").join("ebpf").join("notify-done-ebpf");
 
     // Set RUSTFLAGS to include the bpf_target_arch cfg
-    let rustflags = format!("--cfg bpf_target_arch=\"{}\"", bpf_arch);
+    let rustflags = format!("--cfg bpf_target_arch=\"{}\"", target.arch);
     let existing_flags = std::env::var("RUSTFLAGS").unwrap_or_default();
     let combined_flags = if existing_flags.is_empty() {
         rustflags
@@ -63,14 +200,30 @@ fn build_ebpf(release: bool) -> Result<()> {
         format!("{} {}", existing_flags, rustflags)
     };
 
+    // Records what the copied object was last built for and when
+    let stamp_path = dst.with_extension("stamp");
+    let stamp = format!(
+        "{} {} {} {}",
+        target.arch,
+        target.endian.as_str(),
+        profile,
+        combined_flags
+    );
+    if !force && is_up_to_date(&root, &stamp_path, &stamp)? {
+        println!("eBPF program up to date: {}", dst.display());
+        return Ok(());
+    }
+
     let mut cmd = Command::new("cargo");
     cmd.current_dir(&ebpf_dir)
         .env("RUSTFLAGS", &combined_flags)
+        // Overrides the host default in notify-done-ebpf/.cargo/config.toml
+        .env("CARGO_CFG_BPF_TARGET_ARCH", target.arch)
         .args([
             "+nightly",
             "build",
             "--target",
-            "bpfel-unknown-none",
+            target.triple(),
             "-Z",
             "build-std=core",
         ]);
@@ -85,24 +238,71 @@ fn build_ebpf(release: bool) -> Result<()> {
         bail!("eBPF build failed");
     }
 
-    // Copy the built binary to a known location
-    let profile = if release { "release" } else { "debug" };
+    // Copy the built binary to a known location, leaving it untouched if it
+    // didn't change so the daemon isn't rebuilt for nothing
     let src = ebpf_dir
         .join("target")
-        .join("bpfel-unknown-none")
+        .join(target.triple())
         .join(profile)
         .join("notify-done-ebpf");
-    let dst = root.join("target").join("ebpf").join("notify-done-ebpf");
 
     std::fs::create_dir_all(dst.parent().unwrap())?;
     if src.exists() {
-        std::fs::copy(&src, &dst).context("Failed to copy eBPF binary")?;
-        println!("eBPF program built: {}", dst.display());
+        let built = std::fs::read(&src).context("Failed to read eBPF binary")?;
+        if std::fs::read(&dst).ok().as_deref() != Some(built.as_slice()) {
+            std::fs::write(&dst, &built).context("Failed to copy eBPF binary")?;
+        }
+        std::fs::write(&stamp_path, &stamp)
+            .with_context(|| format!("Failed to write {}", stamp_path.display()))?;
+        println!(
+            "eBPF program built for {} ({}-endian): {}",
+            target.arch,
+            target.endian.as_str(),
+            dst.display()
+        );
     }
 
     Ok(())
 }
 
+/// Whether the last build was for the same target and no eBPF input
+/// changed since
+fn is_up_to_date(root: &Path, stamp_path: &Path, stamp: &str) -> Result<bool> {
+    let Ok(previous) = std::fs::read_to_string(stamp_path) else {
+        return Ok(false);
+    };
+    if previous != stamp {
+        return Ok(false);
+    }
+    let built_at = std::fs::metadata(stamp_path)?.modified()?;
+
+    for input in EBPF_INPUTS {
+        if newest_mtime(&root.join(input))?.is_some_and(|mtime| mtime > built_at) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Latest modification time of `path` or anything below it, if it exists
+fn newest_mtime(path: &Path) -> Result<Option<SystemTime>> {
+    let meta = match std::fs::metadata(path) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to stat {}", path.display())),
+    };
+
+    let mut newest = meta.modified()?;
+    if meta.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            if let Some(mtime) = newest_mtime(&entry?.path())? {
+                newest = newest.max(mtime);
+            }
+        }
+    }
+    Ok(Some(newest))
+}
+
 fn build_userspace(release: bool) -> Result<()> {
     let root = project_root();
 
@@ -125,3 +325,51 @@ fn build_userspace(release: bool) -> Result<()> {
     println!("Userspace programs built successfully");
     Ok(())
 }
+
+fn run_ebpf(log_level: Option<&str>) -> Result<()> {
+    build_ebpf(false, EbpfTarget::host()?, false)?;
+
+    let root = project_root();
+    let status = Command::new("cargo")
+        .current_dir(&root)
+        .args(["build", "-p", "notify-done-daemon"])
+        .status()
+        .context("Failed to run cargo build for the daemon")?;
+    if !status.success() {
+        bail!("Daemon build failed");
+    }
+
+    let daemon = root.join("target").join("debug").join("notify-done-daemon");
+    let object = root.join("target").join("ebpf").join("notify-done-ebpf");
+    let mut env = vec![("NOTIFY_DONE_EBPF_PATH", object.display().to_string())];
+    if let Some(level) = log_level {
+        env.push(("NOTIFY_DONE_VERIFIER_LOG", level.to_string()));
+    }
+
+    // Loading eBPF needs root; sudo drops the environment unless asked
+    let mut cmd = if is_root() {
+        Command::new(&daemon)
+    } else {
+        let mut sudo = Command::new("sudo");
+        sudo.arg("env");
+        for (key, value) in &env {
+            sudo.arg(format!("{}={}", key, value));
+        }
+        sudo.arg(&daemon);
+        sudo
+    };
+    cmd.envs(env.iter().map(|(key, value)| (key, value)));
+
+    let status = cmd.status().context("Failed to run the daemon")?;
+    if !status.success() {
+        bail!("Daemon exited with {}", status);
+    }
+    Ok(())
+}
+
+fn is_root() -> bool {
+    Command::new("id")
+        .arg("-u")
+        .output()
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).trim() == "0")
+}